@@ -1580,10 +1580,20 @@ rec {
             name = "indexmap";
             packageId = "indexmap 2.14.0";
           }
+          {
+            name = "openssl";
+            packageId = "openssl";
+          }
           {
             name = "password-auth";
             packageId = "password-auth";
           }
+          {
+            name = "reqwest";
+            packageId = "reqwest";
+            usesDefaultFeatures = false;
+            features = [ "rustls-tls" ];
+          }
           {
             name = "rusty-s3";
             packageId = "rusty-s3";
@@ -1631,7 +1641,7 @@ rec {
             name = "tokio";
             packageId = "tokio";
             usesDefaultFeatures = false;
-            features = [ "macros" ];
+            features = [ "macros" "sync" ];
           }
           {
             name = "tracing";
@@ -0,0 +1,9 @@
+CREATE TABLE ChannelMessageAttachment
+(
+    id           BIGINT UNSIGNED PRIMARY KEY,
+    message_id   BIGINT UNSIGNED NOT NULL,
+    filename     VARCHAR(255)    NOT NULL,
+    content_type VARCHAR(255)    NOT NULL,
+    size         INT UNSIGNED    NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES ChannelMessage (id) ON DELETE CASCADE
+);
@@ -0,0 +1,23 @@
+CREATE TABLE ServerRole
+(
+    id          BIGINT UNSIGNED PRIMARY KEY,
+    server_id   BIGINT UNSIGNED NOT NULL,
+    name        VARCHAR(32)     NOT NULL,
+    permissions BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    FOREIGN KEY (server_id) REFERENCES Server (id) ON DELETE CASCADE
+);
+
+CREATE TABLE ServerMemberRole
+(
+    server_id BIGINT UNSIGNED NOT NULL,
+    user_id   BIGINT UNSIGNED NOT NULL,
+    role_id   BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (server_id, user_id, role_id),
+    FOREIGN KEY (server_id, user_id) REFERENCES ServerMember (server_id, user_id) ON DELETE CASCADE,
+    FOREIGN KEY (role_id) REFERENCES ServerRole (id) ON DELETE CASCADE
+);
+
+-- the @everyone role shares its id with the server, and is implicitly held by every member
+INSERT INTO ServerRole (id, server_id, name, permissions)
+SELECT id, id, '@everyone', 0
+FROM Server;
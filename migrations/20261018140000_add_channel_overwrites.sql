@@ -0,0 +1,16 @@
+CREATE TABLE ChannelPermissionOverwrite
+(
+    channel_id BIGINT UNSIGNED         NOT NULL,
+    -- a role id, or a user id for member overwrites
+    target_id  BIGINT UNSIGNED         NOT NULL,
+    kind       ENUM ('role', 'member') NOT NULL,
+    allow      BIGINT UNSIGNED         NOT NULL DEFAULT 0,
+    deny       BIGINT UNSIGNED         NOT NULL DEFAULT 0,
+    PRIMARY KEY (channel_id, target_id),
+    FOREIGN KEY (channel_id) REFERENCES Channel (id) ON DELETE CASCADE
+);
+
+-- every member could view and post in every channel so far
+UPDATE ServerRole
+SET permissions = permissions | (1 << 8) | (1 << 9)
+WHERE id = server_id;
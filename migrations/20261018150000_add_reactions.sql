@@ -0,0 +1,16 @@
+CREATE TABLE ChannelMessageReaction
+(
+    message_id BIGINT UNSIGNED                                       NOT NULL,
+    user_id    BIGINT UNSIGNED                                       NOT NULL,
+    -- binary collation, as the unicode ones consider many distinct emojis equal
+    emoji      VARCHAR(64) CHARACTER SET utf8mb4 COLLATE utf8mb4_bin NOT NULL,
+    created_at TIMESTAMP                                             NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (message_id, emoji, user_id),
+    FOREIGN KEY (message_id) REFERENCES ChannelMessage (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
+
+-- reacting was previously not restricted
+UPDATE ServerRole
+SET permissions = permissions | (1 << 10)
+WHERE id = server_id;
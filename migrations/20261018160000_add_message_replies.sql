@@ -0,0 +1,3 @@
+-- not a foreign key, so that replies to deleted messages can still tell they were replies
+ALTER TABLE ChannelMessage
+    ADD COLUMN reply_to BIGINT UNSIGNED;
@@ -0,0 +1,26 @@
+ALTER TABLE Channel
+    MODIFY kind ENUM ('text', 'DM', 'thread') NOT NULL;
+
+CREATE TABLE ChannelThread
+(
+    channel_id         BIGINT UNSIGNED PRIMARY KEY,
+    parent_id          BIGINT UNSIGNED NOT NULL,
+    starter_message_id BIGINT UNSIGNED UNIQUE,
+    owner_id           BIGINT UNSIGNED,
+    archived           BOOLEAN         NOT NULL DEFAULT FALSE,
+    FOREIGN KEY (channel_id) REFERENCES Channel (id) ON DELETE CASCADE,
+    -- the thread's Channel row has to be deleted along with its parent manually
+    FOREIGN KEY (parent_id) REFERENCES Channel (id) ON DELETE CASCADE,
+    FOREIGN KEY (starter_message_id) REFERENCES ChannelMessage (id) ON DELETE SET NULL,
+    FOREIGN KEY (owner_id) REFERENCES User (id) ON DELETE SET NULL
+);
+
+CREATE TABLE ChannelThreadMember
+(
+    channel_id BIGINT UNSIGNED NOT NULL,
+    user_id    BIGINT UNSIGNED NOT NULL,
+    created_at TIMESTAMP       NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (channel_id, user_id),
+    FOREIGN KEY (channel_id) REFERENCES Channel (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
@@ -0,0 +1 @@
+CREATE FULLTEXT INDEX ChannelMessageContent ON ChannelMessage (content);
@@ -0,0 +1,23 @@
+CREATE TABLE ChannelReadState
+(
+    channel_id           BIGINT UNSIGNED NOT NULL,
+    user_id              BIGINT UNSIGNED NOT NULL,
+    last_read_message_id BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (user_id, channel_id),
+    FOREIGN KEY (channel_id) REFERENCES Channel (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
+
+-- members are considered to have read everything sent so far, instead of starting with every channel's whole history unread
+INSERT IGNORE INTO ChannelReadState (channel_id, user_id, last_read_message_id)
+SELECT ChannelMessage.channel_id, ServerMember.user_id, MAX(ChannelMessage.id)
+FROM ChannelMessage
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+INNER JOIN ServerMember ON ServerMember.server_id=Channel.server_id
+GROUP BY ChannelMessage.channel_id, ServerMember.user_id;
+
+INSERT IGNORE INTO ChannelReadState (channel_id, user_id, last_read_message_id)
+SELECT ChannelMessage.channel_id, DMChannelRecipient.user_id, MAX(ChannelMessage.id)
+FROM ChannelMessage
+INNER JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=ChannelMessage.channel_id
+GROUP BY ChannelMessage.channel_id, DMChannelRecipient.user_id;
@@ -0,0 +1,3 @@
+ALTER TABLE User
+    ADD status        ENUM ('online', 'idle', 'dnd', 'invisible') NOT NULL DEFAULT 'online',
+    ADD custom_status VARCHAR(128);
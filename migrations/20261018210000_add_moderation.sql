@@ -0,0 +1,13 @@
+CREATE TABLE ServerBan
+(
+    server_id  BIGINT UNSIGNED NOT NULL,
+    user_id    BIGINT UNSIGNED NOT NULL,
+    reason     VARCHAR(512),
+    created_at TIMESTAMP       NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (server_id, user_id),
+    FOREIGN KEY (server_id) REFERENCES Server (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
+
+ALTER TABLE ServerMember
+    ADD timed_out_until TIMESTAMP NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE ServerAuditLogEntry
+(
+    id         BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    server_id  BIGINT UNSIGNED NOT NULL,
+    user_id    BIGINT UNSIGNED,
+    action     ENUM ('server_update', 'channel_create', 'channel_update', 'channel_delete', 'channel_overwrite_update', 'channel_overwrite_delete', 'invite_create', 'invite_delete', 'member_update', 'member_kick', 'member_ban', 'member_unban', 'member_timeout') NOT NULL,
+    target_id  VARCHAR(64),
+    changes    TEXT            NOT NULL,
+    reason     VARCHAR(512),
+    created_at TIMESTAMP       NOT NULL DEFAULT NOW(),
+    INDEX (server_id, action),
+    FOREIGN KEY (server_id) REFERENCES Server (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE SET NULL
+);
@@ -0,0 +1,14 @@
+ALTER TABLE ServerInvite
+    MODIFY expires_at TIMESTAMP NULL DEFAULT (TIMESTAMPADD(DAY, 7, NOW())),
+    ADD max_uses  INT UNSIGNED,
+    ADD uses      INT UNSIGNED NOT NULL DEFAULT 0,
+    ADD temporary BOOLEAN      NOT NULL DEFAULT FALSE;
+
+-- the invite isn't a foreign key so that the record survives the invite being deleted
+ALTER TABLE ServerMember
+    ADD invite_id VARCHAR(32),
+    ADD temporary BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE Server
+    ADD vanity_code VARCHAR(32) COLLATE utf8mb4_unicode_ci UNIQUE,
+    ADD vanity_uses INT UNSIGNED NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+ALTER TABLE Channel
+    MODIFY kind ENUM ('text', 'DM', 'thread', 'category') NOT NULL,
+    ADD parent_id BIGINT UNSIGNED,
+    -- channels with the same position are ordered by their id
+    ADD position  INT UNSIGNED NOT NULL DEFAULT 0,
+    ADD FOREIGN KEY (parent_id) REFERENCES Channel (id) ON DELETE SET NULL;
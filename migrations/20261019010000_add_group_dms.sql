@@ -0,0 +1,18 @@
+ALTER TABLE Channel
+    MODIFY kind ENUM ('text', 'DM', 'thread', 'category', 'group_DM') NOT NULL,
+    ADD owner_id BIGINT UNSIGNED,
+    ADD FOREIGN KEY (owner_id) REFERENCES User (id) ON DELETE SET NULL;
+
+-- group DMs outlive their recipients, so only the 1:1 DMs are deleted along with the user
+DROP EVENT user_deletion_cleanup;
+
+CREATE EVENT user_deletion_cleanup
+    ON SCHEDULE EVERY 8 HOUR
+    DO
+    BEGIN
+        DELETE User, Channel
+        FROM User
+                 LEFT JOIN DMChannelRecipient ON DMChannelRecipient.user_id = User.id
+                 LEFT JOIN Channel ON Channel.id = DMChannelRecipient.channel_id AND Channel.kind = 'DM'
+        WHERE User.began_deletion_at <= NOW() - INTERVAL 7 DAY;
+    END;
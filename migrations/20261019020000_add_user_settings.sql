@@ -0,0 +1,7 @@
+-- users without a row use the defaults
+CREATE TABLE UserSettings
+(
+    user_id                 BIGINT UNSIGNED PRIMARY KEY,
+    allow_server_member_dms BOOLEAN NOT NULL DEFAULT TRUE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
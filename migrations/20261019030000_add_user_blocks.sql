@@ -0,0 +1,9 @@
+CREATE TABLE UserBlock
+(
+    user_id    BIGINT UNSIGNED NOT NULL,
+    blocked_id BIGINT UNSIGNED NOT NULL,
+    created_at TIMESTAMP       NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, blocked_id),
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE,
+    FOREIGN KEY (blocked_id) REFERENCES User (id) ON DELETE CASCADE
+);
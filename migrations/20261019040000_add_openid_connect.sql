@@ -0,0 +1,18 @@
+ALTER TABLE ClientToken
+    MODIFY scope SET ('profile.read', 'profile.write', 'servers.read', 'servers.write', 'messages.read', 'messages.write', 'friends.read', 'friends.write', 'openid') NOT NULL;
+
+ALTER TABLE AuthorizationCode
+    MODIFY scope SET ('profile.read', 'profile.write', 'servers.read', 'servers.write', 'messages.read', 'messages.write', 'friends.read', 'friends.write', 'openid') NOT NULL,
+    ADD COLUMN nonce VARCHAR(255);
+
+ALTER TABLE ClientUserTokens
+    MODIFY scope SET ('profile.read', 'profile.write', 'servers.read', 'servers.write', 'messages.read', 'messages.write', 'friends.read', 'friends.write', 'openid') NOT NULL;
+
+CREATE TABLE SigningKey
+(
+    id          CHAR(24) PRIMARY KEY,
+    private_key BLOB      NOT NULL,
+    created_at  TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX SigningKey_created_at ON SigningKey (created_at);
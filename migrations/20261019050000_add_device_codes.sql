@@ -0,0 +1,24 @@
+CREATE TABLE DeviceCode
+(
+    id             CHAR(64) PRIMARY KEY,
+    user_code      CHAR(8)                                                                                                                                              NOT NULL UNIQUE,
+    created_at     TIMESTAMP                                                                                                                                            NOT NULL DEFAULT NOW(),
+    expires_at     TIMESTAMP                                                                                                                                            NOT NULL DEFAULT (TIMESTAMPADD(MINUTE, 10, NOW())),
+    client_id      BIGINT UNSIGNED                                                                                                                                      NOT NULL,
+    user_id        BIGINT UNSIGNED,
+    denied         BOOLEAN                                                                                                                                              NOT NULL DEFAULT FALSE,
+    scope          SET ('profile.read', 'profile.write', 'servers.read', 'servers.write', 'messages.read', 'messages.write', 'friends.read', 'friends.write', 'openid') NOT NULL,
+    poll_interval  TINYINT UNSIGNED                                                                                                                                     NOT NULL DEFAULT 5,
+    last_polled_at TIMESTAMP                                                                                                                                            NULL,
+    FOREIGN KEY (client_id) REFERENCES Client (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES User (id) ON DELETE CASCADE
+);
+
+CREATE INDEX DeviceCode_expires_at ON DeviceCode (expires_at);
+
+CREATE EVENT device_code_cleanup
+    ON SCHEDULE EVERY 1 DAY
+    DO
+    BEGIN
+        DELETE FROM DeviceCode WHERE expires_at <= NOW();
+    END;
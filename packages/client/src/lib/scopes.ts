@@ -0,0 +1,69 @@
+const SCOPE_TO_DESCRIPTION = {
+	openid: {
+		read: "Know who you are, including your email address",
+	},
+	profile: {
+		read: "Read your account information",
+		write: "Update your account information",
+	},
+	servers: { read: "Read the servers you're in", write: "Update your servers" },
+	messages: {
+		read: "Read the messages you can see",
+		write: "Write messages as you",
+	},
+	friends: {
+		read: "See who you're friends with",
+		write: "Manage your friends",
+	},
+}
+
+const SCOPE_ORDER: (keyof typeof SCOPE_TO_DESCRIPTION)[] = [
+	"openid",
+	"profile",
+	"servers",
+	"messages",
+	"friends",
+] as const
+
+const PERM_ORDER: ["read", "write"] = ["read", "write"]
+
+// if the user has specified a write scope, the read permission is also assumed to be present
+// scopes without an access level (openid) are treated as read scopes
+export const describeScopes = (scopes: string) =>
+	scopes
+		.split(" ")
+		.filter((s) => s.trim() !== "")
+		.map(
+			(s) =>
+				s.split(".") as [keyof typeof SCOPE_TO_DESCRIPTION, "read" | "write"],
+		)
+		.flatMap(([scope, permission]) =>
+			permission === "write"
+				? ([
+						[scope, "read"],
+						[scope, "write"],
+					] as const)
+				: ([[scope, "read"]] as const),
+		)
+		.map(
+			([s, perm]) =>
+				[
+					s,
+					perm,
+					(SCOPE_TO_DESCRIPTION[s] as Record<typeof perm, string>)[perm],
+				] as const,
+		)
+		.toSorted(([a, aPerm], [b, bPerm]) => {
+			const aIndex = SCOPE_ORDER.indexOf(a)
+			const bIndex = SCOPE_ORDER.indexOf(b)
+
+			if (aIndex === bIndex) {
+				const aPermIndex = PERM_ORDER.indexOf(aPerm)
+				const bPermIndex = PERM_ORDER.indexOf(bPerm)
+
+				return aPermIndex - bPermIndex
+			}
+
+			return aIndex - bIndex
+		})
+		.map((a) => a[2])
@@ -19,6 +19,7 @@ import type { ServerMember } from "@biasdo/server-utils/src/ServerMember"
 import type { User } from "@biasdo/server-utils/src/User"
 import type { UserFriend } from "@biasdo/server-utils/src/UserFriend"
 import type { UserFriendRequest } from "@biasdo/server-utils/src/UserFriendRequest"
+import type { WsDispatch } from "@biasdo/server-utils/src/WsDispatch"
 import type { WsUpdateEvent } from "@biasdo/server-utils/src/WsUpdateEvent"
 
 export const currentServerId = derived(
@@ -126,7 +127,7 @@ let reauthCount = 0
 			})
 
 		ws.onmessage = (event) => {
-			const data = JSON.parse(event.data) as WsUpdateEvent
+			const data = JSON.parse(event.data) as WsDispatch
 			if (data.type === "reauthenticate") {
 				ws.send(
 					JSON.stringify({
@@ -0,0 +1,49 @@
+import type { PageLoad } from "./$types"
+import { error } from "@sveltejs/kit"
+import { describeScopes } from "$lib/scopes"
+
+export const load: PageLoad = async ({ fetch, url }) => {
+	const session = localStorage.getItem("session")
+	if (!session) {
+		error(401, "You must be logged in to authorize a device")
+	}
+
+	const userCode = url.searchParams.get("user_code")
+	if (!userCode) {
+		return { userCode: null }
+	}
+
+	const deviceDataRequest = await fetch(
+		`${import.meta.env.VITE_API_URL}/device?${new URLSearchParams({ user_code: userCode })}`,
+		{
+			headers: {
+				Authorization: session,
+			},
+		},
+	)
+
+	if (deviceDataRequest.status === 404) {
+		error(404, "This code is invalid or has expired")
+	}
+
+	if (!deviceDataRequest.ok) {
+		error(deviceDataRequest.status, await deviceDataRequest.text())
+	}
+
+	const { client, scope } = (await deviceDataRequest.json()) as {
+		client: {
+			id: string
+			name: string
+			client_uri: string | null
+			tos_uri: string | null
+			policy_uri: string | null
+		}
+		scope: string
+	}
+
+	return {
+		userCode,
+		client,
+		scopes: describeScopes(scope),
+	}
+}
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type AuditLogAction = "server_update" | "channel_create" | "channel_update" | "channel_delete" | "channel_overwrite_update" | "channel_overwrite_delete" | "invite_create" | "invite_delete" | "member_update" | "member_kick" | "member_ban" | "member_unban" | "member_timeout";
@@ -0,0 +1,5 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuditLogAction } from "./AuditLogAction";
+import type { AuditLogChange } from "./AuditLogChange";
+
+export type AuditLogEntry = { id: `${number}`, server_id: `${number}`, user_id: `${number}` | null, action: AuditLogAction, target_id: string | null, changes: Array<AuditLogChange>, reason: string | null, created_at: string, };
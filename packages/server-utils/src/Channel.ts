@@ -1,5 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { ChannelKind } from "./ChannelKind";
+import type { ReadState } from "./ReadState";
+import type { ThreadMetadata } from "./ThreadMetadata";
 import type { User } from "./User";
 
-export type Channel = { id: `${number}`, name: string, kind: ChannelKind, server_id: `${number}`, user: User | null, };
+export type Channel = { id: `${number}`, name: string, kind: ChannelKind, server_id: `${number}`, parent_id?: `${number}`, position?: number, user: User | null, recipients?: Array<User>, owner_id?: `${number}`, thread?: ThreadMetadata, read_state?: ReadState, };
@@ -1,4 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { Server } from "./Server";
 
-export type Invite = { id: string, server: Server, created_at: string, expires_at: string, };
+export type Invite = { id: string, server: Server, created_at: string, expires_at: string | null, max_uses: number | null, uses: number, temporary: boolean, };
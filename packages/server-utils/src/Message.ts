@@ -1,6 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Attachment } from "./Attachment";
 import type { MessageKind } from "./MessageKind";
+import type { Reaction } from "./Reaction";
+import type { ReferencedMessage } from "./ReferencedMessage";
 import type { ServerMember } from "./ServerMember";
 import type { User } from "./User";
 
-export type Message = { id: `${number}`, kind: MessageKind, updated_at: string | null, content: string, channel_id: `${number}`, user: User, member: ServerMember | null, };
+export type Message = { id: `${number}`, kind: MessageKind, updated_at: string | null, content: string, channel_id: `${number}`, user: User, member: ServerMember | null, attachments: Array<Attachment>, reactions: Array<Reaction>, referenced_message: ReferencedMessage | null, };
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { OverwriteKind } from "./OverwriteKind";
+
+export type PermissionOverwrite = { channel_id: `${number}`, target_id: `${number}`, kind: OverwriteKind, allow: `${number}`, deny: `${number}`, };
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PresenceStatus } from "./PresenceStatus";
+
+export type Presence = { user_id: `${number}`, status: PresenceStatus, custom_status: string | null, };
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ReadState = { channel_id: `${number}`, last_read_message_id: `${number}` | null, unread_count: number, mention_count: number, };
@@ -1,4 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Presence } from "./Presence";
 import type { User } from "./User";
 
-export type ServerMember = { user_id: `${number}`, server_id: `${number}`, created_at: string, nickname: string | null, user: User | null, };
+export type ServerMember = { user_id: `${number}`, server_id: `${number}`, created_at: string, nickname: string | null, user: User | null, roles: Array<`${number}`> | null, timed_out_until: string | null, temporary: boolean, invite_id?: string, presence?: Presence, };
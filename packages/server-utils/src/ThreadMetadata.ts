@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ThreadMetadata = { parent_id: `${number}`, starter_message_id: `${number}` | null, owner_id: `${number}` | null, archived: boolean, };
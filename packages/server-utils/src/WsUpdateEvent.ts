@@ -2,9 +2,16 @@
 import type { Channel } from "./Channel";
 import type { Invite } from "./Invite";
 import type { Message } from "./Message";
+import type { PermissionOverwrite } from "./PermissionOverwrite";
+import type { Presence } from "./Presence";
+import type { ReadState } from "./ReadState";
 import type { Server } from "./Server";
 import type { ServerMember } from "./ServerMember";
+import type { ServerRole } from "./ServerRole";
+import type { User } from "./User";
+import type { UserBlock } from "./UserBlock";
 import type { UserFriend } from "./UserFriend";
 import type { UserFriendRequest } from "./UserFriendRequest";
+import type { UserSettings } from "./UserSettings";
 
-export type WsUpdateEvent = { "type": "reauthenticate" } | { "type": "server_create", "data": Server } | { "type": "server_update", "data": { id: `${number}`, name: string | null, } } | { "type": "server_delete", "data": { id: `${number}`, } } | { "type": "channel_create", "data": Channel } | { "type": "channel_update", "data": { id: `${number}`, name: string | null, } } | { "type": "channel_delete", "data": { id: `${number}`, } } | { "type": "message_create", "data": Message } | { "type": "message_update", "data": { id: `${number}`, updated_at: string, content: string | null, } } | { "type": "message_delete", "data": { id: `${number}`, } } | { "type": "invite_create", "data": Invite } | { "type": "invite_delete", "data": { id: string, } } | { "type": "member_create", "data": ServerMember } | { "type": "member_update", "data": { user_id: `${number}`, server_id: `${number}`, nickname: string | null | null, } } | { "type": "member_delete", "data": { user_id: `${number}`, server_id: `${number}`, } } | { "type": "user_update", "data": { id: `${number}`, username: string | null, display_name: string | null | null, } } | { "type": "friend_request_create", "data": UserFriendRequest } | { "type": "friend_request_delete", "data": { sender_id: `${number}`, receiver_id: `${number}`, } } | { "type": "friend_create", "data": UserFriend } | { "type": "friend_delete", "data": { user_id: `${number}`, friend_id: `${number}`, } };
+export type WsUpdateEvent = { "type": "reauthenticate" } | { "type": "ready", "data": { session_id: `${number}`, } } | { "type": "resumed" } | { "type": "invalid_session" } | { "type": "server_create", "data": Server } | { "type": "server_update", "data": { id: `${number}`, name: string | null, owner_id: `${number}`, } } | { "type": "server_delete", "data": { id: `${number}`, } } | { "type": "channel_create", "data": Channel } | { "type": "channel_update", "data": { id: `${number}`, name: string | null, parent_id: `${number}` | null, position: number | null, owner_id: `${number}`, } } | { "type": "channel_delete", "data": { id: `${number}`, } } | { "type": "channel_overwrite_update", "data": PermissionOverwrite } | { "type": "channel_overwrite_delete", "data": { channel_id: `${number}`, target_id: `${number}`, } } | { "type": "channel_recipient_add", "data": { channel_id: `${number}`, user: User, } } | { "type": "channel_recipient_remove", "data": { channel_id: `${number}`, user_id: `${number}`, } } | { "type": "thread_create", "data": Channel } | { "type": "thread_update", "data": { id: `${number}`, name: string | null, archived: boolean | null, } } | { "type": "thread_delete", "data": { id: `${number}`, parent_id: `${number}`, } } | { "type": "message_create", "data": Message } | { "type": "message_update", "data": { id: `${number}`, updated_at: string, content: string | null, } } | { "type": "message_delete", "data": { id: `${number}`, } } | { "type": "reaction_add", "data": { channel_id: `${number}`, message_id: `${number}`, user_id: `${number}`, emoji: string, } } | { "type": "reaction_remove", "data": { channel_id: `${number}`, message_id: `${number}`, user_id: `${number}`, emoji: string, } } | { "type": "typing_start", "data": { channel_id: `${number}`, user_id: `${number}`, timestamp: string, } } | { "type": "typing_stop", "data": { channel_id: `${number}`, user_id: `${number}`, } } | { "type": "read_state_update", "data": ReadState } | { "type": "invite_create", "data": Invite } | { "type": "invite_delete", "data": { id: string, } } | { "type": "member_create", "data": ServerMember } | { "type": "member_update", "data": { user_id: `${number}`, server_id: `${number}`, nickname: string | null | null, roles: Array<`${number}`>, timed_out_until: string | null | null, } } | { "type": "member_delete", "data": { user_id: `${number}`, server_id: `${number}`, } } | { "type": "role_create", "data": ServerRole } | { "type": "role_update", "data": { id: `${number}`, server_id: `${number}`, name: string | null, permissions: `${number}`, } } | { "type": "role_delete", "data": { id: `${number}`, server_id: `${number}`, } } | { "type": "user_update", "data": { id: `${number}`, username: string | null, display_name: string | null | null, } } | { "type": "presence_update", "data": Presence } | { "type": "user_settings_update", "data": UserSettings } | { "type": "friend_request_create", "data": UserFriendRequest } | { "type": "friend_request_delete", "data": { sender_id: `${number}`, receiver_id: `${number}`, } } | { "type": "friend_create", "data": UserFriend } | { "type": "friend_delete", "data": { user_id: `${number}`, friend_id: `${number}`, } } | { "type": "block_create", "data": UserBlock } | { "type": "block_delete", "data": { user_id: `${number}`, } };
@@ -0,0 +1,101 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::query;
+use validator::Validate;
+
+use crate::{
+	error::ApiResult,
+	middleware::Identity,
+	models::{
+		auditlog::{AuditLogAction, AuditLogEntry},
+		permissions::{has_server_permission, Permissions},
+		scope::{ReadWrite, Scope},
+	},
+	AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GetAuditLogQuery {
+	user_id: Option<u64>,
+	action: Option<AuditLogAction>,
+	#[validate(length(min = 1, max = 64))]
+	target_id: Option<String>,
+	before: Option<u64>,
+	after: Option<u64>,
+	#[validate(range(min = 1, max = 100))]
+	limit: Option<u64>,
+}
+
+pub async fn get_audit_log(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+	query: web::Query<GetAuditLogQuery>,
+) -> ApiResult {
+	query.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	if !has_server_permission(
+		&app_state.db,
+		server_id,
+		user_id,
+		Permissions::VIEW_AUDIT_LOG,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	// newest first, the ids are snowflakes so they double as the pagination cursor
+	let entries = query!(
+		r#"SELECT id, user_id, action, target_id, changes, reason, created_at
+FROM ServerAuditLogEntry
+WHERE server_id = ?
+AND (? IS NULL OR user_id = ?)
+AND (? IS NULL OR action = ?)
+AND (? IS NULL OR target_id = ?)
+AND (? IS NULL OR id < ?)
+AND (? IS NULL OR id > ?)
+ORDER BY id DESC
+LIMIT ?
+"#,
+		server_id,
+		query.user_id,
+		query.user_id,
+		query.action.map(|action| action.to_string()),
+		query.action.map(|action| action.to_string()),
+		query.target_id,
+		query.target_id,
+		query.before,
+		query.before,
+		query.after,
+		query.after,
+		query.limit.unwrap_or(50)
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(
+		entries
+			.into_iter()
+			// entries with actions this version doesn't know about are skipped rather than failing the request
+			.filter_map(|row| {
+				Some(AuditLogEntry {
+					id: row.id,
+					server_id,
+					user_id: row.user_id,
+					action: row.action.parse().ok()?,
+					target_id: row.target_id,
+					changes: serde_json::from_str(&row.changes).unwrap_or_default(),
+					reason: row.reason,
+					created_at: row.created_at,
+				})
+			})
+			.collect::<Vec<_>>(),
+	))
+}
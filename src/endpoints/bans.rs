@@ -0,0 +1,297 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::query;
+use validator::Validate;
+
+use crate::{
+	endpoints::{
+		members::{can_moderate, send_member_removal},
+		AuditLogReason,
+	},
+	error::ApiResult,
+	middleware::Identity,
+	models::{
+		attachment::attachment_key,
+		auditlog::{insert_audit_log_entry, AuditLogAction, AuditLogChange, AuditLogEntry},
+		ban::ServerBan,
+		permissions::{has_server_permission, Permissions},
+		scope::{ReadWrite, Scope},
+		snowflake_at,
+		user::User,
+	},
+	storage::delete_in_background,
+	ws::{channel_connections, send_updates, WsUpdateEvent},
+	AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GetBansQuery {
+	#[validate(range(min = 1, max = 100))]
+	limit: Option<u64>,
+	after: Option<u64>,
+}
+
+pub async fn get_bans(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+	query: web::Query<GetBansQuery>,
+) -> ApiResult {
+	query.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	if !has_server_permission(&app_state.db, server_id, user_id, Permissions::BAN_MEMBERS).await? {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let bans = query!(
+		r#"SELECT ServerBan.reason, ServerBan.created_at, User.id, User.username, User.display_name
+FROM ServerBan
+INNER JOIN User ON User.id=ServerBan.user_id
+WHERE ServerBan.server_id = ? AND ServerBan.user_id > ?
+ORDER BY ServerBan.user_id
+LIMIT ?
+"#,
+		server_id,
+		query.after.unwrap_or(0),
+		query.limit.unwrap_or(50)
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(
+		bans.into_iter()
+			.map(|row| ServerBan {
+				server_id,
+				user: User {
+					id: row.id,
+					username: row.username,
+					display_name: row.display_name,
+				},
+				reason: row.reason,
+				created_at: row.created_at,
+			})
+			.collect::<Vec<_>>(),
+	))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct BanMemberBody {
+	#[serde(default, deserialize_with = "super::trim_opt_string")]
+	#[validate(length(min = 1, max = 512))]
+	reason: Option<String>,
+	// how far back the user's messages in the server are deleted, at most 7 days
+	#[validate(range(max = 604800))]
+	delete_message_seconds: Option<i64>,
+}
+
+pub async fn ban_member(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64)>,
+	body: web::Json<BanMemberBody>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, target_id) = path.into_inner();
+
+	if !can_moderate(
+		&app_state,
+		server_id,
+		user_id,
+		target_id,
+		Permissions::BAN_MEMBERS,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	// users can be banned before they ever join
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM User WHERE id = ?) AS `exists: bool`",
+		target_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	query!(
+		r#"INSERT INTO ServerBan (server_id, user_id, reason) VALUES (?, ?, ?)
+ON DUPLICATE KEY UPDATE reason = VALUES(reason)"#,
+		server_id,
+		target_id,
+		body.reason
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	let was_member = query!(
+		"DELETE FROM ServerMember WHERE server_id = ? AND user_id = ?",
+		server_id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?
+	.rows_affected()
+		> 0;
+
+	let mut deleted_messages = HashMap::<u64, Vec<u64>>::new();
+	let mut attachment_keys = vec![];
+
+	if let Some(seconds) = body.delete_message_seconds.filter(|seconds| *seconds > 0) {
+		let first_id = snowflake_at(Utc::now() - chrono::Duration::seconds(seconds));
+
+		for row in query!(
+			r#"SELECT ChannelMessage.id, ChannelMessage.channel_id
+FROM ChannelMessage
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+WHERE Channel.server_id = ? AND ChannelMessage.user_id = ? AND ChannelMessage.id >= ?
+"#,
+			server_id,
+			target_id,
+			first_id
+		)
+		.fetch_all(&mut *tx)
+		.await?
+		{
+			deleted_messages
+				.entry(row.channel_id)
+				.or_default()
+				.push(row.id);
+		}
+
+		attachment_keys = query!(
+			r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.filename, ChannelMessage.channel_id
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+WHERE Channel.server_id = ? AND ChannelMessage.user_id = ? AND ChannelMessage.id >= ?
+"#,
+			server_id,
+			target_id,
+			first_id
+		)
+		.fetch_all(&mut *tx)
+		.await?
+		.into_iter()
+		.map(|row| attachment_key(row.channel_id, row.id, &row.filename))
+		.collect::<Vec<_>>();
+
+		query!(
+			r#"DELETE ChannelMessage
+FROM ChannelMessage
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+WHERE Channel.server_id = ? AND ChannelMessage.user_id = ? AND ChannelMessage.id >= ?
+"#,
+			server_id,
+			target_id,
+			first_id
+		)
+		.execute(&mut *tx)
+		.await?;
+	}
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::MemberBan,
+		target_id,
+		// the ban's own reason is preferred over the header
+		body.reason.clone().or(reason.0),
+	)
+	.with_changes([AuditLogChange::new(
+		"delete_message_seconds",
+		None,
+		body.delete_message_seconds,
+	)]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	if was_member {
+		send_member_removal(&app_state, server_id, target_id);
+	}
+
+	for (channel_id, message_ids) in deleted_messages {
+		send_updates(
+			message_ids
+				.into_iter()
+				.map(|id| WsUpdateEvent::MessageDelete { id }),
+			&app_state,
+			channel_connections(&app_state, server_id, channel_id).await?,
+		);
+	}
+
+	delete_in_background(&app_state, attachment_keys);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn unban_member(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64)>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, target_id) = path.into_inner();
+
+	if !has_server_permission(&app_state.db, server_id, user_id, Permissions::BAN_MEMBERS).await? {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	let query = query!(
+		"DELETE FROM ServerBan WHERE server_id = ? AND user_id = ?",
+		server_id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	if query.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::MemberUnban,
+		target_id,
+		reason.0,
+	);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	Ok(HttpResponse::Ok().finish())
+}
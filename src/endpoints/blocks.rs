@@ -0,0 +1,211 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::query;
+
+use crate::{
+	error::{ApiResult, ErrorResponse},
+	middleware::Identity,
+	models::{
+		block::UserBlock,
+		scope::{ReadWrite, Scope},
+		user::User,
+	},
+	ws::{send_updates, WsUpdateEvent},
+	AppState,
+};
+
+pub async fn get_blocks(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let blocks = query!(
+		r#"SELECT UserBlock.created_at, User.id, User.username, User.display_name
+FROM UserBlock
+INNER JOIN User ON User.id=UserBlock.blocked_id
+WHERE UserBlock.user_id = ?
+"#,
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(
+		blocks
+			.into_iter()
+			.map(|row| UserBlock {
+				user: User {
+					id: row.id,
+					username: row.username,
+					display_name: row.display_name,
+				},
+				created_at: row.created_at,
+			})
+			.collect::<Vec<_>>(),
+	))
+}
+
+pub async fn block_user(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let target_id = path.into_inner();
+
+	if user_id == target_id {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Cannot block self".to_string(),
+		}));
+	}
+
+	let Some(target) = query!(
+		"SELECT username, display_name FROM User WHERE id = ?",
+		target_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	let created_at = Utc::now();
+
+	let mut tx = app_state.db.begin().await?;
+
+	match query!(
+		"INSERT INTO UserBlock (user_id, blocked_id, created_at) VALUES (?, ?, ?)",
+		user_id,
+		target_id,
+		created_at
+	)
+	.execute(&mut *tx)
+	.await
+	{
+		Err(e)
+			if e.as_database_error()
+				.is_some_and(|e| e.is_unique_violation()) =>
+		{
+			return Ok(HttpResponse::Ok().finish());
+		}
+		r => r?,
+	};
+
+	// blocking ends the friendship along with any pending requests between the users
+	let was_friend = query!(
+		"DELETE FROM UserFriend WHERE (user_id, friend_id) = (?, ?) OR (user_id, friend_id) = (?, ?)",
+		user_id,
+		target_id,
+		target_id,
+		user_id
+	)
+	.execute(&mut *tx)
+	.await?
+	.rows_affected()
+		> 0;
+
+	let friend_requests = query!(
+		"SELECT sender_id, receiver_id FROM UserFriendRequest WHERE (sender_id, receiver_id) = (?, ?) OR (sender_id, receiver_id) = (?, ?)",
+		user_id,
+		target_id,
+		target_id,
+		user_id
+	)
+	.fetch_all(&mut *tx)
+	.await?;
+
+	query!(
+		"DELETE FROM UserFriendRequest WHERE (sender_id, receiver_id) = (?, ?) OR (sender_id, receiver_id) = (?, ?)",
+		user_id,
+		target_id,
+		target_id,
+		user_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	if let Some(mut blocked_users) = app_state.blocked_users.get_mut(&user_id) {
+		blocked_users.insert(target_id);
+	}
+
+	if was_friend {
+		send_updates(
+			[WsUpdateEvent::FriendDelete {
+				user_id,
+				friend_id: target_id,
+			}],
+			&app_state,
+			[user_id, target_id],
+		);
+	}
+
+	send_updates(
+		friend_requests
+			.into_iter()
+			.map(|row| WsUpdateEvent::FriendRequestDelete {
+				sender_id: row.sender_id,
+				receiver_id: row.receiver_id,
+			}),
+		&app_state,
+		[user_id, target_id],
+	);
+
+	send_updates(
+		[WsUpdateEvent::BlockCreate(UserBlock {
+			user: User {
+				id: target_id,
+				username: target.username,
+				display_name: target.display_name,
+			},
+			created_at,
+		})],
+		&app_state,
+		[user_id],
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn unblock_user(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let target_id = path.into_inner();
+
+	let result = query!(
+		"DELETE FROM UserBlock WHERE user_id = ? AND blocked_id = ?",
+		user_id,
+		target_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if result.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	if let Some(mut blocked_users) = app_state.blocked_users.get_mut(&user_id) {
+		blocked_users.remove(&target_id);
+	}
+
+	send_updates(
+		[WsUpdateEvent::BlockDelete { user_id: target_id }],
+		&app_state,
+		[user_id],
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
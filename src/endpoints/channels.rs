@@ -1,25 +1,60 @@
 use crate::{
-	error::{ApiResult, ErrorResponse},
+	endpoints::AuditLogReason,
+	error::{ApiResult, BackendError, ErrorResponse},
 	middleware::Identity,
 	models::{
-		channel::{Channel, ChannelKind},
+		attachment::attachment_key,
+		auditlog::{insert_audit_log_entry, AuditLogAction, AuditLogChange, AuditLogEntry},
+		channel::{Channel, ChannelKind, ThreadMetadata},
+		overwrite::{OverwriteKind, PermissionOverwrite},
+		permissions::{
+			apply_overwrites, get_channel_overwrites, get_member_permissions,
+			get_server_overwrites, get_server_permissions, has_channel_permission,
+			has_server_permission, Permissions,
+		},
+		readstate::get_read_states,
 		scope::{ReadWrite, Scope},
 	},
+	storage::delete_in_background,
 	update_structure,
-	ws::{send_updates, WsUpdateEvent},
+	ws::{channel_connections, send_updates, WsUpdateEvent},
 	AppState,
 };
 use actix_web::{web, HttpResponse};
 use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
 use sqlx::query;
-use std::sync::Mutex;
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Mutex,
+};
 use validator::Validate;
 
+async fn is_category(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	channel_id: u64,
+) -> Result<bool, BackendError> {
+	Ok(query!(
+		"SELECT EXISTS(SELECT 1 FROM Channel WHERE id = ? AND server_id = ? AND kind = 'category') AS `exists: bool`",
+		channel_id,
+		server_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists)
+}
+
+#[serde_as]
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreateChannelBody {
 	#[serde(deserialize_with = "super::trim_string")]
 	#[validate(length(min = 2, max = 32))]
 	name: String,
+	kind: Option<ChannelKind>,
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+	parent_id: Option<u64>,
 }
 
 pub async fn create_channel(
@@ -28,6 +63,7 @@ pub async fn create_channel(
 	generator: web::Data<Mutex<snowflaked::Generator>>,
 	body: web::Json<CreateChannelBody>,
 	path: web::Path<u64>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	body.validate()?;
 
@@ -37,60 +73,107 @@ pub async fn create_channel(
 
 	let server_id = path.into_inner();
 
-	if !query!(
-		"SELECT EXISTS(SELECT 1 FROM Server WHERE id = ? AND owner_id = ?) AS `exists: bool`",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_CHANNELS,
 	)
-	.fetch_one(&app_state.db)
 	.await?
-	.exists
 	{
 		return Ok(HttpResponse::NotFound().finish());
 	};
 
-	if query!(
-		"SELECT COUNT(*) > 200 AS `over_limit: bool` FROM Channel WHERE server_id = ?",
+	let kind = body.kind.unwrap_or(ChannelKind::Text);
+
+	if !matches!(kind, ChannelKind::Text | ChannelKind::Category) {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Only text channels and categories can be created".to_string(),
+		}));
+	}
+
+	if let Some(parent_id) = body.parent_id {
+		if kind == ChannelKind::Category {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "Categories can't be nested".to_string(),
+			}));
+		}
+
+		if !is_category(&app_state, server_id, parent_id).await? {
+			return Ok(HttpResponse::NotFound().finish());
+		}
+	}
+
+	let channels = query!(
+		"SELECT COUNT(*) > 200 AS `over_limit: bool`, MAX(position) AS position FROM Channel WHERE server_id = ? AND kind != 'thread'",
 		server_id
 	)
 	.fetch_one(&app_state.db)
-	.await?
-	.over_limit
-	{
+	.await?;
+
+	if channels.over_limit {
 		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
 			error: "channel_limit_reached".to_string(),
 		}));
 	}
 
-	let channel_id = {
-		let mut generator = generator.lock().unwrap();
-		generator.generate()
-	};
+	// new channels are placed at the bottom of the list
+	let position = channels.position.map_or(0, |position| position + 1);
+
+	let channel_id = generator.lock().unwrap().generate();
+
+	let mut tx = app_state.db.begin().await?;
 
 	query!(
-		"INSERT INTO Channel (id, name, kind, server_id) VALUES (?, ?, 'text', ?)",
+		"INSERT INTO Channel (id, name, kind, server_id, parent_id, position) VALUES (?, ?, ?, ?, ?, ?)",
 		channel_id,
 		body.name,
-		server_id
+		kind.to_string(),
+		server_id,
+		body.parent_id,
+		position
 	)
-	.execute(&app_state.db)
+	.execute(&mut *tx)
 	.await?;
 
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ChannelCreate,
+		channel_id,
+		reason.0,
+	)
+	.with_changes([
+		AuditLogChange::new("name", None, Some(&body.name)),
+		AuditLogChange::new("kind", None, Some(kind)),
+		AuditLogChange::new("parent_id", None, body.parent_id.map(|id| id.to_string())),
+		AuditLogChange::new("position", None, Some(position)),
+	]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
 	let channel = Channel {
 		id: channel_id,
 		name: body.name.to_string(),
-		kind: ChannelKind::Text,
+		kind,
 		server_id: Some(server_id),
+		parent_id: body.parent_id,
+		position: Some(position),
 		user: None,
+		recipients: None,
+		owner_id: None,
+		thread: None,
+		read_state: None,
 	};
 
-	if let Some(members) = app_state.server_connections.get(&server_id) {
-		send_updates(
-			[WsUpdateEvent::ChannelCreate(channel.clone())],
-			&app_state,
-			members.iter().copied(),
-		);
-	}
+	send_updates(
+		[WsUpdateEvent::ChannelCreate(channel.clone())],
+		&app_state,
+		channel_connections(&app_state, server_id, channel_id).await?,
+	);
 
 	Ok(HttpResponse::Created().json(channel))
 }
@@ -106,39 +189,200 @@ pub async fn get_channels(
 
 	let server_id = path.into_inner();
 
-	if !query!(
-        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
-        server_id,
-        user_id
-    )
-    .fetch_one(&app_state.db)
-    .await?
-        .exists
-    {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
+	let Some((permissions, roles)) =
+		get_member_permissions(&app_state.db, server_id, user_id).await?
+	else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
 
 	let channels = query!(
-		"SELECT id, name, kind FROM Channel WHERE server_id = ?",
+		"SELECT id, name, kind, parent_id, position FROM Channel WHERE server_id = ? AND kind != 'thread' ORDER BY position, id",
 		server_id
 	)
 	.fetch_all(&app_state.db)
 	.await?;
 
+	let overwrites = get_server_overwrites(&app_state.db, server_id).await?;
+	let mut read_states = get_read_states(&app_state.db, user_id, Some(server_id)).await?;
+
 	Ok(HttpResponse::Ok().json(
 		channels
 			.into_iter()
+			.filter(|row| {
+				apply_overwrites(
+					permissions,
+					server_id,
+					user_id,
+					&roles,
+					overwrites
+						.get(&row.id)
+						.map(Vec::as_slice)
+						.unwrap_or_default(),
+				)
+				.contains(Permissions::VIEW_CHANNEL)
+			})
 			.map(|row| Channel {
 				id: row.id,
 				name: row.name,
 				kind: row.kind.parse().unwrap(),
 				server_id: Some(server_id),
+				parent_id: row.parent_id,
+				position: Some(row.position),
 				user: None,
+				recipients: None,
+				owner_id: None,
+				thread: None,
+				read_state: read_states.remove(&row.id),
 			})
 			.collect::<Vec<_>>(),
 	))
 }
 
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct ReorderChannelBody {
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+	id: u64,
+	position: Option<u32>,
+	#[serde(default, deserialize_with = "super::deserialize_some_snowflake")]
+	parent_id: Option<Option<u64>>,
+}
+
+pub async fn reorder_channels(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	body: web::Json<Vec<ReorderChannelBody>>,
+	path: web::Path<u64>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	if !has_server_permission(
+		&app_state.db,
+		server_id,
+		user_id,
+		Permissions::MANAGE_CHANNELS,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut ids = HashSet::new();
+
+	if body.is_empty() || !body.iter().all(|channel| ids.insert(channel.id)) {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Each channel must be given exactly once".to_string(),
+		}));
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let channels = query!(
+		"SELECT id, kind, parent_id, position FROM Channel WHERE server_id = ? AND kind != 'thread' FOR UPDATE",
+		server_id
+	)
+	.fetch_all(&mut *tx)
+	.await?
+	.into_iter()
+	.map(|row| (row.id, row))
+	.collect::<HashMap<_, _>>();
+
+	let mut changed = vec![];
+
+	for channel in body.iter() {
+		let Some(current) = channels.get(&channel.id) else {
+			return Ok(HttpResponse::NotFound().finish());
+		};
+
+		if let Some(Some(parent_id)) = channel.parent_id {
+			if current.kind == "category" {
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					error: "Categories can't be nested".to_string(),
+				}));
+			}
+
+			if !channels
+				.get(&parent_id)
+				.is_some_and(|parent| parent.kind == "category")
+			{
+				return Ok(HttpResponse::NotFound().finish());
+			}
+		}
+
+		let position = channel
+			.position
+			.filter(|position| *position != current.position);
+		let parent_id = channel
+			.parent_id
+			.filter(|parent_id| *parent_id != current.parent_id);
+
+		if position.is_some() || parent_id.is_some() {
+			changed.push((current, position, parent_id));
+		}
+	}
+
+	for (current, position, parent_id) in &changed {
+		query!(
+			"UPDATE Channel SET position = ?, parent_id = ? WHERE id = ? AND server_id = ?",
+			position.unwrap_or(current.position),
+			parent_id.unwrap_or(current.parent_id),
+			current.id,
+			server_id
+		)
+		.execute(&mut *tx)
+		.await?;
+
+		let entry = AuditLogEntry::new(
+			&generator,
+			server_id,
+			user_id,
+			AuditLogAction::ChannelUpdate,
+			current.id,
+			reason.0.clone(),
+		)
+		.with_changes(
+			position
+				.map(|position| AuditLogChange::new("position", current.position, position))
+				.into_iter()
+				.chain(parent_id.map(|parent_id| {
+					AuditLogChange::new(
+						"parent_id",
+						current.parent_id.map(|id| id.to_string()),
+						parent_id.map(|id| id.to_string()),
+					)
+				})),
+		);
+
+		insert_audit_log_entry(&mut *tx, &entry).await?;
+	}
+
+	tx.commit().await?;
+
+	for (current, position, parent_id) in changed {
+		send_updates(
+			[WsUpdateEvent::ChannelUpdate {
+				id: current.id,
+				name: None,
+				parent_id,
+				position,
+				owner_id: None,
+			}],
+			&app_state,
+			channel_connections(&app_state, server_id, current.id).await?,
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
 pub async fn get_channel(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
@@ -150,20 +394,24 @@ pub async fn get_channel(
 
 	let (server_id, channel_id) = path.into_inner();
 
-	if !query!(
-        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
-        server_id,
-        user_id
-    )
-    .fetch_one(&app_state.db)
-    .await?
-        .exists
-    {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		channel_id,
+		user_id,
+		Permissions::VIEW_CHANNEL,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
 
 	let channel = query!(
-		"SELECT name, kind FROM Channel WHERE id = ? AND server_id = ?",
+		r#"SELECT Channel.name, Channel.kind, Channel.parent_id AS `category_id`, Channel.position, ChannelThread.parent_id AS `parent_id?`, ChannelThread.starter_message_id, ChannelThread.owner_id, ChannelThread.archived AS `archived?: bool`
+FROM Channel
+LEFT JOIN ChannelThread ON ChannelThread.channel_id=Channel.id
+WHERE Channel.id = ? AND Channel.server_id = ?
+"#,
 		channel_id,
 		server_id
 	)
@@ -175,7 +423,19 @@ pub async fn get_channel(
 		name: row.name,
 		kind: row.kind.parse().unwrap(),
 		server_id: Some(server_id),
+		parent_id: row.category_id,
+		// threads aren't part of the channel list, so they have no position
+		position: row.parent_id.is_none().then_some(row.position),
 		user: None,
+		recipients: None,
+		owner_id: None,
+		thread: row.parent_id.map(|parent_id| ThreadMetadata {
+			parent_id,
+			starter_message_id: row.starter_message_id,
+			owner_id: row.owner_id,
+			archived: row.archived.unwrap_or_default(),
+		}),
+		read_state: None,
 	})))
 }
 
@@ -184,13 +444,17 @@ pub struct UpdateChannelBody {
 	#[serde(default, deserialize_with = "super::trim_opt_string")]
 	#[validate(length(min = 2, max = 32))]
 	name: Option<String>,
+	#[serde(default, deserialize_with = "super::deserialize_some_snowflake")]
+	parent_id: Option<Option<u64>>,
 }
 
 pub async fn update_channel(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	body: web::Json<UpdateChannelBody>,
 	path: web::Path<(u64, u64)>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	body.validate()?;
 
@@ -200,41 +464,94 @@ pub async fn update_channel(
 
 	let (server_id, channel_id) = path.into_inner();
 
-	if !query!(
-		"SELECT EXISTS(SELECT 1 FROM Server WHERE id = ? AND owner_id = ?) AS `exists: bool`",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_CHANNELS,
 	)
-	.fetch_one(&app_state.db)
 	.await?
-	.exists
 	{
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
-	let query = update_structure!("Channel", body, name)
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let Some(channel) = query!(
+		"SELECT name, kind, parent_id FROM Channel WHERE id = ? AND server_id = ? AND kind != 'thread' FOR UPDATE",
+		channel_id,
+		server_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if let Some(Some(parent_id)) = body.parent_id {
+		if channel.kind == "category" {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "Categories can't be nested".to_string(),
+			}));
+		}
+
+		if !is_category(&app_state, server_id, parent_id).await? {
+			return Ok(HttpResponse::NotFound().finish());
+		}
+	}
+
+	let query = update_structure!("Channel", body, name, parent_id)
 		.push(" WHERE id = ")
 		.push_bind(channel_id)
 		.push(" AND server_id = ")
 		.push_bind(server_id)
+		.push(" AND kind != 'thread'")
 		.build()
-		.execute(&app_state.db)
+		.execute(&mut *tx)
 		.await?;
 
 	if query.rows_affected() == 0 {
 		return Ok(HttpResponse::NotFound().finish());
 	}
 
-	if let Some(members) = app_state.server_connections.get(&server_id) {
-		send_updates(
-			[WsUpdateEvent::ChannelUpdate {
-				id: channel_id,
-				name: body.name.clone(),
-			}],
-			&app_state,
-			members.iter().copied(),
-		);
-	}
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ChannelUpdate,
+		channel_id,
+		reason.0,
+	)
+	.with_changes(
+		body.name
+			.as_ref()
+			.map(|name| AuditLogChange::new("name", &channel.name, name))
+			.into_iter()
+			.chain(body.parent_id.map(|parent_id| {
+				AuditLogChange::new(
+					"parent_id",
+					channel.parent_id.map(|id| id.to_string()),
+					parent_id.map(|id| id.to_string()),
+				)
+			})),
+	);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelUpdate {
+			id: channel_id,
+			name: body.name.clone(),
+			parent_id: body.parent_id,
+			position: None,
+			owner_id: None,
+		}],
+		&app_state,
+		channel_connections(&app_state, server_id, channel_id).await?,
+	);
 
 	Ok(HttpResponse::Ok().finish())
 }
@@ -242,7 +559,9 @@ pub async fn update_channel(
 pub async fn delete_channel(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	path: web::Path<(u64, u64)>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
 		return Ok(HttpResponse::Forbidden().finish());
@@ -250,37 +569,413 @@ pub async fn delete_channel(
 
 	let (server_id, channel_id) = path.into_inner();
 
-	if !query!(
-		"SELECT EXISTS(SELECT 1 FROM Server WHERE id = ? AND owner_id = ?) AS `exists: bool`",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_CHANNELS,
 	)
-	.fetch_one(&app_state.db)
 	.await?
-	.exists
 	{
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
+	// the overwrites are deleted along with the channel, so the viewers are determined beforehand
+	let viewers = channel_connections(&app_state, server_id, channel_id).await?;
+
+	let mut tx = app_state.db.begin().await?;
+
+	let Some(channel) = query!(
+		"SELECT name FROM Channel WHERE id = ? AND server_id = ? AND kind != 'thread' FOR UPDATE",
+		channel_id,
+		server_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	// the channels in a deleted category are moved out of it by the foreign key
+	let children = query!(
+		"SELECT id FROM Channel WHERE parent_id = ? AND server_id = ? FOR UPDATE",
+		channel_id,
+		server_id
+	)
+	.fetch_all(&mut *tx)
+	.await?;
+
+	let threads = query!(
+		"SELECT channel_id FROM ChannelThread WHERE parent_id = ? FOR UPDATE",
+		channel_id
+	)
+	.fetch_all(&mut *tx)
+	.await?;
+
+	// the attachments of the channel and its threads are deleted along with their messages
+	let attachment_keys = query!(
+		r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.filename, ChannelMessage.channel_id
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+WHERE ChannelMessage.channel_id = ? OR ChannelMessage.channel_id IN (SELECT channel_id FROM ChannelThread WHERE parent_id = ?)
+"#,
+		channel_id,
+		channel_id
+	)
+	.fetch_all(&mut *tx)
+	.await?
+	.into_iter()
+	.map(|row| attachment_key(row.channel_id, row.id, &row.filename))
+	.collect::<Vec<_>>();
+
+	// deleting the parent only cascades to the ChannelThread rows, so the threads' channels are removed explicitly
+	query!(
+		"DELETE FROM Channel WHERE id IN (SELECT channel_id FROM ChannelThread WHERE parent_id = ?) AND server_id = ?",
+		channel_id,
+		server_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
 	let query = query!(
-		"DELETE FROM Channel WHERE id = ? AND server_id = ?",
+		"DELETE FROM Channel WHERE id = ? AND server_id = ? AND kind != 'thread'",
 		channel_id,
 		server_id
 	)
-	.execute(&app_state.db)
+	.execute(&mut *tx)
 	.await?;
 
 	if query.rows_affected() == 0 {
 		return Ok(HttpResponse::NotFound().finish());
 	}
 
-	if let Some(members) = app_state.server_connections.get(&server_id) {
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ChannelDelete,
+		channel_id,
+		reason.0,
+	)
+	.with_changes([AuditLogChange::new("name", Some(&channel.name), None)]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	delete_in_background(&app_state, attachment_keys);
+
+	// threads are visible to whoever can view their parent
+	send_updates(
+		threads
+			.into_iter()
+			.map(|thread| WsUpdateEvent::ThreadDelete {
+				id: thread.channel_id,
+				parent_id: channel_id,
+			})
+			.chain([WsUpdateEvent::ChannelDelete { id: channel_id }]),
+		&app_state,
+		viewers,
+	);
+
+	for child in children {
 		send_updates(
-			[WsUpdateEvent::ChannelDelete { id: channel_id }],
+			[WsUpdateEvent::ChannelUpdate {
+				id: child.id,
+				name: None,
+				parent_id: Some(None),
+				position: None,
+				owner_id: None,
+			}],
 			&app_state,
-			members.iter().copied(),
+			channel_connections(&app_state, server_id, child.id).await?,
 		);
 	}
 
 	Ok(HttpResponse::Ok().finish())
 }
+
+pub async fn get_overwrites(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, channel_id) = path.into_inner();
+
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		channel_id,
+		user_id,
+		Permissions::VIEW_CHANNEL,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM Channel WHERE id = ? AND server_id = ?) AS `exists: bool`",
+		channel_id,
+		server_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	Ok(HttpResponse::Ok().json(get_channel_overwrites(&app_state.db, channel_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOverwriteBody {
+	kind: OverwriteKind,
+	#[serde(default)]
+	allow: Permissions,
+	#[serde(default)]
+	deny: Permissions,
+}
+
+pub async fn update_overwrite(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	body: web::Json<UpdateOverwriteBody>,
+	path: web::Path<(u64, u64, u64)>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, channel_id, target_id) = path.into_inner();
+
+	if body.allow & body.deny != Permissions::empty() {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Permissions cannot be both allowed and denied".to_string(),
+		}));
+	}
+
+	// members can't grant or revoke permissions they don't have themselves
+	if !get_server_permissions(&app_state.db, server_id, user_id)
+		.await?
+		.is_some_and(|permissions| {
+			permissions.contains(Permissions::MANAGE_CHANNELS | body.allow | body.deny)
+		}) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM Channel WHERE id = ? AND server_id = ? AND kind != 'thread') AS `exists: bool`",
+		channel_id,
+		server_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let target_exists = match body.kind {
+		OverwriteKind::Role => query!(
+			"SELECT EXISTS(SELECT 1 FROM ServerRole WHERE id = ? AND server_id = ?) AS `exists: bool`",
+			target_id,
+			server_id
+		)
+		.fetch_one(&app_state.db)
+		.await?
+		.exists,
+		OverwriteKind::Member => query!(
+			"SELECT EXISTS(SELECT 1 FROM ServerMember WHERE user_id = ? AND server_id = ?) AS `exists: bool`",
+			target_id,
+			server_id
+		)
+		.fetch_one(&app_state.db)
+		.await?
+		.exists,
+	};
+
+	if !target_exists {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	// members who lose access to the channel need to be told about it too
+	let previous_viewers = channel_connections(&app_state, server_id, channel_id).await?;
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let previous = query!(
+		"SELECT allow, deny FROM ChannelPermissionOverwrite WHERE channel_id = ? AND target_id = ? FOR UPDATE",
+		channel_id,
+		target_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?;
+
+	query!(
+		r#"INSERT INTO ChannelPermissionOverwrite (channel_id, target_id, kind, allow, deny) VALUES (?, ?, ?, ?, ?)
+ON DUPLICATE KEY UPDATE kind = VALUES(kind), allow = VALUES(allow), deny = VALUES(deny)"#,
+		channel_id,
+		target_id,
+		body.kind.to_string(),
+		body.allow.bits(),
+		body.deny.bits()
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ChannelOverwriteUpdate,
+		channel_id,
+		reason.0,
+	)
+	.with_changes([
+		AuditLogChange::new(
+			"target_id",
+			previous.as_ref().map(|_| target_id.to_string()),
+			Some(target_id.to_string()),
+		),
+		AuditLogChange::new(
+			"allow",
+			previous
+				.as_ref()
+				.map(|row| Permissions::from_bits_truncate(row.allow)),
+			Some(body.allow),
+		),
+		AuditLogChange::new(
+			"deny",
+			previous
+				.as_ref()
+				.map(|row| Permissions::from_bits_truncate(row.deny)),
+			Some(body.deny),
+		),
+	]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	let overwrite = PermissionOverwrite {
+		channel_id,
+		target_id,
+		kind: body.kind,
+		allow: body.allow,
+		deny: body.deny,
+	};
+
+	let viewers = channel_connections(&app_state, server_id, channel_id).await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelOverwriteUpdate(overwrite.clone())],
+		&app_state,
+		previous_viewers.union(&viewers).copied(),
+	);
+
+	Ok(HttpResponse::Ok().json(overwrite))
+}
+
+pub async fn delete_overwrite(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64, u64)>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, channel_id, target_id) = path.into_inner();
+
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually deleted, even with concurrent updates
+	let Some(overwrite) = query!(
+		r#"SELECT ChannelPermissionOverwrite.allow, ChannelPermissionOverwrite.deny
+FROM ChannelPermissionOverwrite
+INNER JOIN Channel ON Channel.id=ChannelPermissionOverwrite.channel_id
+WHERE ChannelPermissionOverwrite.channel_id = ? AND ChannelPermissionOverwrite.target_id = ? AND Channel.server_id = ?
+FOR UPDATE
+"#,
+		channel_id,
+		target_id,
+		server_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if !permissions.contains(
+		Permissions::MANAGE_CHANNELS
+			| Permissions::from_bits_truncate(overwrite.allow)
+			| Permissions::from_bits_truncate(overwrite.deny),
+	) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let previous_viewers = channel_connections(&app_state, server_id, channel_id).await?;
+
+	query!(
+		"DELETE FROM ChannelPermissionOverwrite WHERE channel_id = ? AND target_id = ?",
+		channel_id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ChannelOverwriteDelete,
+		channel_id,
+		reason.0,
+	)
+	.with_changes([
+		AuditLogChange::new("target_id", Some(target_id.to_string()), None),
+		AuditLogChange::new(
+			"allow",
+			Some(Permissions::from_bits_truncate(overwrite.allow)),
+			None,
+		),
+		AuditLogChange::new(
+			"deny",
+			Some(Permissions::from_bits_truncate(overwrite.deny)),
+			None,
+		),
+	]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	let viewers = channel_connections(&app_state, server_id, channel_id).await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelOverwriteDelete {
+			channel_id,
+			target_id,
+		}],
+		&app_state,
+		previous_viewers.union(&viewers).copied(),
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
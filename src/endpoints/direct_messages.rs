@@ -1,15 +1,112 @@
 use crate::{
-	error::ApiResult,
+	error::{ApiResult, BackendError, ErrorResponse},
 	middleware::Identity,
 	models::{
+		block::is_blocked_between,
 		channel::{Channel, ChannelKind},
+		friend::are_friends,
+		readstate::{get_read_states, seed_read_state},
 		scope::{ReadWrite, Scope},
+		settings::get_user_settings,
 		user::User,
 	},
+	ws::{send_updates, WsUpdateEvent},
 	AppState,
 };
 use actix_web::{web, HttpResponse};
-use sqlx::query;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use sqlx::{query, Executor, MySql};
+use std::{collections::HashSet, sync::Mutex};
+use validator::Validate;
+
+// including the owner
+pub const MAX_GROUP_DM_RECIPIENTS: usize = 10;
+
+// the recipients are returned as one row each, so the consecutive rows of a channel are merged
+fn collect_direct_channels(
+	user_id: u64,
+	rows: impl IntoIterator<Item = (u64, String, ChannelKind, Option<u64>, User)>,
+) -> Vec<Channel> {
+	let mut channels: Vec<Channel> = vec![];
+
+	for (id, name, kind, owner_id, recipient) in rows {
+		if !channels.last().is_some_and(|channel| channel.id == id) {
+			channels.push(Channel {
+				id,
+				name,
+				kind,
+				server_id: None,
+				parent_id: None,
+				position: None,
+				user: None,
+				recipients: Some(vec![]),
+				owner_id,
+				thread: None,
+				read_state: None,
+			});
+		}
+
+		let channel = channels.last_mut().unwrap();
+
+		// 1:1 DMs also expose the other user directly
+		if kind == ChannelKind::DM && recipient.id != user_id {
+			channel.user = Some(recipient.clone());
+		}
+
+		channel
+			.recipients
+			.get_or_insert_with(Vec::new)
+			.push(recipient);
+	}
+
+	channels
+}
+
+pub async fn get_group_dm<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	channel_id: u64,
+) -> Result<Option<Channel>, BackendError> {
+	let rows = query!(
+		r#"SELECT Channel.name, Channel.owner_id, User.id, User.username, User.display_name
+FROM Channel
+INNER JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
+INNER JOIN User ON User.id=DMChannelRecipient.user_id
+WHERE Channel.id = ? AND Channel.kind = 'group_DM'
+"#,
+		channel_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	// the viewing user is only needed to find the other side of 1:1 DMs
+	Ok(collect_direct_channels(
+		0,
+		rows.into_iter().map(|row| {
+			(
+				channel_id,
+				row.name,
+				ChannelKind::GroupDM,
+				row.owner_id,
+				User {
+					id: row.id,
+					username: row.username,
+					display_name: row.display_name,
+				},
+			)
+		}),
+	)
+	.pop())
+}
+
+fn recipient_ids(channel: &Channel) -> HashSet<u64> {
+	channel
+		.recipients
+		.iter()
+		.flatten()
+		.map(|recipient| recipient.id)
+		.collect()
+}
 
 pub async fn get_direct_channels(
 	identity: web::ReqData<Identity>,
@@ -19,34 +116,594 @@ pub async fn get_direct_channels(
 		return Ok(HttpResponse::Forbidden().finish());
 	};
 
-	let channels = query!(
-        r#"SELECT Channel.id,
+	let rows = query!(
+		r#"SELECT Channel.id, Channel.name, Channel.kind, Channel.owner_id,
 User.id AS user_id, User.username, User.display_name
 FROM DMChannelRecipient
-INNER JOIN Channel ON DMChannelRecipient.channel_id=Channel.id AND Channel.kind='DM'
-INNER JOIN DMChannelRecipient AS OtherRecipient ON Channel.id=OtherRecipient.channel_id AND OtherRecipient.user_id!=DMChannelRecipient.user_id
-INNER JOIN User ON OtherRecipient.user_id=User.id
+INNER JOIN Channel ON DMChannelRecipient.channel_id=Channel.id AND Channel.kind IN ('DM', 'group_DM')
+INNER JOIN DMChannelRecipient AS Recipient ON Channel.id=Recipient.channel_id
+INNER JOIN User ON Recipient.user_id=User.id
 WHERE DMChannelRecipient.user_id = ?
+ORDER BY Channel.id
 "#,
-        user_id
-    )
-    .fetch_all(&app_state.db)
-    .await?;
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	let mut read_states = get_read_states(&app_state.db, user_id, None).await?;
 
 	Ok(HttpResponse::Ok().json(
-		channels
-			.into_iter()
-			.map(|row| Channel {
-				id: row.id,
-				name: "".to_string(),
-				kind: ChannelKind::DM,
-				server_id: None,
-				user: Some(User {
-					id: row.user_id,
-					username: row.username,
-					display_name: row.display_name,
-				}),
-			})
-			.collect::<Vec<_>>(),
+		collect_direct_channels(
+			user_id,
+			rows.into_iter().map(|row| {
+				(
+					row.id,
+					row.name,
+					row.kind.parse().unwrap(),
+					row.owner_id,
+					User {
+						id: row.user_id,
+						username: row.username,
+						display_name: row.display_name,
+					},
+				)
+			}),
+		)
+		.into_iter()
+		.map(|channel| Channel {
+			read_state: read_states.remove(&channel.id),
+			..channel
+		})
+		.collect::<Vec<_>>(),
 	))
 }
+
+#[serde_as]
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateGroupDMBody {
+	#[serde(default, deserialize_with = "super::trim_opt_string")]
+	#[validate(length(min = 1, max = 32))]
+	name: Option<String>,
+	// the creator is added on top of these
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "Vec<PickFirst<(_, DisplayFromStr)>>")]
+	#[validate(length(min = 1, max = 9))]
+	recipients: Vec<u64>,
+}
+
+pub async fn create_group_dm(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	body: web::Json<CreateGroupDMBody>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let recipients = body
+		.recipients
+		.iter()
+		.copied()
+		.filter(|id| *id != user_id)
+		.collect::<HashSet<_>>();
+
+	if recipients.is_empty() {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Group DMs need at least one other recipient".to_string(),
+		}));
+	}
+
+	for recipient_id in &recipients {
+		if !are_friends(&app_state.db, user_id, *recipient_id).await? {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "Only friends can be added to group DMs".to_string(),
+			}));
+		}
+	}
+
+	let channel_id = generator.lock().unwrap().generate();
+
+	let mut tx = app_state.db.begin().await?;
+
+	query!(
+		"INSERT INTO Channel (id, name, kind, server_id, owner_id) VALUES (?, ?, 'group_DM', NULL, ?)",
+		channel_id,
+		body.name.clone().unwrap_or_default(),
+		user_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	for recipient_id in recipients.iter().chain([&user_id]) {
+		query!(
+			"INSERT INTO DMChannelRecipient (channel_id, user_id) VALUES (?, ?)",
+			channel_id,
+			recipient_id
+		)
+		.execute(&mut *tx)
+		.await?;
+	}
+
+	let Some(channel) = get_group_dm(&mut *tx, channel_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	tx.commit().await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelCreate(channel.clone())],
+		&app_state,
+		recipient_ids(&channel),
+	);
+
+	Ok(HttpResponse::Created().json(channel))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateGroupDMBody {
+	// an empty name clears it
+	#[serde(deserialize_with = "super::trim_string")]
+	#[validate(length(max = 32))]
+	name: String,
+}
+
+pub async fn update_group_dm(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	body: web::Json<UpdateGroupDMBody>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let channel_id = path.into_inner();
+
+	let Some(channel) = get_group_dm(&app_state.db, channel_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	let recipients = recipient_ids(&channel);
+
+	if !recipients.contains(&user_id) {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	query!(
+		"UPDATE Channel SET name = ? WHERE id = ? AND kind = 'group_DM'",
+		body.name,
+		channel_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelUpdate {
+			id: channel_id,
+			name: Some(body.name.clone()),
+			parent_id: None,
+			position: None,
+			owner_id: None,
+		}],
+		&app_state,
+		recipients,
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn add_recipient(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, target_id) = path.into_inner();
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that concurrent additions can't go over the recipient limit
+	if query!(
+		"SELECT id FROM Channel WHERE id = ? AND kind = 'group_DM' FOR UPDATE",
+		channel_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	.is_none()
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let Some(mut channel) = get_group_dm(&mut *tx, channel_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	let recipients = recipient_ids(&channel);
+
+	if !recipients.contains(&user_id) {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	if recipients.contains(&target_id) {
+		return Ok(HttpResponse::Ok().finish());
+	}
+
+	if recipients.len() >= MAX_GROUP_DM_RECIPIENTS {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "recipient_limit_reached".to_string(),
+		}));
+	}
+
+	if !are_friends(&app_state.db, user_id, target_id).await? {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Only friends can be added to group DMs".to_string(),
+		}));
+	}
+
+	let Some(target) = query!(
+		"SELECT username, display_name FROM User WHERE id = ?",
+		target_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	query!(
+		"INSERT INTO DMChannelRecipient (channel_id, user_id) VALUES (?, ?)",
+		channel_id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	seed_read_state(&mut *tx, target_id, channel_id).await?;
+
+	tx.commit().await?;
+
+	let target = User {
+		id: target_id,
+		username: target.username,
+		display_name: target.display_name,
+	};
+
+	send_updates(
+		[WsUpdateEvent::ChannelRecipientAdd {
+			channel_id,
+			user: target.clone(),
+		}],
+		&app_state,
+		recipients,
+	);
+
+	channel.recipients.get_or_insert_with(Vec::new).push(target);
+
+	send_updates(
+		[WsUpdateEvent::ChannelCreate(channel)],
+		&app_state,
+		[target_id],
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+async fn remove_group_dm_recipient(
+	app_state: &web::Data<AppState>,
+	channel: Channel,
+	target_id: u64,
+) -> ApiResult {
+	let recipients = recipient_ids(&channel);
+
+	if !recipients.contains(&target_id) {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let remaining = recipients
+		.iter()
+		.copied()
+		.filter(|id| *id != target_id)
+		.collect::<HashSet<_>>();
+
+	let mut tx = app_state.db.begin().await?;
+
+	query!(
+		"DELETE FROM DMChannelRecipient WHERE channel_id = ? AND user_id = ?",
+		channel.id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	// the channel is gone once nobody is left in it
+	if remaining.is_empty() {
+		query!(
+			"DELETE FROM Channel WHERE id = ? AND kind = 'group_DM'",
+			channel.id
+		)
+		.execute(&mut *tx)
+		.await?;
+
+		tx.commit().await?;
+
+		send_updates(
+			[WsUpdateEvent::ChannelDelete { id: channel.id }],
+			app_state,
+			[target_id],
+		);
+
+		return Ok(HttpResponse::Ok().finish());
+	}
+
+	// the ownership is passed on if the owner leaves
+	let new_owner_id = if !channel
+		.owner_id
+		.is_some_and(|owner_id| owner_id != target_id)
+	{
+		remaining.iter().min().copied()
+	} else {
+		None
+	};
+
+	if let Some(owner_id) = new_owner_id {
+		query!(
+			"UPDATE Channel SET owner_id = ? WHERE id = ?",
+			owner_id,
+			channel.id
+		)
+		.execute(&mut *tx)
+		.await?;
+	}
+
+	tx.commit().await?;
+
+	send_updates(
+		[WsUpdateEvent::ChannelRecipientRemove {
+			channel_id: channel.id,
+			user_id: target_id,
+		}],
+		app_state,
+		recipients,
+	);
+
+	// the removed recipient can't see the channel anymore
+	send_updates(
+		[WsUpdateEvent::ChannelDelete { id: channel.id }],
+		app_state,
+		[target_id],
+	);
+
+	if new_owner_id.is_some() {
+		send_updates(
+			[WsUpdateEvent::ChannelUpdate {
+				id: channel.id,
+				name: None,
+				parent_id: None,
+				position: None,
+				owner_id: new_owner_id,
+			}],
+			app_state,
+			remaining,
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn remove_recipient(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, target_id) = path.into_inner();
+
+	let Some(channel) = get_group_dm(&app_state.db, channel_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if !recipient_ids(&channel).contains(&user_id) {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	// anyone can leave, but only the owner can remove others
+	if target_id != user_id && channel.owner_id != Some(user_id) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	remove_group_dm_recipient(&app_state, channel, target_id).await
+}
+
+pub async fn leave_group_dm(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let channel_id = path.into_inner();
+
+	let Some(channel) = get_group_dm(&app_state.db, channel_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	remove_group_dm_recipient(&app_state, channel, user_id).await
+}
+
+// friends can message each other unless either has blocked the other, other users only if they share a server and the target allows it
+async fn can_open_direct_channel(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+	target_id: u64,
+) -> Result<bool, BackendError> {
+	if is_blocked_between(&app_state.db, user_id, target_id).await? {
+		return Ok(false);
+	}
+
+	if are_friends(&app_state.db, user_id, target_id).await? {
+		return Ok(true);
+	}
+
+	let shares_server = query!(
+		r#"SELECT EXISTS(
+    SELECT 1
+    FROM ServerMember
+    INNER JOIN ServerMember AS Target ON Target.server_id=ServerMember.server_id
+    WHERE ServerMember.user_id = ? AND Target.user_id = ?
+) AS `exists: bool`"#,
+		user_id,
+		target_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists;
+
+	Ok(shares_server
+		&& get_user_settings(&app_state.db, target_id)
+			.await?
+			.allow_server_member_dms)
+}
+
+pub async fn open_direct_channel(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Friends(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let target_id = path.into_inner();
+
+	if user_id == target_id {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Cannot open a DM with self".to_string(),
+		}));
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	// both users are locked so that concurrent requests can't each create a DM between them,
+	// in the same order from either side
+	query!(
+		"SELECT id FROM User WHERE id IN (?, ?) ORDER BY id FOR UPDATE",
+		user_id,
+		target_id
+	)
+	.fetch_all(&mut *tx)
+	.await?;
+
+	let Some(users) = query!(
+		"SELECT User.username, User.display_name, Target.username AS `target_username`, Target.display_name AS `target_display_name` FROM User, User AS Target WHERE User.id = ? AND Target.id = ?",
+		user_id,
+		target_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	let user = User {
+		id: user_id,
+		username: users.username,
+		display_name: users.display_name,
+	};
+
+	let target = User {
+		id: target_id,
+		username: users.target_username,
+		display_name: users.target_display_name,
+	};
+
+	let direct_channel = |id: u64, other_user: &User| Channel {
+		id,
+		name: "".to_string(),
+		kind: ChannelKind::DM,
+		server_id: None,
+		parent_id: None,
+		position: None,
+		user: Some(other_user.clone()),
+		recipients: Some(vec![user.clone(), target.clone()]),
+		owner_id: None,
+		thread: None,
+		read_state: None,
+	};
+
+	if let Some(channel) = query!(
+		r#"SELECT Channel.id
+FROM Channel
+INNER JOIN DMChannelRecipient AS UserRecipient ON UserRecipient.channel_id=Channel.id
+INNER JOIN DMChannelRecipient AS TargetRecipient ON TargetRecipient.channel_id=Channel.id
+WHERE UserRecipient.user_id = ? AND TargetRecipient.user_id = ? AND Channel.kind = 'DM'
+LIMIT 1
+"#,
+		user_id,
+		target_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	{
+		return Ok(HttpResponse::Ok().json(direct_channel(channel.id, &target)));
+	}
+
+	if !can_open_direct_channel(&app_state, user_id, target_id).await? {
+		return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+			error: "Cannot open a DM with this user".to_string(),
+		}));
+	}
+
+	let channel_id = generator.lock().unwrap().generate();
+
+	query!(
+		"INSERT INTO Channel (id, name, kind, server_id) VALUES (?, '', 'DM', NULL)",
+		channel_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	query!(
+		"INSERT INTO DMChannelRecipient (channel_id, user_id) VALUES (?, ?), (?, ?)",
+		channel_id,
+		user_id,
+		channel_id,
+		target_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	let channel = direct_channel(channel_id, &target);
+
+	// each side sees the other one as the channel's user
+	send_updates(
+		[WsUpdateEvent::ChannelCreate(channel.clone())],
+		&app_state,
+		[user_id],
+	);
+	send_updates(
+		[WsUpdateEvent::ChannelCreate(direct_channel(
+			channel_id, &user,
+		))],
+		&app_state,
+		[target_id],
+	);
+
+	Ok(HttpResponse::Created().json(channel))
+}
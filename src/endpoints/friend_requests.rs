@@ -6,6 +6,7 @@ use crate::{
 	error::{ApiResult, ErrorResponse},
 	middleware::Identity,
 	models::{
+		block::is_blocked_between,
 		channel::{Channel, ChannelKind},
 		friend::UserFriend,
 		friendrequest::UserFriendRequest,
@@ -124,6 +125,12 @@ pub async fn create_friend_request(
         return Ok(HttpResponse::NotFound().finish());
     };
 
+	if is_blocked_between(&app_state.db, user_id, target_id).await? {
+		return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+			error: "Cannot send friend request to this user".to_string(),
+		}));
+	}
+
 	if query!(
 		r#"
 SELECT EXISTS(
@@ -286,7 +293,7 @@ WHERE (UserFriendRequest.sender_id, UserFriendRequest.receiver_id) = (?, ?) OR (
 FROM Channel
 INNER JOIN DMChannelRecipient AS Sender ON Sender.channel_id=Channel.id
 INNER JOIN DMChannelRecipient AS Receiver ON Receiver.channel_id=Channel.id
-WHERE (Sender.user_id, Receiver.user_id) = (?, ?) OR (Sender.user_id, Receiver.user_id) = (?, ?) AND Channel.kind = 'DM'
+WHERE ((Sender.user_id, Receiver.user_id) = (?, ?) OR (Sender.user_id, Receiver.user_id) = (?, ?)) AND Channel.kind = 'DM'
 LIMIT 1
 "#,
         row.sender_id,
@@ -319,7 +326,13 @@ LIMIT 1
 			name: "".to_string(),
 			kind: ChannelKind::DM,
 			server_id: None,
+			parent_id: None,
+			position: None,
 			user: Some(other_user),
+			recipients: Some(vec![user.clone(), friend.clone()]),
+			owner_id: None,
+			thread: None,
+			read_state: None,
 		},
 		None => {
 			let channel_id: u64 = {
@@ -349,7 +362,13 @@ LIMIT 1
 				server_id: None,
 				kind: ChannelKind::DM,
 				name: "".to_string(),
+				parent_id: None,
+				position: None,
 				user: Some(other_user),
+				recipients: Some(vec![user.clone(), friend.clone()]),
+				owner_id: None,
+				thread: None,
+				read_state: None,
 			}
 		}
 	};
@@ -369,6 +388,7 @@ LIMIT 1
 						friend,
 						channel,
 						created_at,
+						presence: None,
 					}),
 				],
 				&app_state,
@@ -4,38 +4,65 @@ use crate::{
 	models::{
 		channel::{Channel, ChannelKind},
 		friend::UserFriend,
+		presence::Presence,
 		scope::{ReadWrite, Scope},
 		user::User,
 	},
-	ws::{send_updates, WsUpdateEvent},
+	ws::{is_connected, send_updates, WsUpdateEvent},
 	AppState,
 };
 use actix_web::{web, HttpResponse};
 use sqlx::query;
 
 macro_rules! user_friend_row {
-	($row:expr) => {{
+	($app_state:expr, $user_id:expr, $row:expr) => {{
 		let user = User {
 			id: $row.user_id,
 			username: $row.user_username,
 			display_name: $row.user_display_name,
 		};
 
+		// the friendship is stored once, so the current user can be on either side of it
+		let presence = if $row.user_id == $user_id {
+			Presence::visible(
+				$row.friend_id,
+				$row.friend_status.parse().unwrap(),
+				$row.friend_custom_status,
+				is_connected(&$app_state, $row.friend_id),
+			)
+		} else {
+			Presence::visible(
+				$row.user_id,
+				$row.user_status.parse().unwrap(),
+				$row.user_custom_status,
+				is_connected(&$app_state, $row.user_id),
+			)
+		};
+
+		let friend = User {
+			id: $row.friend_id,
+			username: $row.friend_username,
+			display_name: $row.friend_display_name,
+		};
+
 		UserFriend {
-			friend: User {
-				id: $row.friend_id,
-				username: $row.friend_username,
-				display_name: $row.friend_display_name,
-			},
+			friend: friend.clone(),
 			user: user.clone(),
 			channel: Channel {
 				id: $row.channel_id,
 				name: "".to_string(),
 				kind: ChannelKind::DM,
 				server_id: None,
+				parent_id: None,
+				position: None,
+				recipients: Some(vec![user.clone(), friend]),
 				user: Some(user),
+				owner_id: None,
+				thread: None,
+				read_state: None,
 			},
 			created_at: $row.created_at,
+			presence: Some(presence),
 		}
 	}};
 }
@@ -50,8 +77,8 @@ pub async fn get_friends(
 
 	let friends = query!(
 		r#"SELECT UserFriend.friend_id, UserFriend.user_id, UserFriend.created_at,
-Friend.username AS `friend_username`, Friend.display_name AS `friend_display_name`,
-User.username AS `user_username`, User.display_name AS `user_display_name`,
+Friend.username AS `friend_username`, Friend.display_name AS `friend_display_name`, Friend.status AS `friend_status`, Friend.custom_status AS `friend_custom_status`,
+User.username AS `user_username`, User.display_name AS `user_display_name`, User.status AS `user_status`, User.custom_status AS `user_custom_status`,
 Channel.id AS `channel_id`
 FROM UserFriend
 INNER JOIN User AS Friend ON UserFriend.friend_id=Friend.id
@@ -69,7 +96,7 @@ WHERE ? IN (UserFriend.user_id, UserFriend.friend_id) AND Channel.kind = 'DM'
 	Ok(HttpResponse::Ok().json(
 		friends
 			.into_iter()
-			.map(|row| user_friend_row!(row))
+			.map(|row| user_friend_row!(app_state, user_id, row))
 			.collect::<Vec<_>>(),
 	))
 }
@@ -87,8 +114,8 @@ pub async fn get_friend(
 
 	let Some(friend) = query!(
         r#"SELECT UserFriend.friend_id, UserFriend.user_id, UserFriend.created_at,
-Friend.username AS `friend_username`, Friend.display_name AS `friend_display_name`,
-User.username AS `user_username`, User.display_name AS `user_display_name`,
+Friend.username AS `friend_username`, Friend.display_name AS `friend_display_name`, Friend.status AS `friend_status`, Friend.custom_status AS `friend_custom_status`,
+User.username AS `user_username`, User.display_name AS `user_display_name`, User.status AS `user_status`, User.custom_status AS `user_custom_status`,
 Channel.id AS `channel_id`
 FROM UserFriend
 INNER JOIN User AS Friend ON UserFriend.friend_id=Friend.id
@@ -96,7 +123,7 @@ INNER JOIN User ON UserFriend.user_id=User.id
 INNER JOIN DMChannelRecipient AS UserRecipient ON UserRecipient.user_id=User.id
 INNER JOIN DMChannelRecipient AS FriendRecipient ON FriendRecipient.user_id=Friend.id
 INNER JOIN Channel ON UserRecipient.channel_id=Channel.id AND FriendRecipient.channel_id=Channel.id
-WHERE (UserFriend.user_id, UserFriend.friend_id) = (?, ?) OR (UserFriend.user_id, UserFriend.friend_id) = (?, ?) AND Channel.kind = 'DM'
+WHERE ((UserFriend.user_id, UserFriend.friend_id) = (?, ?) OR (UserFriend.user_id, UserFriend.friend_id) = (?, ?)) AND Channel.kind = 'DM'
 "#,
         user_id,
         friend_id,
@@ -108,7 +135,7 @@ WHERE (UserFriend.user_id, UserFriend.friend_id) = (?, ?) OR (UserFriend.user_id
         return Ok(HttpResponse::NotFound().finish());
     };
 
-	Ok(HttpResponse::Ok().json(user_friend_row!(friend)))
+	Ok(HttpResponse::Ok().json(user_friend_row!(app_state, user_id, friend)))
 }
 
 pub async fn delete_friend(
@@ -1,17 +1,27 @@
 use actix_web::{web, HttpResponse};
 use cuid2::CuidConstructor;
+use serde::Deserialize;
 use sqlx::query;
-use std::sync::LazyLock;
+use std::sync::{LazyLock, Mutex};
+use validator::Validate;
 
 use crate::{
+	endpoints::AuditLogReason,
 	error::{ApiResult, ErrorResponse},
 	middleware::Identity,
 	models::{
+		auditlog::{insert_audit_log_entry, AuditLogAction, AuditLogChange, AuditLogEntry},
 		channel::Channel,
 		invite::Invite,
+		permissions::{
+			apply_overwrites, get_server_overwrites, get_server_permissions, has_server_permission,
+			Permissions,
+		},
+		readstate::seed_read_states,
 		scope::{ReadWrite, Scope},
 		server::Server,
 		servermember::ServerMember,
+		snowflake_time,
 		user::User,
 	},
 	ws::{send_updates, WsUpdateEvent},
@@ -21,30 +31,67 @@ use crate::{
 static INVITE_GENERATOR: LazyLock<CuidConstructor> =
 	LazyLock::new(|| CuidConstructor::new().with_length(24));
 
+const DEFAULT_INVITE_MAX_AGE: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct CreateInviteBody {
+	// 0 for unlimited uses, which is the default
+	#[validate(range(max = 100))]
+	max_uses: Option<u32>,
+	// in seconds, 0 for an invite which never expires
+	#[validate(range(max = 2592000))]
+	max_age: Option<u64>,
+	#[serde(default)]
+	temporary: bool,
+}
+
 pub async fn create_invite(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	path: web::Path<u64>,
+	// the body is optional, without one the invite lasts 7 days with unlimited uses
+	body: web::Bytes,
+	reason: AuditLogReason,
 ) -> ApiResult {
+	// an empty body uses the defaults, but a malformed one is still rejected
+	let body = if body.is_empty() {
+		CreateInviteBody::default()
+	} else {
+		match serde_json::from_slice::<CreateInviteBody>(&body) {
+			Ok(body) => body,
+			Err(error) => {
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					error: error.to_string(),
+				}))
+			}
+		}
+	};
+	body.validate()?;
+
 	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
 		return Ok(HttpResponse::Forbidden().finish());
 	};
 
 	let server_id = path.into_inner();
 
-	let Some(server) = query!(
-		"SELECT name FROM Server WHERE id = ? AND owner_id = ?",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_INVITES,
 	)
-	.fetch_optional(&app_state.db)
 	.await?
-	else {
+	{
 		return Ok(HttpResponse::Forbidden().finish());
-	};
+	}
+
+	let server = query!("SELECT name, owner_id FROM Server WHERE id = ?", server_id)
+		.fetch_one(&app_state.db)
+		.await?;
 
 	if query!(
-        "SELECT COUNT(*) > 30 AS `over_limit: bool` FROM ServerInvite WHERE server_id = ? AND expires_at > NOW()",
+        "SELECT COUNT(*) > 30 AS `over_limit: bool` FROM ServerInvite WHERE server_id = ? AND (expires_at IS NULL OR expires_at > NOW()) AND (max_uses IS NULL OR uses < max_uses)",
         server_id
     )
     .fetch_one(&app_state.db)
@@ -58,26 +105,54 @@ pub async fn create_invite(
 
 	let invite_code = INVITE_GENERATOR.create_id();
 	let created_at = chrono::Utc::now();
-	let expires_at = created_at + chrono::Duration::days(7);
+	let expires_at = Some(body.max_age.unwrap_or(DEFAULT_INVITE_MAX_AGE))
+		.filter(|max_age| *max_age > 0)
+		.map(|max_age| created_at + chrono::Duration::seconds(max_age as i64));
+	let max_uses = body.max_uses.filter(|max_uses| *max_uses > 0);
+
+	let mut tx = app_state.db.begin().await?;
 
 	query!(
-		"INSERT INTO ServerInvite (id, created_at, expires_at, server_id) VALUES (?, ?, ?, ?)",
+		"INSERT INTO ServerInvite (id, created_at, expires_at, server_id, max_uses, uses, temporary) VALUES (?, ?, ?, ?, ?, 0, ?)",
 		invite_code,
 		created_at,
 		expires_at,
-		server_id
+		server_id,
+		max_uses,
+		body.temporary
 	)
-	.execute(&app_state.db)
+	.execute(&mut *tx)
 	.await?;
 
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::InviteCreate,
+		&invite_code,
+		reason.0,
+	)
+	.with_changes([
+		AuditLogChange::new("expires_at", None, expires_at),
+		AuditLogChange::new("max_uses", None, max_uses),
+		AuditLogChange::new("temporary", None, Some(body.temporary)),
+	]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
 	let invite = Invite {
 		id: invite_code.to_string(),
 		created_at,
 		expires_at,
+		max_uses,
+		uses: 0,
+		temporary: body.temporary,
 		server: Server {
 			id: server_id,
 			name: server.name,
-			owner_id: user_id,
+			owner_id: server.owner_id,
 		},
 	};
 
@@ -114,7 +189,7 @@ pub async fn get_invites(
 	};
 
 	let invites = query!(
-        "SELECT id, created_at, expires_at FROM ServerInvite WHERE server_id = ? AND expires_at > NOW()",
+        "SELECT id, created_at, expires_at, max_uses, uses, temporary AS `temporary: bool` FROM ServerInvite WHERE server_id = ? AND (expires_at IS NULL OR expires_at > NOW()) AND (max_uses IS NULL OR uses < max_uses)",
         server_id
     )
     .fetch_all(&app_state.db)
@@ -127,6 +202,9 @@ pub async fn get_invites(
 				id: row.id,
 				created_at: row.created_at,
 				expires_at: row.expires_at,
+				max_uses: row.max_uses,
+				uses: row.uses,
+				temporary: row.temporary,
 				server: Server {
 					id: server_id,
 					name: server.name.to_string(),
@@ -142,32 +220,69 @@ pub async fn get_invite(
 	app_state: web::Data<AppState>,
 	path: web::Path<String>,
 ) -> ApiResult {
-	if identity
-		.is_user_like_with_scope(Scope::Servers(ReadWrite::Read))
-		.is_none()
-	{
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
 		return Ok(HttpResponse::Forbidden().finish());
-	}
+	};
 
 	let invite_id = path.into_inner();
 
-	let Some(invite) = query!(
-        "SELECT ServerInvite.id, ServerInvite.created_at, ServerInvite.expires_at, Server.id AS `server_id`, Server.owner_id, Server.name FROM ServerInvite INNER JOIN Server ON Server.id=ServerInvite.server_id WHERE ServerInvite.id = ? AND ServerInvite.expires_at > NOW()",
-        invite_id
-    )
-    .fetch_optional(&app_state.db)
-    .await? else {
-        return Ok(HttpResponse::NotFound().finish());
-    };
+	// banned users can't see the server behind the invite
+	if let Some(invite) = query!(
+		r#"SELECT ServerInvite.id, ServerInvite.created_at, ServerInvite.expires_at, ServerInvite.max_uses, ServerInvite.uses, ServerInvite.temporary AS `temporary: bool`,
+Server.id AS `server_id`, Server.owner_id, Server.name
+FROM ServerInvite
+INNER JOIN Server ON Server.id=ServerInvite.server_id
+WHERE ServerInvite.id = ? AND (ServerInvite.expires_at IS NULL OR ServerInvite.expires_at > NOW()) AND (ServerInvite.max_uses IS NULL OR ServerInvite.uses < ServerInvite.max_uses)
+AND NOT EXISTS(SELECT 1 FROM ServerBan WHERE ServerBan.server_id=ServerInvite.server_id AND ServerBan.user_id = ?)
+"#,
+		invite_id,
+		user_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	{
+		return Ok(HttpResponse::Ok().json(Invite {
+			id: invite.id,
+			created_at: invite.created_at,
+			expires_at: invite.expires_at,
+			max_uses: invite.max_uses,
+			uses: invite.uses,
+			temporary: invite.temporary,
+			server: Server {
+				id: invite.server_id,
+				name: invite.name,
+				owner_id: invite.owner_id,
+			},
+		}));
+	}
+
+	// otherwise the code may be a server's vanity code
+	let Some(server) = query!(
+		r#"SELECT id, name, owner_id, vanity_code AS `vanity_code!`, vanity_uses
+FROM Server
+WHERE vanity_code = ? AND NOT EXISTS(SELECT 1 FROM ServerBan WHERE ServerBan.server_id=Server.id AND ServerBan.user_id = ?)
+"#,
+		invite_id,
+		user_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
 
 	Ok(HttpResponse::Ok().json(Invite {
-		id: invite.id,
-		created_at: invite.created_at,
-		expires_at: invite.expires_at,
+		id: server.vanity_code,
+		// when the vanity code was set isn't tracked, so the server's creation is used instead
+		created_at: snowflake_time(server.id),
+		expires_at: None,
+		max_uses: None,
+		uses: server.vanity_uses,
+		temporary: false,
 		server: Server {
-			id: invite.server_id,
-			name: invite.name,
-			owner_id: invite.owner_id,
+			id: server.id,
+			name: server.name,
+			owner_id: server.owner_id,
 		},
 	}))
 }
@@ -175,7 +290,9 @@ pub async fn get_invite(
 pub async fn delete_invite(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	path: web::Path<(u64, String)>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
 		return Ok(HttpResponse::Forbidden().finish());
@@ -183,29 +300,51 @@ pub async fn delete_invite(
 
 	let (server_id, invite_id) = path.into_inner();
 
-	if !query!(
-		"SELECT EXISTS(SELECT 1 FROM Server WHERE id = ? AND owner_id = ?) AS `exists: bool`",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_INVITES,
 	)
-	.fetch_one(&app_state.db)
 	.await?
-	.exists
 	{
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
-	let results = query!(
-		"DELETE FROM ServerInvite WHERE id = ? AND server_id = ? AND expires_at > NOW()",
+	let mut tx = app_state.db.begin().await?;
+
+	let Some(invite) = query!(
+		"SELECT expires_at FROM ServerInvite WHERE id = ? AND server_id = ? AND (expires_at IS NULL OR expires_at > NOW()) FOR UPDATE",
 		invite_id,
 		server_id
 	)
-	.execute(&app_state.db)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	query!(
+		"DELETE FROM ServerInvite WHERE id = ? AND server_id = ?",
+		invite_id,
+		server_id
+	)
+	.execute(&mut *tx)
 	.await?;
 
-	if results.rows_affected() == 0 {
-		return Ok(HttpResponse::NotFound().finish());
-	}
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::InviteDelete,
+		&invite_id,
+		reason.0,
+	)
+	.with_changes([AuditLogChange::new("expires_at", invite.expires_at, None)]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
 
 	if let Some(members) = app_state.server_connections.get(&server_id) {
 		send_updates(
@@ -229,16 +368,38 @@ pub async fn accept_invite(
 
 	let invite_id = path.into_inner();
 
-	let Some(invite) = query!(
-		"SELECT server_id, expires_at FROM ServerInvite WHERE id = ? AND expires_at > NOW()",
+	let invite = match query!(
+		"SELECT server_id, temporary AS `temporary: bool` FROM ServerInvite WHERE id = ? AND (expires_at IS NULL OR expires_at > NOW()) AND (max_uses IS NULL OR uses < max_uses)",
 		invite_id
 	)
 	.fetch_optional(&app_state.db)
 	.await?
-	else {
+	{
+		Some(invite) => Some((invite.server_id, invite.temporary, false)),
+		None => query!("SELECT id FROM Server WHERE vanity_code = ?", invite_id)
+			.fetch_optional(&app_state.db)
+			.await?
+			.map(|server| (server.id, false, true)),
+	};
+
+	let Some((server_id, temporary, vanity)) = invite else {
 		return Ok(HttpResponse::NotFound().finish());
 	};
 
+	if query!(
+		"SELECT EXISTS(SELECT 1 FROM ServerBan WHERE server_id = ? AND user_id = ?) AS `banned: bool`",
+		server_id,
+		user_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.banned
+	{
+		return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+			error: "You are banned from this server".to_string(),
+		}));
+	}
+
 	let user = query!(
 		"SELECT username, display_name FROM User WHERE id = ?",
 		user_id
@@ -248,85 +409,140 @@ pub async fn accept_invite(
 
 	let created_at = chrono::Utc::now();
 
-	let result = query!(
-        "INSERT INTO ServerMember (server_id, user_id, created_at, nickname) VALUES (?, ?, ?, NULL)",
-        invite.server_id,
-        user_id,
-        created_at
-    )
-    .execute(&app_state.db)
-    .await;
-
-	match result {
-		Ok(_) => {
-			if app_state
-				.user_connections
-				.get(&user_id)
-				.is_some_and(|conns| !conns.is_empty())
-			{
-				app_state
-					.server_connections
-					.entry(invite.server_id)
-					.or_default()
-					.insert(user_id);
-
-				let records = query!("SELECT Server.name, Server.owner_id, Channel.id, Channel.name AS `channel_name`, Channel.kind FROM Server LEFT JOIN Channel ON Server.id=Channel.server_id WHERE Server.id = ?", invite.server_id)
-                     .fetch_all(&app_state.db)
-                     .await?;
-
-				send_updates(
-					std::iter::once(WsUpdateEvent::ServerCreate(Server {
-						id: invite.server_id,
-						name: records[0].name.clone(),
-						owner_id: records[0].owner_id,
-					}))
-					.chain(records.into_iter().filter_map(|row| {
-						match (row.id, row.channel_name, row.kind) {
-							(Some(id), Some(name), Some(kind)) => {
-								Some(WsUpdateEvent::ChannelCreate(Channel {
-									id,
-									name,
-									server_id: Some(invite.server_id),
-									kind: kind.parse().unwrap(),
-									user: None,
-								}))
-							}
-							_ => None,
-						}
-					})),
-					&app_state,
-					[user_id],
-				);
-			}
-
-			if let Some(members) = app_state.server_connections.get(&invite.server_id) {
-				send_updates(
-					[WsUpdateEvent::MemberCreate(ServerMember {
-						server_id: invite.server_id,
-						user_id,
-						nickname: None,
-						created_at,
-						user: Some(User {
-							id: user_id,
-							username: user.username,
-							display_name: user.display_name,
-						}),
-					})],
-					&app_state,
-					members.iter().copied(),
-				);
-			}
+	let mut tx = app_state.db.begin().await?;
 
-			Ok(HttpResponse::Ok().finish())
-		}
+	match query!(
+		"INSERT INTO ServerMember (server_id, user_id, created_at, nickname, invite_id, temporary) VALUES (?, ?, ?, NULL, ?, ?)",
+		server_id,
+		user_id,
+		created_at,
+		invite_id,
+		temporary
+	)
+	.execute(&mut *tx)
+	.await
+	{
 		Err(e)
 			if e.as_database_error()
 				.is_some_and(|e| e.is_unique_violation()) =>
 		{
-			Ok(HttpResponse::Conflict().json(ErrorResponse {
+			return Ok(HttpResponse::Conflict().json(ErrorResponse {
 				error: "You are already a member of this server".to_string(),
-			}))
+			}));
 		}
-		Err(err) => Err(err.into()),
+		r => r?,
+	};
+
+	// the use is counted in the same transaction, so concurrent joins can't exceed the limit
+	let counted = if vanity {
+		query!(
+			"UPDATE Server SET vanity_uses = vanity_uses + 1 WHERE id = ?",
+			server_id
+		)
+		.execute(&mut *tx)
+		.await?
+	} else {
+		query!(
+			"UPDATE ServerInvite SET uses = uses + 1 WHERE id = ? AND (expires_at IS NULL OR expires_at > NOW()) AND (max_uses IS NULL OR uses < max_uses)",
+			invite_id
+		)
+		.execute(&mut *tx)
+		.await?
+	}
+	.rows_affected()
+		> 0;
+
+	if !counted {
+		return Ok(HttpResponse::NotFound().finish());
 	}
+
+	seed_read_states(&mut *tx, user_id, server_id).await?;
+
+	tx.commit().await?;
+
+	if app_state
+		.user_connections
+		.get(&user_id)
+		.is_some_and(|conns| !conns.is_empty())
+	{
+		app_state
+			.server_connections
+			.entry(server_id)
+			.or_default()
+			.insert(user_id);
+
+		let records = query!("SELECT Server.name, Server.owner_id, Channel.id, Channel.name AS `channel_name`, Channel.kind, Channel.parent_id, Channel.position FROM Server LEFT JOIN Channel ON Server.id=Channel.server_id AND Channel.kind != 'thread' WHERE Server.id = ? ORDER BY Channel.position, Channel.id", server_id)
+                     .fetch_all(&app_state.db)
+                     .await?;
+
+		// new members only have the @everyone role
+		let permissions = get_server_permissions(&app_state.db, server_id, user_id)
+			.await?
+			.unwrap_or_default();
+		let overwrites = get_server_overwrites(&app_state.db, server_id).await?;
+
+		send_updates(
+			std::iter::once(WsUpdateEvent::ServerCreate(Server {
+				id: server_id,
+				name: records[0].name.clone(),
+				owner_id: records[0].owner_id,
+			}))
+			.chain(records.into_iter().filter_map(|row| {
+				match (row.id, row.channel_name, row.kind) {
+					(Some(id), Some(name), Some(kind))
+						if apply_overwrites(
+							permissions,
+							server_id,
+							user_id,
+							&[],
+							overwrites.get(&id).map(Vec::as_slice).unwrap_or_default(),
+						)
+						.contains(Permissions::VIEW_CHANNEL) =>
+					{
+						Some(WsUpdateEvent::ChannelCreate(Channel {
+							id,
+							name,
+							server_id: Some(server_id),
+							kind: kind.parse().unwrap(),
+							parent_id: row.parent_id,
+							position: row.position,
+							user: None,
+							recipients: None,
+							owner_id: None,
+							thread: None,
+							read_state: None,
+						}))
+					}
+					_ => None,
+				}
+			})),
+			&app_state,
+			[user_id],
+		);
+	}
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::MemberCreate(ServerMember {
+				server_id,
+				user_id,
+				nickname: None,
+				created_at,
+				user: Some(User {
+					id: user_id,
+					username: user.username,
+					display_name: user.display_name,
+				}),
+				roles: Some(vec![]),
+				timed_out_until: None,
+				temporary,
+				invite_id: None,
+				presence: None,
+			})],
+			&app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
 }
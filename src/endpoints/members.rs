@@ -1,18 +1,25 @@
 use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use dashmap::mapref::entry::Entry;
 use serde::Deserialize;
 use sqlx::query;
+use std::{collections::HashMap, sync::Mutex};
 use validator::Validate;
 
 use crate::{
-	error::ApiResult,
+	endpoints::AuditLogReason,
+	error::{ApiResult, BackendError},
 	middleware::Identity,
 	models::{
+		auditlog::{insert_audit_log_entry, AuditLogAction, AuditLogChange, AuditLogEntry},
+		permissions::{get_server_permissions, has_server_permission, Permissions},
+		presence::Presence,
 		scope::{ReadWrite, Scope},
 		servermember::ServerMember,
 		user::User,
 	},
 	update_structure,
-	ws::{send_updates, WsUpdateEvent},
+	ws::{is_connected, send_updates, WsUpdateEvent},
 	AppState,
 };
 
@@ -24,19 +31,31 @@ pub struct GetMembersQuery {
 }
 
 macro_rules! member_row {
-	($server_id:expr, $row:expr) => {{
+	($app_state:expr, $server_id:expr, $row:expr, $roles:expr, $can_manage_invites:expr) => {{
 		let user = User {
 			id: $row.user_id,
 			username: $row.username,
 			display_name: $row.display_name,
 		};
 
+		let presence = Presence::visible(
+			$row.user_id,
+			$row.status.parse().unwrap(),
+			$row.custom_status,
+			is_connected(&$app_state, $row.user_id),
+		);
+
 		ServerMember {
 			user_id: $row.user_id,
 			server_id: $server_id,
 			nickname: $row.nickname,
 			created_at: $row.created_at,
 			user: Some(user),
+			roles: Some($roles),
+			timed_out_until: $row.timed_out_until.filter(|until| *until > Utc::now()),
+			temporary: $row.temporary,
+			invite_id: $row.invite_id.filter(|_| $can_manage_invites),
+			presence: Some(presence),
 		}
 	}};
 }
@@ -55,24 +74,16 @@ pub async fn get_members(
 
 	let server_id = path.into_inner();
 
-	if !query!(
-        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
-        server_id,
-        user_id
-    )
-    .fetch_one(&app_state.db)
-    .await?
-        .exists
-    {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
 
 	let limit = query.limit.unwrap_or(50);
 	let last_id = query.last_id.unwrap_or(u64::MAX);
 
 	let mut members = query!(
-		r#"SELECT User.username, User.display_name,
-ServerMember.nickname, ServerMember.created_at, ServerMember.user_id
+		r#"SELECT User.username, User.display_name, User.status, User.custom_status,
+ServerMember.nickname, ServerMember.created_at, ServerMember.timed_out_until, ServerMember.temporary AS `temporary: bool`, ServerMember.invite_id, ServerMember.user_id
 FROM ServerMember
 INNER JOIN User ON User.id=ServerMember.user_id
 WHERE ServerMember.server_id = ? AND ServerMember.user_id < ?
@@ -88,10 +99,36 @@ LIMIT ?
 
 	members.reverse();
 
+	let mut roles = HashMap::<u64, Vec<u64>>::new();
+
+	if let (Some(first), Some(last)) = (members.first(), members.last()) {
+		let rows = query!(
+			"SELECT user_id, role_id FROM ServerMemberRole WHERE server_id = ? AND user_id BETWEEN ? AND ?",
+			server_id,
+			first.user_id,
+			last.user_id
+		)
+		.fetch_all(&app_state.db)
+		.await?;
+
+		for row in rows {
+			roles.entry(row.user_id).or_default().push(row.role_id);
+		}
+	}
+
 	Ok(HttpResponse::Ok().json(
 		members
 			.into_iter()
-			.map(|row| member_row!(server_id, row))
+			.map(|row| {
+				let member_roles = roles.remove(&row.user_id).unwrap_or_default();
+				member_row!(
+					app_state,
+					server_id,
+					row,
+					member_roles,
+					permissions.contains(Permissions::MANAGE_INVITES)
+				)
+			})
 			.collect::<Vec<_>>(),
 	))
 }
@@ -107,21 +144,13 @@ pub async fn get_member(
 
 	let (server_id, member_id) = path.into_inner();
 
-	if !query!(
-        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
-        server_id,
-        user_id
-    )
-        .fetch_one(&app_state.db)
-        .await?
-        .exists
-    {
-        return Ok(HttpResponse::Forbidden().finish());
-    }
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
 
 	let Some(member) = query!(
-		r#"SELECT User.username, User.display_name,
-ServerMember.nickname, ServerMember.created_at, ServerMember.user_id
+		r#"SELECT User.username, User.display_name, User.status, User.custom_status,
+ServerMember.nickname, ServerMember.created_at, ServerMember.timed_out_until, ServerMember.temporary AS `temporary: bool`, ServerMember.invite_id, ServerMember.user_id
 FROM ServerMember
 INNER JOIN User ON User.id=ServerMember.user_id
 WHERE ServerMember.server_id = ? AND ServerMember.user_id = ?
@@ -135,7 +164,24 @@ WHERE ServerMember.server_id = ? AND ServerMember.user_id = ?
 		return Ok(HttpResponse::NotFound().finish());
 	};
 
-	Ok(HttpResponse::Ok().json(member_row!(server_id, member)))
+	let roles = query!(
+		"SELECT role_id FROM ServerMemberRole WHERE server_id = ? AND user_id = ?",
+		server_id,
+		member_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| row.role_id)
+	.collect();
+
+	Ok(HttpResponse::Ok().json(member_row!(
+		app_state,
+		server_id,
+		member,
+		roles,
+		permissions.contains(Permissions::MANAGE_INVITES)
+	)))
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -148,8 +194,10 @@ pub struct UpdateMemberBody {
 pub async fn update_member(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	path: web::Path<(u64, u64)>,
 	body: web::Json<UpdateMemberBody>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	body.validate()?;
 
@@ -159,40 +207,72 @@ pub async fn update_member(
 
 	let (server_id, member_id) = path.into_inner();
 
-	let Some(server) = query!(
-        "SELECT Server.owner_id FROM Server INNER JOIN ServerMember ON ServerMember.server_id=Server.id AND ServerMember.user_id=? WHERE Server.id = ?",
-        member_id,
-        server_id
-    )
-    .fetch_optional(&app_state.db)
-    .await? else {
-        return Ok(HttpResponse::NotFound().finish());
-    };
-
-	// server owners can update any member, but other members can only update themselves
-	if server.owner_id != user_id && member_id != user_id {
+	// members can always update themselves, but updating others requires a permission
+	if member_id != user_id
+		&& !has_server_permission(
+			&app_state.db,
+			server_id,
+			user_id,
+			Permissions::MANAGE_NICKNAMES,
+		)
+		.await?
+	{
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let Some(member) = query!(
+		"SELECT nickname FROM ServerMember WHERE server_id = ? AND user_id = ? FOR UPDATE",
+		server_id,
+		member_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
 	let query = update_structure!("ServerMember", body, nickname)
 		.push(" WHERE server_id = ")
 		.push_bind(server_id)
 		.push(" AND user_id = ")
 		.push_bind(member_id)
 		.build()
-		.execute(&app_state.db)
+		.execute(&mut *tx)
 		.await?;
 
 	if query.rows_affected() == 0 {
 		return Ok(HttpResponse::NotFound().finish());
 	}
 
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::MemberUpdate,
+		member_id,
+		reason.0,
+	)
+	.with_changes(
+		body.nickname
+			.as_ref()
+			.map(|nickname| AuditLogChange::new("nickname", &member.nickname, nickname)),
+	);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
 	if let Some(members) = app_state.server_connections.get(&server_id) {
 		send_updates(
 			[WsUpdateEvent::MemberUpdate {
 				server_id,
 				user_id: member_id,
 				nickname: body.nickname.clone(),
+				roles: None,
+				timed_out_until: None,
 			}],
 			&app_state,
 			members.iter().copied(),
@@ -201,3 +281,252 @@ pub async fn update_member(
 
 	Ok(HttpResponse::Ok().finish())
 }
+
+// tells the removed user that the server is gone, and the remaining members that they left
+pub fn send_member_removal(app_state: &web::Data<AppState>, server_id: u64, user_id: u64) {
+	send_updates(
+		[WsUpdateEvent::ServerDelete { id: server_id }],
+		app_state,
+		[user_id],
+	);
+
+	if let Entry::Occupied(mut members) = app_state.server_connections.entry(server_id) {
+		send_updates(
+			[WsUpdateEvent::MemberDelete { server_id, user_id }],
+			app_state,
+			members.get().iter().copied(),
+		);
+
+		if members.get().len() == 1 {
+			members.remove_entry();
+		} else {
+			members.get_mut().remove(&user_id);
+		}
+	}
+}
+
+// the owner can't be moderated, administrators only by the owner, and nobody can moderate themselves
+pub async fn can_moderate(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	user_id: u64,
+	target_id: u64,
+	permission: Permissions,
+) -> Result<bool, BackendError> {
+	if user_id == target_id
+		|| !has_server_permission(&app_state.db, server_id, user_id, permission).await?
+	{
+		return Ok(false);
+	}
+
+	let Some(server) = query!("SELECT owner_id FROM Server WHERE id = ?", server_id)
+		.fetch_optional(&app_state.db)
+		.await?
+	else {
+		return Ok(false);
+	};
+
+	if server.owner_id == target_id {
+		return Ok(false);
+	}
+
+	// users who aren't members (anymore) have no permissions to protect them
+	Ok(server.owner_id == user_id
+		|| !get_server_permissions(&app_state.db, server_id, target_id)
+			.await?
+			.is_some_and(|permissions| permissions.contains(Permissions::ADMINISTRATOR)))
+}
+
+pub async fn kick_member(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64)>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, member_id) = path.into_inner();
+
+	if !can_moderate(
+		&app_state,
+		server_id,
+		user_id,
+		member_id,
+		Permissions::KICK_MEMBERS,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	let query = query!(
+		"DELETE FROM ServerMember WHERE server_id = ? AND user_id = ?",
+		server_id,
+		member_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	if query.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::MemberKick,
+		member_id,
+		reason.0,
+	);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	send_member_removal(&app_state, server_id, member_id);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TimeoutMemberBody {
+	// at most 28 days
+	#[validate(range(min = 1, max = 2419200))]
+	duration: i64,
+}
+
+pub async fn timeout_member(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64)>,
+	body: web::Json<TimeoutMemberBody>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, member_id) = path.into_inner();
+
+	set_member_timeout(
+		&app_state,
+		&generator,
+		server_id,
+		user_id,
+		member_id,
+		Some(Utc::now() + chrono::Duration::seconds(body.duration)),
+		reason,
+	)
+	.await
+}
+
+pub async fn remove_member_timeout(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	path: web::Path<(u64, u64)>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, member_id) = path.into_inner();
+
+	set_member_timeout(
+		&app_state, &generator, server_id, user_id, member_id, None, reason,
+	)
+	.await
+}
+
+async fn set_member_timeout(
+	app_state: &web::Data<AppState>,
+	generator: &Mutex<snowflaked::Generator>,
+	server_id: u64,
+	user_id: u64,
+	member_id: u64,
+	timed_out_until: Option<chrono::DateTime<Utc>>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	if !can_moderate(
+		app_state,
+		server_id,
+		user_id,
+		member_id,
+		Permissions::MODERATE_MEMBERS,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let Some(member) = query!(
+		"SELECT timed_out_until FROM ServerMember WHERE server_id = ? AND user_id = ? FOR UPDATE",
+		server_id,
+		member_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	let query = query!(
+		"UPDATE ServerMember SET timed_out_until = ? WHERE server_id = ? AND user_id = ?",
+		timed_out_until,
+		server_id,
+		member_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	if query.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let entry = AuditLogEntry::new(
+		generator,
+		server_id,
+		user_id,
+		AuditLogAction::MemberTimeout,
+		member_id,
+		reason.0,
+	)
+	.with_changes([AuditLogChange::new(
+		"timed_out_until",
+		member.timed_out_until.filter(|until| *until > Utc::now()),
+		timed_out_until,
+	)]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::MemberUpdate {
+				server_id,
+				user_id: member_id,
+				nickname: None,
+				roles: None,
+				timed_out_until: Some(timed_out_until),
+			}],
+			app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
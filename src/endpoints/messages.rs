@@ -1,55 +1,227 @@
-use std::{collections::HashSet, sync::Mutex};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::Mutex,
+};
 
-use actix_web::{web, HttpResponse};
+use actix_multipart::form::{
+	bytes::Bytes as MultipartBytes, json::Json as MultipartJson, MultipartForm,
+};
+use actix_web::{web, Either, HttpResponse};
 use chrono::{DateTime, Utc};
+use futures::future::try_join_all;
 use serde::Deserialize;
-use sqlx::{query, query_as};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use sqlx::{query, query_as, FromRow, MySql, QueryBuilder};
 use validator::Validate;
 
 use crate::{
-	error::ApiResult,
+	error::{ApiResult, BackendError, ErrorResponse},
 	middleware::Identity,
 	models::{
-		message::{Message, MessageKind},
+		attachment::{
+			attachment_key, normalize_content_type, sanitize_filename, Attachment,
+			DOWNLOAD_CONTENT_TYPE,
+		},
+		block::is_blocked_between,
+		message::{
+			search_boolean_query, search_highlights, search_terms, Message, MessageKind,
+			MessageSearchHit, MessageSearchResults, ReferencedMessage,
+		},
+		permissions::{
+			apply_overwrites, get_channel_permissions, get_member_permissions,
+			get_server_overwrites, has_channel_permission, Permissions,
+		},
+		reaction::Reaction,
+		readstate::get_read_state,
 		scope::{ReadWrite, Scope},
 		servermember::ServerMember,
 		user::User,
 	},
+	storage::delete_in_background,
 	update_structure,
-	ws::{send_updates, WsUpdateEvent},
+	ws::{channel_connections, send_updates, WsUpdateEvent},
 	AppState,
 };
 
-#[derive(Debug, Deserialize, Validate)]
+pub const MAX_ATTACHMENTS: usize = 10;
+// 25 MiB for the files, plus some room for the JSON payload and the multipart boundaries
+pub const MAX_PAYLOAD_SIZE: usize = 25 * 1024 * 1024 + 64 * 1024;
+
+// server channels notify the connected members who can view them, direct messages their recipients
+pub async fn message_recipients(
+	app_state: &web::Data<AppState>,
+	server_id: Option<u64>,
+	channel_id: u64,
+	recipients: HashSet<u64>,
+) -> Result<HashSet<u64>, BackendError> {
+	let Some(server_id) = server_id else {
+		return Ok(recipients);
+	};
+
+	channel_connections(app_state, server_id, channel_id).await
+}
+
+// a channel the user can view, along with what they may do in it
+pub struct ChannelAccess {
+	channel_id: u64,
+	pub server_id: Option<u64>,
+	// direct messages have no permissions besides being a recipient
+	pub permissions: Permissions,
+	recipients: HashSet<u64>,
+	// archived threads are read-only
+	pub archived: bool,
+}
+
+impl ChannelAccess {
+	// the users to send the channel's events to
+	pub async fn recipients(
+		self,
+		app_state: &web::Data<AppState>,
+	) -> Result<HashSet<u64>, BackendError> {
+		message_recipients(app_state, self.server_id, self.channel_id, self.recipients).await
+	}
+}
+
+// returns None if the user can't view the channel
+// categories only group other channels, so they're never accessible here
+pub async fn get_channel_access(
+	app_state: &web::Data<AppState>,
+	channel_id: u64,
+	user_id: u64,
+) -> Result<Option<ChannelAccess>, BackendError> {
+	let rows = query!(
+		r#"SELECT ServerMember.server_id, DMChannelRecipient.user_id, ChannelThread.archived AS `archived?: bool`
+FROM Channel
+LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
+LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
+LEFT JOIN ChannelThread ON ChannelThread.channel_id=Channel.id
+WHERE Channel.id = ? AND Channel.kind != 'category'
+"#,
+		user_id,
+		channel_id,
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	let Some(channel_row) = rows.first() else {
+		return Ok(None);
+	};
+
+	let recipients = rows
+		.iter()
+		.filter_map(|row| row.user_id)
+		.collect::<HashSet<_>>();
+
+	let permissions = match channel_row.server_id {
+		Some(server_id) => {
+			let permissions =
+				get_channel_permissions(&app_state.db, server_id, channel_id, user_id)
+					.await?
+					.unwrap_or_default();
+
+			if !permissions.contains(Permissions::VIEW_CHANNEL) {
+				return Ok(None);
+			}
+
+			permissions
+		}
+		None if recipients.contains(&user_id) => Permissions::empty(),
+		None => return Ok(None),
+	};
+
+	Ok(Some(ChannelAccess {
+		channel_id,
+		server_id: channel_row.server_id,
+		permissions,
+		recipients,
+		archived: channel_row.archived == Some(true),
+	}))
+}
+
+// returns the users to send the channel's events to, or None if the user can't access the channel with the given permissions
+pub async fn channel_recipients(
+	app_state: &web::Data<AppState>,
+	channel_id: u64,
+	user_id: u64,
+	permissions: Permissions,
+) -> Result<Option<HashSet<u64>>, BackendError> {
+	let Some(access) = get_channel_access(app_state, channel_id, user_id).await? else {
+		return Ok(None);
+	};
+
+	if access.server_id.is_some() && !access.permissions.contains(permissions) {
+		return Ok(None);
+	}
+
+	Ok(Some(access.recipients(app_state).await?))
+}
+
+#[serde_as]
+#[derive(Debug, Default, Deserialize, Validate)]
 pub struct CreateMessageBody {
-	#[serde(deserialize_with = "super::trim_string")]
-	#[validate(length(min = 1, max = 3500))]
+	// may only be empty if the message has attachments
+	#[serde(default, deserialize_with = "super::trim_string")]
+	#[validate(length(max = 3500))]
 	content: String,
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+	reply_to: Option<u64>,
+}
+
+#[derive(Debug, MultipartForm)]
+pub struct CreateMessageForm {
+	#[multipart(limit = "64KiB")]
+	payload_json: Option<MultipartJson<CreateMessageBody>>,
+	#[multipart(limit = "25MiB")]
+	files: Vec<MultipartBytes>,
 }
 
 pub async fn create_message(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
 	generator: web::Data<Mutex<snowflaked::Generator>>,
-	body: web::Json<CreateMessageBody>,
+	body: Either<web::Json<CreateMessageBody>, MultipartForm<CreateMessageForm>>,
 	path: web::Path<u64>,
 ) -> ApiResult {
+	let (body, files) = match body {
+		Either::Left(body) => (body.into_inner(), vec![]),
+		Either::Right(MultipartForm(form)) => (
+			form.payload_json
+				.map(|body| body.into_inner())
+				.unwrap_or_default(),
+			form.files,
+		),
+	};
+
 	body.validate()?;
 
+	if files.len() > MAX_ATTACHMENTS {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "attachment_limit_reached".to_string(),
+		}));
+	}
+
+	if body.content.is_empty() && files.is_empty() {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "empty_message".to_string(),
+		}));
+	}
+
 	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Write)) else {
 		return Ok(HttpResponse::Forbidden().finish());
 	};
 
 	let channel_id = path.into_inner();
 
-	let (recipients, member) = {
+	let (recipients, member, is_thread) = {
 		let rows = query!(
-            r#"SELECT ServerMember.server_id, ServerMember.nickname, ServerMember.created_at AS `created_at: DateTime<Utc>`,
-DMChannelRecipient.user_id
+            r#"SELECT ServerMember.server_id, ServerMember.nickname, ServerMember.created_at AS `created_at: DateTime<Utc>`, ServerMember.timed_out_until AS `timed_out_until: DateTime<Utc>`,
+Channel.kind, DMChannelRecipient.user_id, ChannelThread.archived AS `archived?: bool`
 FROM Channel
 LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
 LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
-WHERE Channel.id = ?
+LEFT JOIN ChannelThread ON ChannelThread.channel_id=Channel.id
+WHERE Channel.id = ? AND Channel.kind != 'category'
 "#,
             user_id,
             channel_id,
@@ -70,28 +242,107 @@ WHERE Channel.id = ?
 			return Ok(HttpResponse::Forbidden().finish());
 		}
 
+		// group DMs stay usable, the gateway flags the messages of blocked users instead
+		if channel_row.kind == "DM" {
+			if let Some(other_id) = recipients.iter().find(|id| **id != user_id) {
+				if is_blocked_between(&app_state.db, user_id, *other_id).await? {
+					return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+						error: "Cannot message this user".to_string(),
+					}));
+				}
+			}
+		}
+
+		if let Some(server_id) = channel_row.server_id {
+			if !has_channel_permission(
+				&app_state.db,
+				server_id,
+				channel_id,
+				user_id,
+				Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+			)
+			.await?
+			{
+				return Ok(HttpResponse::Forbidden().finish());
+			}
+		}
+
+		if channel_row
+			.timed_out_until
+			.is_some_and(|timed_out_until| timed_out_until > Utc::now())
+		{
+			return Ok(HttpResponse::Forbidden().json(ErrorResponse {
+				error: "member_timed_out".to_string(),
+			}));
+		}
+
+		if channel_row.archived == Some(true) {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "thread_archived".to_string(),
+			}));
+		}
+
 		(
-			channel_row
-				.server_id
-				.and_then(|server_id| app_state.server_connections.get(&server_id))
-				.map(|conns| conns.clone())
-				.or(Some(recipients)),
+			message_recipients(&app_state, channel_row.server_id, channel_id, recipients).await?,
 			channel_row.created_at.map(|created_at| ServerMember {
 				user_id,
 				server_id: channel_row.server_id.unwrap(),
 				nickname: channel_row.nickname.clone(),
 				created_at,
 				user: None,
+				roles: None,
+				timed_out_until: None,
+				temporary: false,
+				invite_id: None,
+				presence: None,
 			}),
+			channel_row.archived.is_some(),
 		)
 	};
 
-	let message_id: u64 = {
+	let referenced_message = match body.reply_to {
+		Some(reply_to) => {
+			// replies are limited to messages of the same channel
+			let Some(row) = query!(
+				r#"SELECT ChannelMessage.user_id, LEFT(ChannelMessage.content, 100) AS content, User.username, User.display_name
+FROM ChannelMessage
+INNER JOIN User ON User.id=ChannelMessage.user_id
+WHERE ChannelMessage.id = ? AND ChannelMessage.channel_id = ?
+"#,
+				reply_to,
+				channel_id
+			)
+			.fetch_optional(&app_state.db)
+			.await?
+			else {
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					error: "invalid_reply".to_string(),
+				}));
+			};
+
+			Some(ReferencedMessage {
+				id: reply_to,
+				deleted: false,
+				user: Some(User {
+					id: row.user_id,
+					username: row.username,
+					display_name: row.display_name,
+				}),
+				content: row.content,
+			})
+		}
+		None => None,
+	};
+
+	let (message_id, attachment_ids): (u64, Vec<u64>) = {
 		let mut generator = generator.lock().unwrap();
-		generator.generate()
+		(
+			generator.generate(),
+			files.iter().map(|_| generator.generate()).collect(),
+		)
 	};
 
-	let content = body.content.clone();
+	let content = body.content;
 
 	let user = query_as!(
 		User,
@@ -101,33 +352,123 @@ WHERE Channel.id = ?
 	.fetch_one(&app_state.db)
 	.await?;
 
-	query!(
-        "INSERT INTO ChannelMessage (id, updated_at, content, kind, channel_id, user_id) VALUES (?, NULL, ?, 'text', ?, ?)",
-        message_id,
-        content,
-        channel_id,
-        user_id
-    )
-    .execute(&app_state.db)
-    .await?;
+	let attachments = files
+		.into_iter()
+		.zip(attachment_ids)
+		.map(|(file, id)| {
+			let filename = sanitize_filename(file.file_name.as_deref().unwrap_or_default());
+			let content_type =
+				normalize_content_type(file.content_type.as_ref().map(|mime| mime.essence_str()))
+					.to_string();
+
+			(id, filename, content_type, file.data)
+		})
+		.collect::<Vec<_>>();
+
+	let storage = &app_state.storage;
+	let keys = attachments
+		.iter()
+		.map(|(id, filename, _, _)| attachment_key(channel_id, *id, filename))
+		.collect::<Vec<_>>();
+
+	let result = async {
+		try_join_all(
+			keys.iter()
+				.zip(&attachments)
+				.map(|(key, (_, _, content_type, data))| {
+					storage.put(
+						key,
+						content_type,
+						content_type != DOWNLOAD_CONTENT_TYPE,
+						data.clone(),
+					)
+				}),
+		)
+		.await?;
+
+		let mut tx = app_state.db.begin().await?;
+
+		query!(
+			"INSERT INTO ChannelMessage (id, updated_at, content, kind, channel_id, user_id, reply_to) VALUES (?, NULL, ?, 'text', ?, ?, ?)",
+			message_id,
+			content,
+			channel_id,
+			user_id,
+			body.reply_to
+		)
+		.execute(&mut *tx)
+		.await?;
+
+		for (id, filename, content_type, data) in &attachments {
+			query!(
+				"INSERT INTO ChannelMessageAttachment (id, message_id, filename, content_type, size) VALUES (?, ?, ?, ?, ?)",
+				id,
+				message_id,
+				filename,
+				content_type,
+				data.len() as u32
+			)
+			.execute(&mut *tx)
+			.await?;
+		}
+
+		// sending a message in a thread joins it
+		if is_thread {
+			query!(
+				"INSERT IGNORE INTO ChannelThreadMember (channel_id, user_id) VALUES (?, ?)",
+				channel_id,
+				user_id
+			)
+			.execute(&mut *tx)
+			.await?;
+		}
+
+		tx.commit().await?;
+
+		Ok::<_, BackendError>(())
+	}
+	.await;
+
+	// the message was never created, so nothing references the uploaded objects anymore.
+	// some of them may have failed to upload, which deleting is fine with
+	if let Err(e) = result {
+		delete_in_background(&app_state, keys);
+
+		return Err(e);
+	}
 
 	let message = Message {
 		id: message_id,
 		updated_at: None,
-		content: content.clone(),
+		content,
 		kind: MessageKind::Text,
 		channel_id,
 		user: user.clone(),
 		member: member.clone(),
+		attachments: attachments
+			.into_iter()
+			.map(|(id, filename, content_type, data)| Attachment {
+				id,
+				url: app_state
+					.storage
+					.url(&attachment_key(channel_id, id, &filename)),
+				filename,
+				content_type,
+				size: data.len() as u32,
+			})
+			.collect(),
+		reactions: vec![],
+		referenced_message,
 	};
 
-	if let Some(recipients) = recipients {
-		send_updates(
-			[WsUpdateEvent::MessageCreate(message.clone())],
-			&app_state,
-			recipients,
-		);
-	}
+	// the message ends the author's typing indicator, which clients are expected to clear on their own
+	app_state.typing.remove(&(channel_id, user_id));
+
+	send_updates(
+		[WsUpdateEvent::MessageCreate(message.clone())],
+		&app_state,
+		recipients,
+	);
 
 	Ok(HttpResponse::Created().json(message))
 }
@@ -139,8 +480,30 @@ pub struct GetMessagesQuery {
 	last_id: Option<u64>,
 }
 
+macro_rules! attachment_row {
+	($storage:expr, $channel_id:expr, $row:expr) => {
+		Attachment {
+			id: $row.id,
+			url: $storage.url(&attachment_key($channel_id, $row.id, &$row.filename)),
+			filename: $row.filename,
+			content_type: $row.content_type,
+			size: $row.size,
+		}
+	};
+}
+
+macro_rules! reaction_row {
+	($row:expr) => {
+		Reaction {
+			emoji: $row.emoji,
+			count: $row.count as u32,
+			me: $row.me,
+		}
+	};
+}
+
 macro_rules! message_row {
-	($server_id:expr, $row:expr) => {{
+	($server_id:expr, $row:expr, $attachments:expr, $reactions:expr) => {{
 		let user = User {
 			id: $row.user_id,
 			username: $row.username,
@@ -153,8 +516,36 @@ macro_rules! message_row {
 			nickname: $row.nickname,
 			created_at,
 			user: None,
+			roles: None,
+			timed_out_until: None,
+			temporary: false,
+			invite_id: None,
+			presence: None,
 		});
 
+		// the referenced message's columns are all null if it has been deleted
+		let referenced_message =
+			$row.reply_to.map(
+				|id| match ($row.referenced_user_id, $row.referenced_username) {
+					(Some(user_id), Some(username)) => ReferencedMessage {
+						id,
+						deleted: false,
+						user: Some(User {
+							id: user_id,
+							username,
+							display_name: $row.referenced_display_name,
+						}),
+						content: $row.referenced_content,
+					},
+					_ => ReferencedMessage {
+						id,
+						deleted: true,
+						user: None,
+						content: None,
+					},
+				},
+			);
+
 		Message {
 			id: $row.id,
 			updated_at: $row.updated_at,
@@ -163,6 +554,9 @@ macro_rules! message_row {
 			channel_id: $row.channel_id,
 			user,
 			member,
+			attachments: $attachments,
+			reactions: $reactions,
+			referenced_message,
 		}
 	}};
 }
@@ -181,48 +575,28 @@ pub async fn get_messages(
 
 	let channel_id = path.into_inner();
 
-	let server_id = {
-		let rows = query!(
-			r#"SELECT ServerMember.server_id, DMChannelRecipient.user_id
-FROM Channel
-LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
-LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
-WHERE Channel.id = ?
-"#,
-			user_id,
-			channel_id,
-		)
-		.fetch_all(&app_state.db)
-		.await?;
-
-		let Some(channel_row) = rows.first() else {
-			return Ok(HttpResponse::Forbidden().finish());
-		};
-
-		let recipients = rows
-			.iter()
-			.filter_map(|row| row.user_id)
-			.collect::<HashSet<_>>();
-
-		if !recipients.contains(&user_id) && channel_row.server_id.is_none() {
-			return Ok(HttpResponse::Forbidden().finish());
-		}
-
-		channel_row.server_id
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
 	};
 
+	let server_id = access.server_id;
+
 	let limit = query.limit.unwrap_or(50);
 	let last_id = query.last_id.unwrap_or(u64::MAX);
 
 	let mut messages = query!(
-        r#"SELECT ChannelMessage.id, ChannelMessage.updated_at, ChannelMessage.content, ChannelMessage.kind, ChannelMessage.channel_id, ChannelMessage.user_id,
+        r#"SELECT ChannelMessage.id, ChannelMessage.updated_at, ChannelMessage.content, ChannelMessage.kind, ChannelMessage.channel_id, ChannelMessage.user_id, ChannelMessage.reply_to,
 User.username, User.display_name,
-ServerMember.nickname, ServerMember.created_at
+ServerMember.nickname, ServerMember.created_at,
+ReferencedMessage.user_id AS referenced_user_id, LEFT(ReferencedMessage.content, 100) AS referenced_content,
+ReferencedUser.username AS referenced_username, ReferencedUser.display_name AS referenced_display_name
 FROM ChannelMessage
 INNER JOIN User ON User.id=ChannelMessage.user_id
 LEFT JOIN ServerMember ON ServerMember.user_id=ChannelMessage.user_id AND ServerMember.server_id=?
+LEFT JOIN ChannelMessage AS ReferencedMessage ON ReferencedMessage.id=ChannelMessage.reply_to
+LEFT JOIN User AS ReferencedUser ON ReferencedUser.id=ReferencedMessage.user_id
 WHERE ChannelMessage.channel_id = ? AND ChannelMessage.id < ?
-ORDER BY id DESC
+ORDER BY ChannelMessage.id DESC
 LIMIT ?
 "#,
         server_id,
@@ -235,10 +609,64 @@ LIMIT ?
 
 	messages.reverse();
 
+	let mut attachments = HashMap::<u64, Vec<Attachment>>::new();
+	let mut reactions = HashMap::<u64, Vec<Reaction>>::new();
+
+	// the page is a contiguous range of the channel's messages, so a range query covers it
+	if let (Some(first), Some(last)) = (messages.first(), messages.last()) {
+		let rows = query!(
+			r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.message_id, ChannelMessageAttachment.filename, ChannelMessageAttachment.content_type, ChannelMessageAttachment.size
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+WHERE ChannelMessage.channel_id = ? AND ChannelMessage.id BETWEEN ? AND ?
+ORDER BY ChannelMessageAttachment.id
+"#,
+			channel_id,
+			first.id,
+			last.id
+		)
+		.fetch_all(&app_state.db)
+		.await?;
+
+		for row in rows {
+			attachments
+				.entry(row.message_id)
+				.or_default()
+				.push(attachment_row!(app_state.storage, channel_id, row));
+		}
+
+		let rows = query!(
+			r#"SELECT ChannelMessageReaction.message_id, ChannelMessageReaction.emoji, COUNT(*) AS count, MAX(ChannelMessageReaction.user_id = ?) AS `me: bool`
+FROM ChannelMessageReaction
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageReaction.message_id
+WHERE ChannelMessage.channel_id = ? AND ChannelMessage.id BETWEEN ? AND ?
+GROUP BY ChannelMessageReaction.message_id, ChannelMessageReaction.emoji
+ORDER BY MIN(ChannelMessageReaction.created_at)
+"#,
+			user_id,
+			channel_id,
+			first.id,
+			last.id
+		)
+		.fetch_all(&app_state.db)
+		.await?;
+
+		for row in rows {
+			reactions
+				.entry(row.message_id)
+				.or_default()
+				.push(reaction_row!(row));
+		}
+	}
+
 	Ok(HttpResponse::Ok().json(
 		messages
 			.into_iter()
-			.map(|row| message_row!(server_id, row))
+			.map(|row| {
+				let message_attachments = attachments.remove(&row.id).unwrap_or_default();
+				let message_reactions = reactions.remove(&row.id).unwrap_or_default();
+				message_row!(server_id, row, message_attachments, message_reactions)
+			})
 			.collect::<Vec<_>>(),
 	))
 }
@@ -254,43 +682,23 @@ pub async fn get_message(
 
 	let (channel_id, message_id) = path.into_inner();
 
-	let server_id = {
-		let rows = query!(
-			r#"SELECT ServerMember.server_id, DMChannelRecipient.user_id
-FROM Channel
-LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
-LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
-WHERE Channel.id = ?
-"#,
-			user_id,
-			channel_id,
-		)
-		.fetch_all(&app_state.db)
-		.await?;
-
-		let Some(channel_row) = rows.first() else {
-			return Ok(HttpResponse::Forbidden().finish());
-		};
-
-		let recipients = rows
-			.iter()
-			.filter_map(|row| row.user_id)
-			.collect::<HashSet<_>>();
-
-		if !recipients.contains(&user_id) && channel_row.server_id.is_none() {
-			return Ok(HttpResponse::Forbidden().finish());
-		}
-
-		channel_row.server_id
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
 	};
 
+	let server_id = access.server_id;
+
 	let Some(message) = query!(
-        r#"SELECT ChannelMessage.id, ChannelMessage.updated_at, ChannelMessage.content, ChannelMessage.kind, ChannelMessage.channel_id, ChannelMessage.user_id,
+        r#"SELECT ChannelMessage.id, ChannelMessage.updated_at, ChannelMessage.content, ChannelMessage.kind, ChannelMessage.channel_id, ChannelMessage.user_id, ChannelMessage.reply_to,
 User.username, User.display_name,
-ServerMember.nickname, ServerMember.created_at
+ServerMember.nickname, ServerMember.created_at,
+ReferencedMessage.user_id AS referenced_user_id, LEFT(ReferencedMessage.content, 100) AS referenced_content,
+ReferencedUser.username AS referenced_username, ReferencedUser.display_name AS referenced_display_name
 FROM ChannelMessage
 INNER JOIN User ON User.id=ChannelMessage.user_id
 LEFT JOIN ServerMember ON ServerMember.user_id=ChannelMessage.user_id AND ServerMember.server_id=?
+LEFT JOIN ChannelMessage AS ReferencedMessage ON ReferencedMessage.id=ChannelMessage.reply_to
+LEFT JOIN User AS ReferencedUser ON ReferencedUser.id=ReferencedMessage.user_id
 WHERE ChannelMessage.id = ? AND ChannelMessage.channel_id = ?
 "#,
         server_id,
@@ -302,7 +710,85 @@ WHERE ChannelMessage.id = ? AND ChannelMessage.channel_id = ?
         return Ok(HttpResponse::NotFound().finish());
     };
 
-	Ok(HttpResponse::Ok().json(message_row!(server_id, message)))
+	let attachments = query!(
+		"SELECT id, filename, content_type, size FROM ChannelMessageAttachment WHERE message_id = ? ORDER BY id",
+		message_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| attachment_row!(app_state.storage, channel_id, row))
+	.collect();
+
+	let reactions = query!(
+		r#"SELECT emoji, COUNT(*) AS count, MAX(user_id = ?) AS `me: bool`
+FROM ChannelMessageReaction
+WHERE message_id = ?
+GROUP BY emoji
+ORDER BY MIN(created_at)
+"#,
+		user_id,
+		message_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| reaction_row!(row))
+	.collect();
+
+	Ok(HttpResponse::Ok().json(message_row!(server_id, message, attachments, reactions)))
+}
+
+pub async fn ack_message(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, message_id) = path.into_inner();
+
+	if get_channel_access(&app_state, channel_id, user_id)
+		.await?
+		.is_none()
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM ChannelMessage WHERE id = ? AND channel_id = ?) AS `exists: bool`",
+		message_id,
+		channel_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	// acking an older message marks the channel as unread from that message on
+	query!(
+		r#"INSERT INTO ChannelReadState (channel_id, user_id, last_read_message_id) VALUES (?, ?, ?)
+ON DUPLICATE KEY UPDATE last_read_message_id = VALUES(last_read_message_id)"#,
+		channel_id,
+		user_id,
+		message_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	let read_state = get_read_state(&app_state.db, user_id, channel_id).await?;
+
+	send_updates(
+		[WsUpdateEvent::ReadStateUpdate(read_state.clone())],
+		&app_state,
+		[user_id],
+	);
+
+	Ok(HttpResponse::Ok().json(read_state))
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -326,39 +812,17 @@ pub async fn update_message(
 
 	let (channel_id, message_id) = path.into_inner();
 
-	let recipients = {
-		let rows = query!(
-			r#"SELECT ServerMember.server_id, DMChannelRecipient.user_id
-FROM Channel
-LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
-LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
-WHERE Channel.id = ?
-"#,
-			user_id,
-			channel_id,
-		)
-		.fetch_all(&app_state.db)
-		.await?;
-
-		let Some(channel_row) = rows.first() else {
-			return Ok(HttpResponse::Forbidden().finish());
-		};
-
-		let recipients = rows
-			.iter()
-			.filter_map(|row| row.user_id)
-			.collect::<HashSet<_>>();
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
 
-		if !recipients.contains(&user_id) && channel_row.server_id.is_none() {
-			return Ok(HttpResponse::Forbidden().finish());
-		}
+	if access.archived {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "thread_archived".to_string(),
+		}));
+	}
 
-		channel_row
-			.server_id
-			.and_then(|server_id| app_state.server_connections.get(&server_id))
-			.map(|conns| conns.clone())
-			.or(Some(recipients))
-	};
+	let recipients = access.recipients(&app_state).await?;
 
 	let updated_at = Utc::now();
 
@@ -379,17 +843,15 @@ WHERE Channel.id = ?
 		return Ok(HttpResponse::NotFound().finish());
 	}
 
-	if let Some(recipients) = recipients {
-		send_updates(
-			[WsUpdateEvent::MessageUpdate {
-				id: message_id,
-				content: body.content.clone(),
-				updated_at,
-			}],
-			&app_state,
-			recipients,
-		);
-	}
+	send_updates(
+		[WsUpdateEvent::MessageUpdate {
+			id: message_id,
+			content: body.content.clone(),
+			updated_at,
+		}],
+		&app_state,
+		recipients,
+	);
 
 	Ok(HttpResponse::Ok().finish())
 }
@@ -405,44 +867,42 @@ pub async fn delete_message(
 
 	let (channel_id, message_id) = path.into_inner();
 
-	let recipients = {
-		let rows = query!(
-			r#"SELECT ServerMember.server_id, DMChannelRecipient.user_id
-FROM Channel
-LEFT JOIN ServerMember ON ServerMember.server_id=Channel.server_id AND ServerMember.user_id=?
-LEFT JOIN DMChannelRecipient ON DMChannelRecipient.channel_id=Channel.id
-WHERE Channel.id = ?
-"#,
-			user_id,
-			channel_id,
-		)
-		.fetch_all(&app_state.db)
-		.await?;
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
 
-		let Some(channel_row) = rows.first() else {
-			return Ok(HttpResponse::Forbidden().finish());
-		};
+	let permissions = access.permissions;
+	let recipients = access.recipients(&app_state).await?;
 
-		let recipients = rows
-			.iter()
-			.filter_map(|row| row.user_id)
-			.collect::<HashSet<_>>();
+	let Some(message) = query!(
+		"SELECT user_id FROM ChannelMessage WHERE id = ? AND channel_id = ?",
+		message_id,
+		channel_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
 
-		if !recipients.contains(&user_id) && channel_row.server_id.is_none() {
-			return Ok(HttpResponse::Forbidden().finish());
-		}
+	// messages can be deleted by their author, or by members allowed to manage messages
+	if message.user_id != user_id && !permissions.contains(Permissions::MANAGE_MESSAGES) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
 
-		channel_row
-			.server_id
-			.and_then(|server_id| app_state.server_connections.get(&server_id))
-			.map(|conns| conns.clone())
-			.or(Some(recipients))
-	};
+	let attachment_keys = query!(
+		"SELECT id, filename FROM ChannelMessageAttachment WHERE message_id = ?",
+		message_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| attachment_key(channel_id, row.id, &row.filename))
+	.collect::<Vec<_>>();
 
 	let result = query!(
-		"DELETE FROM ChannelMessage WHERE id = ? AND user_id = ? AND channel_id = ?",
+		"DELETE FROM ChannelMessage WHERE id = ? AND channel_id = ?",
 		message_id,
-		user_id,
 		channel_id
 	)
 	.execute(&app_state.db)
@@ -452,13 +912,335 @@ WHERE Channel.id = ?
 		return Ok(HttpResponse::NotFound().finish());
 	}
 
-	if let Some(recipients) = recipients {
-		send_updates(
-			[WsUpdateEvent::MessageDelete { id: message_id }],
-			&app_state,
-			recipients,
+	delete_in_background(&app_state, attachment_keys);
+
+	send_updates(
+		[WsUpdateEvent::MessageDelete { id: message_id }],
+		&app_state,
+		recipients,
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct SearchMessagesQuery {
+	#[validate(length(min = 1, max = 256))]
+	content: Option<String>,
+	author_id: Option<u64>,
+	channel_id: Option<u64>,
+	before: Option<u64>,
+	after: Option<u64>,
+	has_attachment: Option<bool>,
+	has_link: Option<bool>,
+	#[validate(range(min = 1, max = 50))]
+	limit: Option<u64>,
+}
+
+#[derive(FromRow)]
+struct SearchHitRow {
+	id: u64,
+	updated_at: Option<DateTime<Utc>>,
+	content: String,
+	kind: String,
+	channel_id: u64,
+	user_id: u64,
+	reply_to: Option<u64>,
+	username: String,
+	display_name: Option<String>,
+	nickname: Option<String>,
+	created_at: Option<DateTime<Utc>>,
+	referenced_user_id: Option<u64>,
+	referenced_content: Option<String>,
+	referenced_username: Option<String>,
+	referenced_display_name: Option<String>,
+	total_results: i64,
+}
+
+#[derive(FromRow)]
+struct SearchAttachmentRow {
+	id: u64,
+	message_id: u64,
+	filename: String,
+	content_type: String,
+	size: u32,
+	channel_id: u64,
+}
+
+#[derive(FromRow)]
+struct SearchReactionRow {
+	message_id: u64,
+	emoji: String,
+	count: i64,
+	me: bool,
+}
+
+// pushes a parenthesized list of the ids, which mustn't be empty
+fn push_ids(builder: &mut QueryBuilder<'_, MySql>, ids: &[u64]) {
+	builder.push("(");
+
+	let mut separated = builder.separated(", ");
+	for id in ids {
+		separated.push_bind(*id);
+	}
+
+	builder.push(")");
+}
+
+// channel_ids are the channels the user is allowed to read
+async fn search_messages(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+	server_id: Option<u64>,
+	channel_ids: Vec<u64>,
+	query: &SearchMessagesQuery,
+) -> ApiResult {
+	let terms = query
+		.content
+		.as_deref()
+		.map(search_terms)
+		.unwrap_or_default();
+
+	// searching without any filter would go through every message the user can read
+	if query.content.is_none()
+		&& query.author_id.is_none()
+		&& query.channel_id.is_none()
+		&& query.before.is_none()
+		&& query.after.is_none()
+		&& query.has_attachment.is_none()
+		&& query.has_link.is_none()
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "missing_search_filter".to_string(),
+		}));
+	}
+
+	if query.content.is_some() && terms.is_empty() {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "invalid_search_content".to_string(),
+		}));
+	}
+
+	let channel_ids = match query.channel_id {
+		Some(channel_id) if channel_ids.contains(&channel_id) => vec![channel_id],
+		Some(_) => return Ok(HttpResponse::Forbidden().finish()),
+		None => channel_ids,
+	};
+
+	if channel_ids.is_empty() {
+		return Ok(HttpResponse::Ok().json(MessageSearchResults {
+			total_results: 0,
+			hits: vec![],
+		}));
+	}
+
+	let boolean_query = (!terms.is_empty()).then(|| search_boolean_query(&terms));
+
+	// the amount of channels varies, so the query is built at runtime
+	let mut builder = QueryBuilder::<MySql>::new(
+		r#"SELECT ChannelMessage.id, ChannelMessage.updated_at, ChannelMessage.content, ChannelMessage.kind, ChannelMessage.channel_id, ChannelMessage.user_id, ChannelMessage.reply_to,
+User.username, User.display_name,
+ServerMember.nickname, ServerMember.created_at,
+ReferencedMessage.user_id AS referenced_user_id, LEFT(ReferencedMessage.content, 100) AS referenced_content,
+ReferencedUser.username AS referenced_username, ReferencedUser.display_name AS referenced_display_name,
+COUNT(*) OVER () AS total_results
+FROM ChannelMessage
+INNER JOIN User ON User.id=ChannelMessage.user_id
+LEFT JOIN ServerMember ON ServerMember.user_id=ChannelMessage.user_id AND ServerMember.server_id="#,
+	);
+	builder.push_bind(server_id).push(
+		r#"
+LEFT JOIN ChannelMessage AS ReferencedMessage ON ReferencedMessage.id=ChannelMessage.reply_to
+LEFT JOIN User AS ReferencedUser ON ReferencedUser.id=ReferencedMessage.user_id
+WHERE ChannelMessage.channel_id IN "#,
+	);
+	push_ids(&mut builder, &channel_ids);
+
+	if let Some(boolean_query) = &boolean_query {
+		builder
+			.push(" AND MATCH (ChannelMessage.content) AGAINST (")
+			.push_bind(boolean_query)
+			.push(" IN BOOLEAN MODE)");
+	}
+	if let Some(author_id) = query.author_id {
+		builder
+			.push(" AND ChannelMessage.user_id = ")
+			.push_bind(author_id);
+	}
+	if let Some(before) = query.before {
+		builder.push(" AND ChannelMessage.id < ").push_bind(before);
+	}
+	if let Some(after) = query.after {
+		builder.push(" AND ChannelMessage.id > ").push_bind(after);
+	}
+	if let Some(has_attachment) = query.has_attachment {
+		builder
+			.push(" AND EXISTS(SELECT 1 FROM ChannelMessageAttachment WHERE ChannelMessageAttachment.message_id=ChannelMessage.id) = ")
+			.push_bind(has_attachment);
+	}
+	if let Some(has_link) = query.has_link {
+		builder
+			.push(" AND (ChannelMessage.content REGEXP 'https?://') = ")
+			.push_bind(has_link);
+	}
+
+	builder
+		.push(" ORDER BY ChannelMessage.id DESC LIMIT ")
+		.push_bind(query.limit.unwrap_or(25));
+
+	let messages = builder
+		.build_query_as::<SearchHitRow>()
+		.fetch_all(&app_state.db)
+		.await?;
+
+	let total_results = messages
+		.first()
+		.map(|row| row.total_results as u32)
+		.unwrap_or_default();
+
+	let mut attachments = HashMap::<u64, Vec<Attachment>>::new();
+	let mut reactions = HashMap::<u64, Vec<Reaction>>::new();
+
+	// unlike a page of get_messages, the hits aren't a contiguous range of messages
+	if !messages.is_empty() {
+		let message_ids = messages.iter().map(|row| row.id).collect::<Vec<_>>();
+
+		let mut builder = QueryBuilder::<MySql>::new(
+			r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.message_id, ChannelMessageAttachment.filename, ChannelMessageAttachment.content_type, ChannelMessageAttachment.size, ChannelMessage.channel_id
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+WHERE ChannelMessageAttachment.message_id IN "#,
+		);
+		push_ids(&mut builder, &message_ids);
+		builder.push(" ORDER BY ChannelMessageAttachment.id");
+
+		let rows = builder
+			.build_query_as::<SearchAttachmentRow>()
+			.fetch_all(&app_state.db)
+			.await?;
+
+		for row in rows {
+			attachments
+				.entry(row.message_id)
+				.or_default()
+				.push(attachment_row!(app_state.storage, row.channel_id, row));
+		}
+
+		let mut builder = QueryBuilder::<MySql>::new(
+			"SELECT message_id, emoji, COUNT(*) AS count, MAX(user_id = ",
 		);
+		builder
+			.push_bind(user_id)
+			.push(") AS me FROM ChannelMessageReaction WHERE message_id IN ");
+		push_ids(&mut builder, &message_ids);
+		builder.push(" GROUP BY message_id, emoji ORDER BY MIN(created_at)");
+
+		let rows = builder
+			.build_query_as::<SearchReactionRow>()
+			.fetch_all(&app_state.db)
+			.await?;
+
+		for row in rows {
+			reactions
+				.entry(row.message_id)
+				.or_default()
+				.push(reaction_row!(row));
+		}
 	}
 
-	Ok(HttpResponse::Ok().finish())
+	Ok(HttpResponse::Ok().json(MessageSearchResults {
+		total_results,
+		hits: messages
+			.into_iter()
+			.map(|row| {
+				let highlights = search_highlights(&row.content, &terms);
+				let message_attachments = attachments.remove(&row.id).unwrap_or_default();
+				let message_reactions = reactions.remove(&row.id).unwrap_or_default();
+
+				MessageSearchHit {
+					message: message_row!(server_id, row, message_attachments, message_reactions),
+					highlights,
+				}
+			})
+			.collect(),
+	}))
+}
+
+pub async fn search_server_messages(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+	query: web::Query<SearchMessagesQuery>,
+) -> ApiResult {
+	query.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	let Some((permissions, roles)) =
+		get_member_permissions(&app_state.db, server_id, user_id).await?
+	else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let channels = query!(
+		r#"SELECT Channel.id, ChannelThread.parent_id AS `parent_id?`
+FROM Channel
+LEFT JOIN ChannelThread ON ChannelThread.channel_id=Channel.id
+WHERE Channel.server_id = ?
+"#,
+		server_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	let overwrites = get_server_overwrites(&app_state.db, server_id).await?;
+
+	// threads are readable by whoever can view their parent channel
+	let channel_ids = channels
+		.into_iter()
+		.filter(|row| {
+			apply_overwrites(
+				permissions,
+				server_id,
+				user_id,
+				&roles,
+				overwrites
+					.get(&row.parent_id.unwrap_or(row.id))
+					.map(Vec::as_slice)
+					.unwrap_or_default(),
+			)
+			.contains(Permissions::VIEW_CHANNEL)
+		})
+		.map(|row| row.id)
+		.collect();
+
+	search_messages(&app_state, user_id, Some(server_id), channel_ids, &query).await
+}
+
+pub async fn search_direct_messages(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	query: web::Query<SearchMessagesQuery>,
+) -> ApiResult {
+	query.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let channel_ids = query!(
+		"SELECT channel_id FROM DMChannelRecipient WHERE user_id = ?",
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| row.channel_id)
+	.collect();
+
+	search_messages(&app_state, user_id, None, channel_ids, &query).await
 }
@@ -1,5 +1,11 @@
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
 use serde::{Deserialize, Deserializer};
+use serde_with::{DeserializeAs, DisplayFromStr, PickFirst, Same};
+use std::future::{ready, Ready};
 
+pub mod audit_log;
+pub mod bans;
+pub mod blocks;
 pub mod channels;
 pub mod direct_messages;
 pub mod friend_requests;
@@ -8,7 +14,13 @@ pub mod invites;
 pub mod members;
 pub mod messages;
 pub mod oauth;
+pub mod presence;
+pub mod reactions;
+pub mod roles;
 pub mod servers;
+pub mod settings;
+pub mod threads;
+pub mod typing;
 pub mod users;
 pub mod webauthn;
 pub mod ws;
@@ -70,3 +82,37 @@ where
 		.map(|s: Option<String>| s.map(|s| s.to_string().trim().to_string()))
 		.map(Some)
 }
+
+// distinguishes a snowflake explicitly set to null from a missing one, which has to be paired with #[serde(default)]
+// snowflakes are sent as strings, but numbers are accepted too
+pub fn deserialize_some_snowflake<'de, D>(deserializer: D) -> Result<Option<Option<u64>>, D::Error>
+where
+	D: Deserializer<'de>,
+{
+	<Option<PickFirst<(Same, DisplayFromStr)>> as DeserializeAs<'de, Option<u64>>>::deserialize_as(
+		deserializer,
+	)
+	.map(Some)
+}
+
+pub const MAX_AUDIT_LOG_REASON_LENGTH: usize = 512;
+
+// the reason recorded in the audit log for a change, taken from the X-Audit-Log-Reason header
+#[derive(Debug)]
+pub struct AuditLogReason(pub Option<String>);
+
+impl FromRequest for AuditLogReason {
+	type Error = actix_web::Error;
+	type Future = Ready<Result<Self, Self::Error>>;
+
+	fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+		let reason = req
+			.headers()
+			.get("X-Audit-Log-Reason")
+			.map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+			.filter(|reason| !reason.is_empty())
+			.map(|reason| reason.chars().take(MAX_AUDIT_LOG_REASON_LENGTH).collect());
+
+		ready(Ok(AuditLogReason(reason)))
+	}
+}
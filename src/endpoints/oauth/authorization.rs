@@ -29,6 +29,8 @@ pub struct AuthorizeQuery {
 	state: Option<String>,
 	code_challenge: Option<String>,
 	code_challenge_method: Option<CodeChallengeMethod>,
+	// OpenID Connect, echoed back in the id token
+	nonce: Option<String>,
 }
 
 // this differs from get_client because it doesn't require the user to be the owner of the client,
@@ -91,6 +93,7 @@ pub async fn authorize_client(
 		state,
 		code_challenge,
 		code_challenge_method,
+		nonce,
 	})) = query
 	else {
 		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
@@ -132,7 +135,7 @@ pub async fn authorize_client(
 	let code = CODE_GENERATOR.create_id();
 
 	query!(
-        "INSERT INTO AuthorizationCode (id, created_at, expires_at, client_id, user_id, scope, code_challenge, code_challenge_method) VALUES (?, DEFAULT, DEFAULT, ?, ?, ?, ?, ?)",
+        "INSERT INTO AuthorizationCode (id, created_at, expires_at, client_id, user_id, scope, code_challenge, code_challenge_method, nonce) VALUES (?, DEFAULT, DEFAULT, ?, ?, ?, ?, ?, ?)",
         code,
         client_id,
         user_id,
@@ -145,6 +148,7 @@ pub async fn authorize_client(
         code_challenge_method
             .map(|m| m.to_string())
             .unwrap_or_else(|| "plain".to_string()),
+        nonce,
     )
     .execute(&app_state.db)
     .await?;
@@ -0,0 +1,278 @@
+//! The device authorization grant (RFC 8628)
+
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_with::{formats::SpaceSeparator, serde_as, StringWithSeparator};
+use sqlx::query;
+use std::collections::HashSet;
+use url::Url;
+
+use crate::{
+	endpoints::oauth::{
+		token::{get_client_id, TOKEN_GENERATOR},
+		ErrorResponse,
+	},
+	error::{ApiResult, BackendError},
+	middleware::Identity,
+	models::{client::Client, scope::Scope},
+	AppState,
+};
+
+// consonants only, so codes are easy to type and can't spell out words (RFC 8628 section 6.1)
+const USER_CODE_CHARSET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+// in seconds
+const DEVICE_CODE_EXPIRES_IN: u16 = 600;
+// in seconds, how long devices wait between polls, which is increased each time they poll too fast
+pub(super) const POLL_INTERVAL: u8 = 5;
+pub(super) const MAX_POLL_INTERVAL: u8 = 60;
+
+fn generate_user_code() -> Result<String, BackendError> {
+	// bytes past the largest multiple of the charset's length are skipped, so every character is equally likely
+	let limit = 256 - 256 % USER_CODE_CHARSET.len();
+	let mut code = String::with_capacity(USER_CODE_LENGTH);
+	let mut bytes = [0u8; USER_CODE_LENGTH * 2];
+
+	while code.len() < USER_CODE_LENGTH {
+		openssl::rand::rand_bytes(&mut bytes)?;
+
+		for byte in bytes.iter().map(|b| *b as usize).filter(|b| *b < limit) {
+			if code.len() == USER_CODE_LENGTH {
+				break;
+			}
+
+			code.push(USER_CODE_CHARSET[byte % USER_CODE_CHARSET.len()] as char);
+		}
+	}
+
+	Ok(code)
+}
+
+// users may type the code in any case and with or without the dash
+fn normalize_user_code(code: &str) -> String {
+	code.chars()
+		.filter(|c| c.is_ascii_alphanumeric())
+		.map(|c| c.to_ascii_uppercase())
+		.collect()
+}
+
+fn format_user_code(code: &str) -> String {
+	let (first, second) = code.split_at(USER_CODE_LENGTH / 2);
+	format!("{first}-{second}")
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct DeviceAuthorizationBody {
+	#[serde_as(as = "Option<StringWithSeparator<SpaceSeparator, Scope>>")]
+	scope: Option<HashSet<Scope>>,
+	client_id: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct DeviceAuthorizationResponse {
+	device_code: String,
+	user_code: String,
+	verification_uri: Url,
+	verification_uri_complete: Url,
+	expires_in: u16,
+	interval: u8,
+}
+
+pub async fn device_authorization(
+	app_state: web::Data<AppState>,
+	body: web::Form<DeviceAuthorizationBody>,
+	identity: web::ReqData<Option<Identity>>,
+) -> ApiResult {
+	let DeviceAuthorizationBody { scope, client_id } = body.into_inner();
+
+	let client_id = match get_client_id(client_id, identity.into_inner()) {
+		Ok(id) => id,
+		Err(resp) => return Ok(resp),
+	};
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM Client WHERE id = ?) AS `exists: bool`",
+		client_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			redirect: false,
+			error: "invalid_client",
+			error_description: "Invalid client",
+		}));
+	}
+
+	let scope = scope
+		.unwrap_or_default()
+		.iter()
+		.map(|s| s.to_string())
+		.collect::<Vec<String>>()
+		.join(",");
+
+	let device_code = TOKEN_GENERATOR.create_id();
+
+	// user codes are short, so a collision with one which hasn't been cleaned up yet is possible
+	let user_code = loop {
+		let user_code = generate_user_code()?;
+
+		match query!(
+            "INSERT INTO DeviceCode (id, user_code, created_at, expires_at, client_id, scope, poll_interval) VALUES (?, ?, DEFAULT, TIMESTAMPADD(SECOND, ?, NOW()), ?, ?, ?)",
+            device_code,
+            user_code,
+            DEVICE_CODE_EXPIRES_IN,
+            client_id,
+            scope,
+            POLL_INTERVAL
+        )
+        .execute(&app_state.db)
+        .await
+		{
+			Err(e)
+				if e.as_database_error()
+					.is_some_and(|e| e.is_unique_violation()) =>
+			{
+				continue;
+			}
+			r => r?,
+		};
+
+		break format_user_code(&user_code);
+	};
+
+	let verification_uri = app_state.app_url.join("app/oauth/device").unwrap();
+	let mut verification_uri_complete = verification_uri.clone();
+	verification_uri_complete
+		.query_pairs_mut()
+		.append_pair("user_code", &user_code);
+
+	Ok(HttpResponse::Ok().json(DeviceAuthorizationResponse {
+		device_code,
+		user_code,
+		verification_uri,
+		verification_uri_complete,
+		expires_in: DEVICE_CODE_EXPIRES_IN,
+		interval: POLL_INTERVAL,
+	}))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceQuery {
+	user_code: String,
+}
+
+// lets the website show which client is asking for access before the user approves it
+pub async fn get_device_info(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	query: web::Query<DeviceQuery>,
+) -> ApiResult {
+	if !matches!(identity.into_inner(), Identity::User(_)) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let Some(record) = query!(
+        "SELECT Client.id, Client.name, Client.owner_id, Client.client_uri, Client.tos_uri, Client.policy_uri, DeviceCode.scope FROM DeviceCode INNER JOIN Client ON Client.id=DeviceCode.client_id WHERE DeviceCode.user_code = ? AND DeviceCode.expires_at > NOW() AND DeviceCode.user_id IS NULL AND NOT DeviceCode.denied",
+        normalize_user_code(&query.user_code)
+    )
+    .fetch_optional(&app_state.db)
+    .await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+	let client = Client {
+		id: record.id,
+		name: record.name,
+		client_uri: record.client_uri.map(|u| u.parse().unwrap()),
+		tos_uri: record.tos_uri.map(|u| u.parse().unwrap()),
+		policy_uri: record.policy_uri.map(|u| u.parse().unwrap()),
+		owner_id: record.owner_id,
+		redirect_uris: vec![],
+	};
+
+	Ok(HttpResponse::Ok().json(json!({
+		"client": client,
+		"scope": record.scope.replace(',', " "),
+	})))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceApprovalBody {
+	user_code: String,
+	approve: bool,
+}
+
+pub async fn approve_device(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	body: web::Json<DeviceApprovalBody>,
+) -> ApiResult {
+	let user_id = match identity.into_inner() {
+		Identity::User(id) => id,
+		_ => return Ok(HttpResponse::Forbidden().finish()),
+	};
+
+	let user_code = normalize_user_code(&body.user_code);
+
+	// the device picks the decision up the next time it polls the token endpoint
+	let result = if body.approve {
+		query!(
+            "UPDATE DeviceCode SET user_id = ? WHERE user_code = ? AND expires_at > NOW() AND user_id IS NULL AND NOT denied",
+            user_id,
+            user_code
+        )
+        .execute(&app_state.db)
+        .await?
+	} else {
+		query!(
+            "UPDATE DeviceCode SET denied = TRUE WHERE user_code = ? AND expires_at > NOW() AND user_id IS NULL AND NOT denied",
+            user_code
+        )
+        .execute(&app_state.db)
+        .await?
+	};
+
+	if result.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn generates_user_codes_from_the_charset() {
+		for _ in 0..100 {
+			let code = generate_user_code().unwrap();
+
+			assert_eq!(code.len(), USER_CODE_LENGTH);
+			assert!(
+				code.bytes().all(|c| USER_CODE_CHARSET.contains(&c)),
+				"{code}"
+			);
+		}
+	}
+
+	#[test]
+	fn normalizes_user_codes() {
+		assert_eq!(normalize_user_code("BCDF-GHJK"), "BCDFGHJK");
+		assert_eq!(normalize_user_code(" bcdf-ghjk\n"), "BCDFGHJK");
+		assert_eq!(normalize_user_code("bcdfghjk"), "BCDFGHJK");
+	}
+
+	#[test]
+	fn formats_user_codes() {
+		assert_eq!(format_user_code("BCDFGHJK"), "BCDF-GHJK");
+		assert_eq!(
+			normalize_user_code(&format_user_code("BCDFGHJK")),
+			"BCDFGHJK"
+		);
+	}
+}
@@ -0,0 +1,90 @@
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use url::Url;
+
+use crate::{
+	endpoints::oauth::{issuer, token::GrantType, CodeChallengeMethod},
+	models::scope::Scope,
+	AppState,
+};
+
+// RFC 8414
+#[derive(Debug, Serialize)]
+struct AuthorizationServerMetadata {
+	issuer: String,
+	authorization_endpoint: Url,
+	token_endpoint: Url,
+	revocation_endpoint: Url,
+	introspection_endpoint: Url,
+	device_authorization_endpoint: Url,
+	jwks_uri: Url,
+	scopes_supported: Vec<String>,
+	response_types_supported: &'static [&'static str],
+	grant_types_supported: &'static [&'static str],
+	token_endpoint_auth_methods_supported: &'static [&'static str],
+	revocation_endpoint_auth_methods_supported: &'static [&'static str],
+	introspection_endpoint_auth_methods_supported: &'static [&'static str],
+	code_challenge_methods_supported: Vec<String>,
+}
+
+fn server_metadata(app_state: &web::Data<AppState>) -> AuthorizationServerMetadata {
+	let api_url = |path: &str| app_state.api_url.join(path).unwrap();
+
+	AuthorizationServerMetadata {
+		issuer: issuer(app_state),
+		// the user authorizes clients on the website, which then calls the api
+		authorization_endpoint: app_state.app_url.join("app/oauth/authorize").unwrap(),
+		token_endpoint: api_url("v0/token"),
+		revocation_endpoint: api_url("v0/token/revoke"),
+		introspection_endpoint: api_url("v0/token/introspect"),
+		device_authorization_endpoint: api_url("v0/device/code"),
+		jwks_uri: api_url(".well-known/jwks.json"),
+		scopes_supported: Scope::ALL.iter().map(|s| s.to_string()).collect(),
+		response_types_supported: &["code"],
+		grant_types_supported: GrantType::NAMES,
+		// public clients pass their client_id instead of authenticating
+		token_endpoint_auth_methods_supported: &["client_secret_basic", "none"],
+		// confidential clients have to authenticate to revoke their tokens, "none" is only accepted from public ones
+		revocation_endpoint_auth_methods_supported: &["client_secret_basic", "none"],
+		introspection_endpoint_auth_methods_supported: &["client_secret_basic"],
+		code_challenge_methods_supported: CodeChallengeMethod::ALL
+			.iter()
+			.map(|m| m.to_string())
+			.collect(),
+	}
+}
+
+pub async fn authorization_server_metadata(app_state: web::Data<AppState>) -> HttpResponse {
+	HttpResponse::Ok().json(server_metadata(&app_state))
+}
+
+// OpenID Connect Discovery 1.0
+#[derive(Debug, Serialize)]
+struct OpenIdConfiguration {
+	#[serde(flatten)]
+	metadata: AuthorizationServerMetadata,
+	userinfo_endpoint: Url,
+	subject_types_supported: &'static [&'static str],
+	id_token_signing_alg_values_supported: &'static [&'static str],
+	claims_supported: &'static [&'static str],
+}
+
+pub async fn openid_configuration(app_state: web::Data<AppState>) -> HttpResponse {
+	HttpResponse::Ok().json(OpenIdConfiguration {
+		metadata: server_metadata(&app_state),
+		userinfo_endpoint: app_state.api_url.join("v0/userinfo").unwrap(),
+		subject_types_supported: &["public"],
+		id_token_signing_alg_values_supported: &["RS256"],
+		claims_supported: &[
+			"iss",
+			"sub",
+			"aud",
+			"exp",
+			"iat",
+			"nonce",
+			"username",
+			"email",
+			"email_verified",
+		],
+	})
+}
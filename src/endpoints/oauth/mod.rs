@@ -1,13 +1,24 @@
 //! An implementation of OAuth 2.1 version 10 (draft-ietf-oauth-v2-1-10)
 //! Hopes to be spec-compliant
 
+use actix_web::web;
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, str::FromStr};
 
+use crate::AppState;
+
 pub mod authorization;
 pub mod clients;
+pub mod device;
+pub mod metadata;
+pub mod oidc;
 pub mod token;
 
+// the api's url, which tokens are issued by
+fn issuer(app_state: &web::Data<AppState>) -> String {
+	app_state.api_url.as_str().trim_end_matches('/').to_string()
+}
+
 fn is_true(b: &bool) -> bool {
 	*b
 }
@@ -28,6 +39,10 @@ pub enum CodeChallengeMethod {
 	S256,
 }
 
+impl CodeChallengeMethod {
+	pub const ALL: &'static [Self] = &[CodeChallengeMethod::Plain, CodeChallengeMethod::S256];
+}
+
 impl Display for CodeChallengeMethod {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
 		match self {
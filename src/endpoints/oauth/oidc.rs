@@ -0,0 +1,109 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::query;
+
+use crate::{
+	endpoints::oauth::issuer,
+	error::{ApiResult, BackendError},
+	middleware::Identity,
+	models::{
+		scope::Scope,
+		signingkey::{get_published_keys, get_signing_key},
+	},
+	AppState,
+};
+
+// how long an id token is valid for, the same as an access token
+const ID_TOKEN_LIFETIME: i64 = 600;
+
+#[derive(Debug, Serialize)]
+struct UserInfo {
+	sub: String,
+	username: String,
+	email: String,
+	email_verified: bool,
+}
+
+async fn get_user_info(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+) -> Result<Option<UserInfo>, BackendError> {
+	Ok(query!(
+		"SELECT username, email, email_verified AS `email_verified: bool` FROM User WHERE id = ?",
+		user_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	.map(|user| UserInfo {
+		sub: user_id.to_string(),
+		username: user.username,
+		email: user.email,
+		email_verified: user.email_verified,
+	}))
+}
+
+#[derive(Debug, Serialize)]
+struct IdTokenClaims {
+	iss: String,
+	aud: String,
+	exp: i64,
+	iat: i64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	nonce: Option<String>,
+	#[serde(flatten)]
+	user: UserInfo,
+}
+
+// the id token handed out alongside the access token when the openid scope is granted
+pub async fn issue_id_token(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+	client_id: u64,
+	nonce: Option<String>,
+) -> Result<Option<String>, BackendError> {
+	let Some(user) = get_user_info(app_state, user_id).await? else {
+		return Ok(None);
+	};
+
+	let now = Utc::now().timestamp();
+
+	let id_token = get_signing_key(&app_state.db)
+		.await?
+		.sign_jwt(&IdTokenClaims {
+			iss: issuer(app_state),
+			aud: client_id.to_string(),
+			exp: now + ID_TOKEN_LIFETIME,
+			iat: now,
+			nonce,
+			user,
+		})?;
+
+	Ok(Some(id_token))
+}
+
+pub async fn userinfo(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::OpenId) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let Some(user) = get_user_info(&app_state, user_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	Ok(HttpResponse::Ok().json(user))
+}
+
+pub async fn jwks(app_state: web::Data<AppState>) -> ApiResult {
+	let keys = get_published_keys(&app_state.db)
+		.await?
+		.iter()
+		.map(|key| key.jwk())
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(HttpResponse::Ok().json(json!({ "keys": keys })))
+}
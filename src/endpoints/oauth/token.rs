@@ -1,8 +1,13 @@
 use crate::{
-	endpoints::oauth::{CodeChallengeMethod, ErrorResponse},
+	endpoints::oauth::{
+		device::{MAX_POLL_INTERVAL, POLL_INTERVAL},
+		oidc::issue_id_token,
+		CodeChallengeMethod, ErrorResponse,
+	},
 	error::ApiResult,
-	middleware::Identity,
+	middleware::{lookup_bearer_token, scopes_from_string, Identity},
 	models::scope::Scope,
+	ws::reauthenticate_sessions,
 	AppState,
 };
 use actix_web::{web, HttpResponse};
@@ -14,28 +19,49 @@ use sha2::{Digest, Sha256};
 use sqlx::query;
 use std::{collections::HashSet, sync::LazyLock};
 
-#[serde_as]
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "grant_type")]
-pub enum GrantType {
-	AuthorizationCode {
+// the grant_type of each variant is listed here, so that the server's metadata can't drift from what is accepted
+macro_rules! grant_types {
+	($($variant:ident = $name:literal { $($(#[$field_meta:meta])* $field:ident: $ty:ty),* $(,)? }),* $(,)?) => {
+		#[serde_as]
+		#[derive(Debug, Deserialize)]
+		#[serde(tag = "grant_type")]
+		pub enum GrantType {
+			$(
+				#[serde(rename = $name)]
+				$variant { $($(#[$field_meta])* $field: $ty),* },
+			)*
+		}
+
+		impl GrantType {
+			// the grant_type of each variant, as advertised in the server's metadata
+			pub const NAMES: &'static [&'static str] = &[$($name),*];
+		}
+	};
+}
+
+grant_types! {
+	AuthorizationCode = "authorization_code" {
 		code: String,
 		code_verifier: Option<String>,
 		client_id: Option<u64>,
 	},
-	RefreshToken {
+	RefreshToken = "refresh_token" {
 		refresh_token: String,
 		#[serde_as(as = "Option<StringWithSeparator<SpaceSeparator, Scope>>")]
 		scope: Option<HashSet<Scope>>,
 		client_id: Option<u64>,
 	},
-	ClientCredentials {
+	ClientCredentials = "client_credentials" {
 		#[serde_as(as = "Option<StringWithSeparator<SpaceSeparator, Scope>>")]
 		scope: Option<HashSet<Scope>>,
 	},
+	DeviceCode = "urn:ietf:params:oauth:grant-type:device_code" {
+		device_code: String,
+		client_id: Option<u64>,
+	},
 }
 
-static TOKEN_GENERATOR: LazyLock<CuidConstructor> =
+pub(super) static TOKEN_GENERATOR: LazyLock<CuidConstructor> =
 	LazyLock::new(|| CuidConstructor::new().with_length(64));
 
 #[derive(Debug, Serialize)]
@@ -45,9 +71,14 @@ struct TokenResponse {
 	expires_in: u16,
 	refresh_token: Option<String>,
 	scope: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	id_token: Option<String>,
 }
 
-fn get_client_id(client_id: Option<u64>, identity: Option<Identity>) -> Result<u64, HttpResponse> {
+pub(super) fn get_client_id(
+	client_id: Option<u64>,
+	identity: Option<Identity>,
+) -> Result<u64, HttpResponse> {
 	match (client_id, identity) {
 		(Some(id), None) => Ok(id),
 		(None, Some(identity)) => match identity {
@@ -86,7 +117,7 @@ pub async fn exchange_token(
 			};
 
 			let Some(record) = query!(
-                "SELECT user_id, scope, code_challenge, code_challenge_method FROM AuthorizationCode WHERE id = ? AND client_id = ? AND expires_at > NOW()",
+                "SELECT user_id, scope, code_challenge, code_challenge_method, nonce FROM AuthorizationCode WHERE id = ? AND client_id = ? AND expires_at > NOW()",
                 code,
                 client_id
             )
@@ -164,12 +195,19 @@ pub async fn exchange_token(
             .execute(&app_state.db)
             .await?;
 
+			let id_token = if scopes_from_string(&record.scope).contains(&Scope::OpenId) {
+				issue_id_token(&app_state, record.user_id, client_id, record.nonce).await?
+			} else {
+				None
+			};
+
 			Ok(HttpResponse::Ok().json(TokenResponse {
 				access_token,
 				token_type: "Bearer",
 				expires_in: 600,
 				refresh_token: Some(refresh_token),
 				scope: record.scope.replace(',', " "),
+				id_token,
 			}))
 		}
 		GrantType::RefreshToken {
@@ -225,8 +263,9 @@ pub async fn exchange_token(
 			let access_token = format!("u.{}", TOKEN_GENERATOR.create_id());
 			let refresh_token = format!("u.{}", TOKEN_GENERATOR.create_id());
 
+			// created_at is reset along with the tokens, as it's used as the access token's issue time
 			query!(
-                "UPDATE ClientUserTokens SET access_token = ?, refresh_token = ?, scope = ?, expires_at = DEFAULT, access_expires_at = DEFAULT WHERE user_id = ? AND client_id = ?",
+                "UPDATE ClientUserTokens SET access_token = ?, refresh_token = ?, scope = ?, created_at = DEFAULT, expires_at = DEFAULT, access_expires_at = DEFAULT WHERE user_id = ? AND client_id = ?",
                 access_token,
                 refresh_token,
                 new_scope
@@ -240,6 +279,13 @@ pub async fn exchange_token(
             .execute(&app_state.db)
             .await?;
 
+			// the nonce only applies to the id token issued for the authorization code
+			let id_token = if new_scope.contains(&Scope::OpenId) {
+				issue_id_token(&app_state, record.user_id, client_id, None).await?
+			} else {
+				None
+			};
+
 			Ok(HttpResponse::Ok().json(TokenResponse {
 				access_token,
 				token_type: "Bearer",
@@ -250,6 +296,7 @@ pub async fn exchange_token(
 					.map(|s| s.to_string())
 					.collect::<Vec<String>>()
 					.join(" "),
+				id_token,
 			}))
 		}
 		GrantType::ClientCredentials { scope } => {
@@ -309,7 +356,317 @@ pub async fn exchange_token(
 				expires_in: 600,
 				refresh_token: None,
 				scope: scope.replace(',', " "),
+				id_token: None,
+			}))
+		}
+		GrantType::DeviceCode {
+			device_code,
+			client_id,
+		} => {
+			let client_id = match get_client_id(client_id, identity) {
+				Ok(id) => id,
+				Err(resp) => return Ok(resp),
+			};
+
+			let Some(record) = query!(
+                "SELECT user_id, scope, denied AS `denied: bool`, expires_at <= NOW() AS `expired: bool`, COALESCE(last_polled_at > TIMESTAMPADD(SECOND, -poll_interval, NOW()), FALSE) AS `too_fast: bool` FROM DeviceCode WHERE id = ? AND client_id = ?",
+                device_code,
+                client_id
+            )
+            .fetch_optional(&app_state.db)
+            .await? else {
+                return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+                    redirect: false,
+                    error: "invalid_grant",
+                    error_description: "Invalid device code"
+                }));
+            };
+
+			if record.expired {
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					redirect: false,
+					error: "expired_token",
+					error_description: "Device code has expired",
+				}));
+			}
+
+			// devices polling faster than they're allowed to have to wait longer from then on, up to a limit
+			if record.too_fast {
+				query!(
+                    "UPDATE DeviceCode SET poll_interval = LEAST(poll_interval + ?, ?), last_polled_at = NOW() WHERE id = ?",
+                    POLL_INTERVAL,
+                    MAX_POLL_INTERVAL,
+                    device_code
+                )
+                .execute(&app_state.db)
+                .await?;
+
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					redirect: false,
+					error: "slow_down",
+					error_description: "Polling too frequently",
+				}));
+			}
+
+			if record.denied {
+				query!("DELETE FROM DeviceCode WHERE id = ?", device_code)
+					.execute(&app_state.db)
+					.await?;
+
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					redirect: false,
+					error: "access_denied",
+					error_description: "The user denied the request",
+				}));
+			}
+
+			let Some(user_id) = record.user_id else {
+				query!(
+					"UPDATE DeviceCode SET last_polled_at = NOW() WHERE id = ?",
+					device_code
+				)
+				.execute(&app_state.db)
+				.await?;
+
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					redirect: false,
+					error: "authorization_pending",
+					error_description: "The user hasn't approved the request yet",
+				}));
+			};
+
+			// a device code can only be exchanged once
+			if query!(
+				"DELETE FROM DeviceCode WHERE id = ? AND user_id IS NOT NULL",
+				device_code
+			)
+			.execute(&app_state.db)
+			.await?
+			.rows_affected()
+				== 0
+			{
+				return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+					redirect: false,
+					error: "invalid_grant",
+					error_description: "Invalid device code",
+				}));
+			}
+
+			let access_token = format!("u.{}", TOKEN_GENERATOR.create_id());
+			let refresh_token = format!("u.{}", TOKEN_GENERATOR.create_id());
+
+			query!(
+                "REPLACE INTO ClientUserTokens (user_id, client_id, created_at, access_expires_at, expires_at, auth_code, access_token, refresh_token, scope) VALUES (?, ?, DEFAULT, DEFAULT, DEFAULT, NULL, ?, ?, ?)",
+                user_id,
+                client_id,
+                access_token,
+                refresh_token,
+                record.scope
+            )
+            .execute(&app_state.db)
+            .await?;
+
+			let id_token = if scopes_from_string(&record.scope).contains(&Scope::OpenId) {
+				issue_id_token(&app_state, user_id, client_id, None).await?
+			} else {
+				None
+			};
+
+			Ok(HttpResponse::Ok().json(TokenResponse {
+				access_token,
+				token_type: "Bearer",
+				expires_in: 600,
+				refresh_token: Some(refresh_token),
+				scope: record.scope.replace(',', " "),
+				id_token,
 			}))
 		}
 	}
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenTypeHint {
+	AccessToken,
+	RefreshToken,
+	// unknown hints are ignored (RFC 7009 section 2.1)
+	#[serde(other)]
+	Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenBody {
+	token: String,
+	token_type_hint: Option<TokenTypeHint>,
+	client_id: Option<u64>,
+}
+
+// returns the user id and access token of the ClientUserTokens row the token belongs to
+pub(super) async fn find_user_token(
+	app_state: &web::Data<AppState>,
+	client_id: u64,
+	token: &str,
+	token_type_hint: Option<&TokenTypeHint>,
+) -> Result<Option<(u64, String)>, sqlx::Error> {
+	let by_access_token = async {
+		query!(
+			"SELECT user_id, access_token FROM ClientUserTokens WHERE access_token = ? AND client_id = ?",
+			token,
+			client_id
+		)
+		.fetch_optional(&app_state.db)
+		.await
+		.map(|record| record.map(|record| (record.user_id, record.access_token)))
+	};
+
+	let by_refresh_token = async {
+		query!(
+			"SELECT user_id, access_token FROM ClientUserTokens WHERE refresh_token = ? AND client_id = ?",
+			token,
+			client_id
+		)
+		.fetch_optional(&app_state.db)
+		.await
+		.map(|record| record.map(|record| (record.user_id, record.access_token)))
+	};
+
+	// the hint only decides which kind of token is looked for first
+	if matches!(token_type_hint, Some(TokenTypeHint::RefreshToken)) {
+		match by_refresh_token.await? {
+			Some(record) => Ok(Some(record)),
+			None => by_access_token.await,
+		}
+	} else {
+		match by_access_token.await? {
+			Some(record) => Ok(Some(record)),
+			None => by_refresh_token.await,
+		}
+	}
+}
+
+pub async fn revoke_token(
+	app_state: web::Data<AppState>,
+	body: web::Form<RevokeTokenBody>,
+	identity: web::ReqData<Option<Identity>>,
+) -> ApiResult {
+	let identity = identity.into_inner();
+	let authenticated = identity.is_some();
+
+	let client_id = match get_client_id(body.client_id, identity) {
+		Ok(id) => id,
+		Err(resp) => return Ok(resp),
+	};
+
+	// only public clients may revoke with just their client_id, confidential ones have to authenticate (RFC 7009 section 2.1)
+	if !authenticated
+		&& !query!(
+			"SELECT EXISTS(SELECT 1 FROM Client WHERE id = ? AND secret IS NULL) AS `public: bool`",
+			client_id
+		)
+		.fetch_one(&app_state.db)
+		.await?
+		.public
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			redirect: false,
+			error: "invalid_client",
+			error_description: "Invalid client",
+		}));
+	}
+
+	// tokens which are invalid or belong to another client are treated as already revoked
+	if body.token.starts_with("u.") {
+		let Some((user_id, access_token)) = find_user_token(
+			&app_state,
+			client_id,
+			&body.token,
+			body.token_type_hint.as_ref(),
+		)
+		.await?
+		else {
+			return Ok(HttpResponse::Ok().finish());
+		};
+
+		// revoking either token ends the whole grant, as both live in the same row
+		query!(
+			"DELETE FROM ClientUserTokens WHERE user_id = ? AND client_id = ?",
+			user_id,
+			client_id
+		)
+		.execute(&app_state.db)
+		.await?;
+
+		reauthenticate_sessions(&app_state, user_id, &access_token);
+	} else {
+		query!(
+			"DELETE FROM ClientToken WHERE access_token = ? AND client_id = ?",
+			body.token,
+			client_id
+		)
+		.execute(&app_state.db)
+		.await?;
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IntrospectTokenBody {
+	token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct IntrospectedToken {
+	scope: String,
+	client_id: String,
+	sub: String,
+	token_type: &'static str,
+	exp: i64,
+	iat: i64,
+}
+
+#[derive(Debug, Serialize)]
+struct IntrospectionResponse {
+	active: bool,
+	#[serde(flatten)]
+	token: Option<IntrospectedToken>,
+}
+
+pub async fn introspect_token(
+	app_state: web::Data<AppState>,
+	body: web::Form<IntrospectTokenBody>,
+	identity: web::ReqData<Identity>,
+) -> ApiResult {
+	// any authenticated client may introspect, so that resource servers can check the tokens issued to other clients
+	let Identity::Client(_) = identity.into_inner() else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let token = lookup_bearer_token(&body.token, &app_state)
+		.await?
+		.and_then(|token| {
+			let (sub, scopes) = match token.identity {
+				Identity::UserByClient((user_id, scopes)) => (user_id, scopes),
+				Identity::ClientByClient((client_id, scopes)) => (client_id, scopes),
+				Identity::User(_) | Identity::Client(_) => return None,
+			};
+
+			Some(IntrospectedToken {
+				scope: scopes
+					.iter()
+					.map(|s| s.to_string())
+					.collect::<Vec<String>>()
+					.join(" "),
+				client_id: token.client_id.to_string(),
+				sub: sub.to_string(),
+				token_type: "Bearer",
+				exp: token.expires_at.timestamp(),
+				iat: token.issued_at.timestamp(),
+			})
+		});
+
+	Ok(HttpResponse::Ok().json(IntrospectionResponse {
+		active: token.is_some(),
+		token,
+	}))
+}
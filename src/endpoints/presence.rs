@@ -0,0 +1,134 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::query;
+use validator::Validate;
+
+use crate::{
+	error::{ApiResult, BackendError, ErrorResponse},
+	middleware::Identity,
+	models::{
+		presence::{Presence, PresenceStatus},
+		scope::{ReadWrite, Scope},
+		user::get_associates,
+	},
+	ws::{is_connected, send_ephemeral_updates, WsUpdateEvent},
+	AppState,
+};
+
+pub const MAX_CUSTOM_STATUS_LENGTH: usize = 128;
+
+// tells the user's associates how the user appears to them, and the user's own sessions what was actually set.
+// presence is only relevant while it's current, so resumed sessions are expected to refetch it instead of replaying it
+pub async fn broadcast_presence(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+) -> Result<(), BackendError> {
+	let Some(row) = query!(
+		"SELECT status, custom_status FROM User WHERE id = ?",
+		user_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(());
+	};
+
+	let status = row.status.parse::<PresenceStatus>().unwrap();
+	let connected = is_connected(app_state, user_id);
+
+	let mut associates = get_associates(&app_state.db, user_id).await?;
+	associates.remove(&user_id);
+
+	send_ephemeral_updates(
+		[WsUpdateEvent::PresenceUpdate(Presence::visible(
+			user_id,
+			status,
+			row.custom_status.clone(),
+			connected,
+		))],
+		app_state,
+		associates,
+	);
+
+	send_ephemeral_updates(
+		[WsUpdateEvent::PresenceUpdate(Presence {
+			user_id,
+			status: if connected {
+				status
+			} else {
+				PresenceStatus::Offline
+			},
+			custom_status: row.custom_status,
+		})],
+		app_state,
+		[user_id],
+	);
+
+	Ok(())
+}
+
+// shared by the REST endpoint and the gateway, None leaves the respective value unchanged
+pub async fn set_presence(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+	status: Option<PresenceStatus>,
+	custom_status: Option<Option<String>>,
+) -> Result<(), BackendError> {
+	if let Some(status) = status {
+		query!(
+			"UPDATE User SET status = ? WHERE id = ?",
+			status.to_string(),
+			user_id
+		)
+		.execute(&app_state.db)
+		.await?;
+	}
+
+	if let Some(custom_status) = custom_status {
+		query!(
+			"UPDATE User SET custom_status = ? WHERE id = ?",
+			custom_status.filter(|custom_status| !custom_status.is_empty()),
+			user_id
+		)
+		.execute(&app_state.db)
+		.await?;
+	}
+
+	broadcast_presence(app_state, user_id).await
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdatePresenceBody {
+	status: Option<PresenceStatus>,
+	#[serde(default, deserialize_with = "super::deserialize_some_trimmed")]
+	#[validate(length(max = 128))]
+	custom_status: Option<Option<String>>,
+}
+
+pub async fn update_presence(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	body: web::Json<UpdatePresenceBody>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Profile(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let body = body.into_inner();
+
+	if body.status == Some(PresenceStatus::Offline) {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Use the invisible status to appear offline".to_string(),
+		}));
+	}
+
+	if body.status.is_none() && body.custom_status.is_none() {
+		return Ok(HttpResponse::BadRequest().finish());
+	}
+
+	set_presence(&app_state, user_id, body.status, body.custom_status).await?;
+
+	Ok(HttpResponse::Ok().finish())
+}
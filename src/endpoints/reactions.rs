@@ -0,0 +1,211 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::{query, query_as};
+use validator::Validate;
+
+use crate::{
+	endpoints::messages::{channel_recipients, get_channel_access},
+	error::{ApiResult, ErrorResponse},
+	middleware::Identity,
+	models::{
+		permissions::Permissions,
+		reaction::is_valid_emoji,
+		scope::{ReadWrite, Scope},
+		user::User,
+	},
+	ws::{send_updates, WsUpdateEvent},
+	AppState,
+};
+
+pub const MAX_REACTIONS: i64 = 20;
+
+pub async fn add_reaction(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64, String)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, message_id, emoji) = path.into_inner();
+
+	if !is_valid_emoji(&emoji) {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "invalid_emoji".to_string(),
+		}));
+	}
+
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	if access.server_id.is_some() && !access.permissions.contains(Permissions::ADD_REACTIONS) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if access.archived {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "thread_archived".to_string(),
+		}));
+	}
+
+	let recipients = access.recipients(&app_state).await?;
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM ChannelMessage WHERE id = ? AND channel_id = ?) AS `exists: bool`",
+		message_id,
+		channel_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	// reacting with an emoji which is already present doesn't count towards the limit
+	if query!(
+		"SELECT COUNT(DISTINCT emoji) >= ? AND COALESCE(SUM(emoji = ?), 0) = 0 AS `over_limit: bool` FROM ChannelMessageReaction WHERE message_id = ?",
+		MAX_REACTIONS,
+		emoji,
+		message_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.over_limit
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "reaction_limit_reached".to_string(),
+		}));
+	}
+
+	let result = query!(
+		"INSERT IGNORE INTO ChannelMessageReaction (message_id, user_id, emoji) VALUES (?, ?, ?)",
+		message_id,
+		user_id,
+		emoji
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if result.rows_affected() > 0 {
+		send_updates(
+			[WsUpdateEvent::ReactionAdd {
+				channel_id,
+				message_id,
+				user_id,
+				emoji,
+			}],
+			&app_state,
+			recipients,
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn remove_reaction(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64, String)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, message_id, emoji) = path.into_inner();
+
+	let Some(access) = get_channel_access(&app_state, channel_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	if access.archived {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "thread_archived".to_string(),
+		}));
+	}
+
+	let recipients = access.recipients(&app_state).await?;
+
+	let result = query!(
+		r#"DELETE ChannelMessageReaction
+FROM ChannelMessageReaction
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageReaction.message_id
+WHERE ChannelMessageReaction.message_id = ? AND ChannelMessageReaction.emoji = ? AND ChannelMessageReaction.user_id = ? AND ChannelMessage.channel_id = ?
+"#,
+		message_id,
+		emoji,
+		user_id,
+		channel_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if result.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	send_updates(
+		[WsUpdateEvent::ReactionRemove {
+			channel_id,
+			message_id,
+			user_id,
+			emoji,
+		}],
+		&app_state,
+		recipients,
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct GetReactionsQuery {
+	#[validate(range(min = 1, max = 100))]
+	limit: Option<u64>,
+	after: Option<u64>,
+}
+
+pub async fn get_reactions(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64, String)>,
+	query: web::Query<GetReactionsQuery>,
+) -> ApiResult {
+	query.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (channel_id, message_id, emoji) = path.into_inner();
+
+	if channel_recipients(&app_state, channel_id, user_id, Permissions::VIEW_CHANNEL)
+		.await?
+		.is_none()
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let users = query_as!(
+		User,
+		r#"SELECT User.id, User.username, User.display_name
+FROM ChannelMessageReaction
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageReaction.message_id
+INNER JOIN User ON User.id=ChannelMessageReaction.user_id
+WHERE ChannelMessageReaction.message_id = ? AND ChannelMessageReaction.emoji = ? AND ChannelMessage.channel_id = ? AND User.id > ?
+ORDER BY User.id
+LIMIT ?
+"#,
+		message_id,
+		emoji,
+		channel_id,
+		query.after.unwrap_or(0),
+		query.limit.unwrap_or(25)
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(users))
+}
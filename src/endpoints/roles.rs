@@ -0,0 +1,492 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::query;
+use std::sync::Mutex;
+use validator::Validate;
+
+use crate::{
+	error::{ApiResult, BackendError, ErrorResponse},
+	middleware::Identity,
+	models::{
+		permissions::{get_server_permissions, Permissions},
+		role::ServerRole,
+		scope::{ReadWrite, Scope},
+	},
+	update_structure,
+	ws::{send_updates, WsUpdateEvent},
+	AppState,
+};
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateRoleBody {
+	#[serde(deserialize_with = "super::trim_string")]
+	#[validate(length(min = 1, max = 32))]
+	name: String,
+	#[serde(default)]
+	permissions: Permissions,
+}
+
+pub async fn create_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	body: web::Json<CreateRoleBody>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	// members can't hand out permissions they don't have themselves
+	if !get_server_permissions(&app_state.db, server_id, user_id)
+		.await?
+		.is_some_and(|permissions| {
+			permissions.contains(Permissions::MANAGE_ROLES | body.permissions)
+		}) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if query!(
+		"SELECT COUNT(*) > 100 AS `over_limit: bool` FROM ServerRole WHERE server_id = ?",
+		server_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.over_limit
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "role_limit_reached".to_string(),
+		}));
+	}
+
+	let role_id = {
+		let mut generator = generator.lock().unwrap();
+		generator.generate()
+	};
+
+	query!(
+		"INSERT INTO ServerRole (id, server_id, name, permissions) VALUES (?, ?, ?, ?)",
+		role_id,
+		server_id,
+		body.name,
+		body.permissions.bits()
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	let role = ServerRole {
+		id: role_id,
+		server_id,
+		name: body.name.clone(),
+		permissions: body.permissions,
+	};
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::RoleCreate(role.clone())],
+			&app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(HttpResponse::Created().json(role))
+}
+
+pub async fn get_roles(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = path.into_inner();
+
+	if !query!(
+        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
+        server_id,
+        user_id
+    )
+    .fetch_one(&app_state.db)
+    .await?
+        .exists
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+	let roles = query!(
+		"SELECT id, name, permissions FROM ServerRole WHERE server_id = ?",
+		server_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(
+		roles
+			.into_iter()
+			.map(|row| ServerRole {
+				id: row.id,
+				server_id,
+				name: row.name,
+				permissions: Permissions::from_bits_truncate(row.permissions),
+			})
+			.collect::<Vec<_>>(),
+	))
+}
+
+pub async fn get_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, role_id) = path.into_inner();
+
+	if !query!(
+        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
+        server_id,
+        user_id
+    )
+    .fetch_one(&app_state.db)
+    .await?
+        .exists
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+	let Some(role) = query!(
+		"SELECT name, permissions FROM ServerRole WHERE id = ? AND server_id = ?",
+		role_id,
+		server_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	Ok(HttpResponse::Ok().json(ServerRole {
+		id: role_id,
+		server_id,
+		name: role.name,
+		permissions: Permissions::from_bits_truncate(role.permissions),
+	}))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateRoleBody {
+	#[serde(default, deserialize_with = "super::trim_opt_string")]
+	#[validate(length(min = 1, max = 32))]
+	name: Option<String>,
+	permissions: Option<Permissions>,
+}
+
+pub async fn update_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	body: web::Json<UpdateRoleBody>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, role_id) = path.into_inner();
+
+	if role_id == server_id && body.name.is_some() {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Cannot rename the @everyone role".to_string(),
+		}));
+	}
+
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let Some(role) = query!(
+		"SELECT permissions FROM ServerRole WHERE id = ? AND server_id = ?",
+		role_id,
+		server_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	// neither the current nor the new permissions may exceed the member's own
+	if !permissions.contains(
+		Permissions::MANAGE_ROLES
+			| Permissions::from_bits_truncate(role.permissions)
+			| body.permissions.unwrap_or_default(),
+	) {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let (mut pushed, mut query_builder) = update_structure!(raw "ServerRole", body, name);
+
+	if let Some(permissions) = body.permissions {
+		if pushed {
+			query_builder.push(", ");
+		}
+		pushed = true;
+		query_builder
+			.push("permissions = ")
+			.push_bind(permissions.bits());
+	}
+
+	if !pushed {
+		return Ok(HttpResponse::BadRequest().finish());
+	}
+
+	query_builder
+		.push(" WHERE id = ")
+		.push_bind(role_id)
+		.push(" AND server_id = ")
+		.push_bind(server_id)
+		.build()
+		.execute(&app_state.db)
+		.await?;
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::RoleUpdate {
+				id: role_id,
+				server_id,
+				name: body.name.clone(),
+				permissions: body.permissions,
+			}],
+			&app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn delete_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, role_id) = path.into_inner();
+
+	if role_id == server_id {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Cannot delete the @everyone role".to_string(),
+		}));
+	}
+
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let Some(role) = query!(
+		"SELECT permissions FROM ServerRole WHERE id = ? AND server_id = ?",
+		role_id,
+		server_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if !permissions
+		.contains(Permissions::MANAGE_ROLES | Permissions::from_bits_truncate(role.permissions))
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	query!(
+		"DELETE FROM ServerRole WHERE id = ? AND server_id = ?",
+		role_id,
+		server_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	// overwrites can target roles or members, so they aren't cleaned up by a foreign key
+	query!(
+		"DELETE FROM ChannelPermissionOverwrite WHERE target_id = ? AND kind = 'role'",
+		role_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::RoleDelete {
+				id: role_id,
+				server_id,
+			}],
+			&app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+async fn send_member_roles_update(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	member_id: u64,
+) -> Result<(), BackendError> {
+	let roles = query!(
+		"SELECT role_id FROM ServerMemberRole WHERE server_id = ? AND user_id = ?",
+		server_id,
+		member_id
+	)
+	.fetch_all(&app_state.db)
+	.await?
+	.into_iter()
+	.map(|row| row.role_id)
+	.collect();
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::MemberUpdate {
+				server_id,
+				user_id: member_id,
+				nickname: None,
+				roles: Some(roles),
+				timed_out_until: None,
+			}],
+			app_state,
+			members.iter().copied(),
+		);
+	}
+
+	Ok(())
+}
+
+// the roles a member may assign or remove are limited to the permissions they hold
+async fn can_manage_role(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	user_id: u64,
+	role_id: u64,
+) -> Result<Option<bool>, BackendError> {
+	let Some(permissions) = get_server_permissions(&app_state.db, server_id, user_id).await? else {
+		return Ok(Some(false));
+	};
+
+	let Some(role) = query!(
+		"SELECT permissions FROM ServerRole WHERE id = ? AND server_id = ?",
+		role_id,
+		server_id
+	)
+	.fetch_optional(&app_state.db)
+	.await?
+	else {
+		return Ok(None);
+	};
+
+	Ok(Some(permissions.contains(
+		Permissions::MANAGE_ROLES | Permissions::from_bits_truncate(role.permissions),
+	)))
+}
+
+pub async fn add_member_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, member_id, role_id) = path.into_inner();
+
+	// every member implicitly has the @everyone role
+	if role_id == server_id {
+		return Ok(HttpResponse::BadRequest().finish());
+	}
+
+	match can_manage_role(&app_state, server_id, user_id, role_id).await? {
+		Some(true) => {}
+		Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+		None => return Ok(HttpResponse::NotFound().finish()),
+	}
+
+	if !query!(
+        "SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
+        server_id,
+        member_id
+    )
+    .fetch_one(&app_state.db)
+    .await?
+        .exists
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+	let result = query!(
+		"INSERT IGNORE INTO ServerMemberRole (server_id, user_id, role_id) VALUES (?, ?, ?)",
+		server_id,
+		member_id,
+		role_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if result.rows_affected() > 0 {
+		send_member_roles_update(&app_state, server_id, member_id).await?;
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn remove_member_role(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, member_id, role_id) = path.into_inner();
+
+	match can_manage_role(&app_state, server_id, user_id, role_id).await? {
+		Some(true) => {}
+		Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+		None => return Ok(HttpResponse::NotFound().finish()),
+	}
+
+	let result = query!(
+		"DELETE FROM ServerMemberRole WHERE server_id = ? AND user_id = ? AND role_id = ?",
+		server_id,
+		member_id,
+		role_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if result.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	send_member_roles_update(&app_state, server_id, member_id).await?;
+
+	Ok(HttpResponse::Ok().finish())
+}
@@ -1,23 +1,39 @@
 use crate::{
-	error::ApiResult,
+	endpoints::{
+		members::send_member_removal,
+		webauthn::{verify_passkey, AUTHENTICATION_ID_COOKIE_NAME},
+		AuditLogReason,
+	},
+	error::{ApiResult, ErrorResponse},
 	middleware::Identity,
 	models::{
+		attachment::attachment_key,
+		auditlog::{insert_audit_log_entry, AuditLogAction, AuditLogChange, AuditLogEntry},
 		channel::{Channel, ChannelKind},
+		permissions::{
+			apply_overwrites, get_all_member_permissions, get_member_overwrites,
+			has_server_permission, Permissions,
+		},
+		readstate::get_server_read_states,
+		role::ServerRole,
 		scope::{ReadWrite, Scope},
 		server::Server,
 		servermember::ServerMember,
 	},
+	storage::delete_in_background,
 	update_structure,
 	ws::{send_updates, WsUpdateEvent},
 	AppState,
 };
-use actix_web::{web, HttpResponse};
+use actix_web::{web, HttpRequest, HttpResponse};
 use dashmap::mapref::entry::Entry;
 use serde::Deserialize;
 use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
 use sqlx::query;
 use std::sync::Mutex;
 use validator::Validate;
+use webauthn_rs::prelude::PublicKeyCredential;
 
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreateServerBody {
@@ -73,6 +89,16 @@ pub async fn create_server(
 	.execute(&mut *tx)
 	.await?;
 
+	// the @everyone role shares its id with the server
+	query!(
+		"INSERT INTO ServerRole (id, server_id, name, permissions) VALUES (?, ?, '@everyone', ?)",
+		server_id,
+		server_id,
+		Permissions::DEFAULT.bits()
+	)
+	.execute(&mut *tx)
+	.await?;
+
 	tx.commit().await?;
 
 	let server = Server {
@@ -86,7 +112,13 @@ pub async fn create_server(
 		name: "general".to_string(),
 		kind: ChannelKind::Text,
 		server_id: Some(server_id),
+		parent_id: None,
+		position: Some(0),
 		user: None,
+		recipients: None,
+		owner_id: None,
+		thread: None,
+		read_state: None,
 	};
 
 	let member = ServerMember {
@@ -95,6 +127,18 @@ pub async fn create_server(
 		nickname: None,
 		created_at,
 		user: None,
+		roles: Some(vec![]),
+		timed_out_until: None,
+		temporary: false,
+		invite_id: None,
+		presence: None,
+	};
+
+	let everyone_role = ServerRole {
+		id: server_id,
+		server_id,
+		name: "@everyone".to_string(),
+		permissions: Permissions::DEFAULT,
 	};
 
 	if app_state
@@ -122,6 +166,7 @@ pub async fn create_server(
 	let mut value = serde_json::to_value(server).unwrap();
 	value["channels"] = json!([channel]);
 	value["members"] = json!([member]);
+	value["roles"] = json!([everyone_role]);
 
 	Ok(HttpResponse::Created().json(value))
 }
@@ -141,12 +186,47 @@ pub async fn get_servers(
     .fetch_all(&app_state.db)
     .await?;
 
-	Ok(HttpResponse::Ok().json(
-        servers
-            .into_iter()
-            .map(|row| json!({ "id": row.id.to_string(), "name": row.name, "owner_id": row.owner_id.to_string() }))
-            .collect::<Vec<_>>(),
-    ))
+	let mut permissions = get_all_member_permissions(&app_state.db, user_id).await?;
+	let overwrites = get_member_overwrites(&app_state.db, user_id).await?;
+	let mut read_states = get_server_read_states(&app_state.db, user_id).await?;
+
+	let mut response = Vec::with_capacity(servers.len());
+
+	for row in servers {
+		let (unread_count, mention_count) = match permissions.remove(&row.id) {
+			Some((permissions, roles)) => {
+				// channels the member can't view don't count towards the totals
+				read_states
+					.remove(&row.id)
+					.unwrap_or_default()
+					.into_iter()
+					.filter(|read_state| {
+						apply_overwrites(
+							permissions,
+							row.id,
+							user_id,
+							&roles,
+							overwrites
+								.get(&read_state.channel_id)
+								.map(Vec::as_slice)
+								.unwrap_or_default(),
+						)
+						.contains(Permissions::VIEW_CHANNEL)
+					})
+					.fold((0, 0), |(unread, mentions), read_state| {
+						(
+							unread + read_state.unread_count,
+							mentions + read_state.mention_count,
+						)
+					})
+			}
+			None => (0, 0),
+		};
+
+		response.push(json!({ "id": row.id.to_string(), "name": row.name, "owner_id": row.owner_id.to_string(), "unread_count": unread_count, "mention_count": mention_count }));
+	}
+
+	Ok(HttpResponse::Ok().json(response))
 }
 
 pub async fn get_server(
@@ -160,7 +240,7 @@ pub async fn get_server(
 
 	let server_id = server_id.into_inner();
 	let server = query!(
-        "SELECT Server.id, Server.name, Server.owner_id FROM Server INNER JOIN ServerMember ON Server.id=ServerMember.server_id WHERE ServerMember.user_id = ? AND Server.id = ?",
+        "SELECT Server.id, Server.name, Server.owner_id, Server.vanity_code FROM Server INNER JOIN ServerMember ON Server.id=ServerMember.server_id WHERE ServerMember.user_id = ? AND Server.id = ?",
         user_id,
         server_id
     )
@@ -168,7 +248,7 @@ pub async fn get_server(
     .await?;
 
 	if let Some(server) = server {
-		Ok(HttpResponse::Ok().json(json!({ "id": server.id.to_string(), "name": server.name, "owner_id": server.owner_id.to_string() })))
+		Ok(HttpResponse::Ok().json(json!({ "id": server.id.to_string(), "name": server.name, "owner_id": server.owner_id.to_string(), "vanity_code": server.vanity_code })))
 	} else {
 		Ok(HttpResponse::NotFound().finish())
 	}
@@ -179,13 +259,19 @@ pub struct UpdateServerBody {
 	#[serde(default, deserialize_with = "super::trim_opt_string")]
 	#[validate(length(min = 2, max = 32))]
 	name: Option<String>,
+	// resolves like an invite code, null removes it
+	#[validate(length(min = 2, max = 32))]
+	#[serde(default, deserialize_with = "super::deserialize_some_trimmed")]
+	vanity_code: Option<Option<String>>,
 }
 
 pub async fn update_server(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
 	server_id: web::Path<u64>,
 	body: web::Json<UpdateServerBody>,
+	reason: AuditLogReason,
 ) -> ApiResult {
 	body.validate()?;
 	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
@@ -194,30 +280,91 @@ pub async fn update_server(
 
 	let server_id = server_id.into_inner();
 
-	if !query!(
-		"SELECT EXISTS(SELECT 1 FROM Server WHERE id = ? AND owner_id = ?) AS `exists: bool`",
+	if !has_server_permission(
+		&app_state.db,
 		server_id,
-		user_id
+		user_id,
+		Permissions::MANAGE_SERVER,
 	)
-	.fetch_one(&app_state.db)
 	.await?
-	.exists
 	{
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
-	update_structure!("Server", body, name)
+	if body
+		.vanity_code
+		.as_ref()
+		.and_then(Option::as_ref)
+		.is_some_and(|code| !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "Vanity codes may only contain letters, numbers and dashes".to_string(),
+		}));
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	// locked so that the audit log records what was actually changed, even with concurrent updates
+	let Some(server) = query!(
+		"SELECT name, vanity_code FROM Server WHERE id = ? FOR UPDATE",
+		server_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	match update_structure!("Server", body, name, vanity_code)
 		.push(" WHERE id = ")
 		.push_bind(server_id)
 		.build()
-		.execute(&app_state.db)
-		.await?;
+		.execute(&mut *tx)
+		.await
+	{
+		Err(e)
+			if e.as_database_error()
+				.is_some_and(|e| e.is_unique_violation()) =>
+		{
+			return Ok(HttpResponse::Conflict().json(ErrorResponse {
+				error: "vanity_code_taken".to_string(),
+			}));
+		}
+		r => r?,
+	};
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ServerUpdate,
+		server_id,
+		reason.0,
+	)
+	.with_changes(
+		body.name
+			.as_ref()
+			.map(|name| AuditLogChange::new("name", Some(&server.name), Some(name)))
+			.into_iter()
+			.chain(body.vanity_code.as_ref().map(|vanity_code| {
+				AuditLogChange::new(
+					"vanity_code",
+					server.vanity_code.as_ref(),
+					vanity_code.as_ref(),
+				)
+			})),
+	);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
 
 	if let Some(members) = app_state.server_connections.get(&server_id) {
 		send_updates(
 			[WsUpdateEvent::ServerUpdate {
 				id: server_id,
 				name: body.name.clone(),
+				owner_id: None,
 			}],
 			&app_state,
 			members.iter().copied(),
@@ -227,6 +374,149 @@ pub async fn update_server(
 	Ok(HttpResponse::Ok().finish())
 }
 
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct TransferServerBody {
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+	owner_id: u64,
+	// the owner confirms the transfer with either their password,
+	// or a passkey assertion for an authentication started through /webauthn/auth-start
+	password: Option<String>,
+	passkey: Option<PublicKeyCredential>,
+}
+
+pub async fn transfer_server(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	request: HttpRequest,
+	server_id: web::Path<u64>,
+	body: web::Json<TransferServerBody>,
+	reason: AuditLogReason,
+) -> ApiResult {
+	// only the user themselves can confirm with their credentials
+	let Identity::User(user_id) = identity.into_inner() else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let server_id = server_id.into_inner();
+
+	let Some(server) = query!("SELECT owner_id FROM Server WHERE id = ?", server_id)
+		.fetch_optional(&app_state.db)
+		.await?
+	else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if server.owner_id != user_id {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	if body.owner_id == user_id {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "You already own this server".to_string(),
+		}));
+	}
+
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM ServerMember WHERE server_id = ? AND user_id = ?) AS `exists: bool`",
+		server_id,
+		body.owner_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	let mut cookie = None;
+
+	match (&body.password, &body.passkey) {
+		(Some(password), _) => {
+			let user = query!("SELECT password FROM User WHERE id = ?", user_id)
+				.fetch_one(&mut *tx)
+				.await?;
+
+			password_auth::verify_password(password, &user.password)?;
+		}
+		(None, Some(passkey)) => {
+			let Some(auth_cookie) = request.cookie(AUTHENTICATION_ID_COOKIE_NAME) else {
+				return Ok(HttpResponse::Unauthorized().finish());
+			};
+
+			if verify_passkey(&app_state, &mut tx, auth_cookie.value(), passkey).await?
+				!= Some(user_id)
+			{
+				return Ok(HttpResponse::Unauthorized().finish());
+			}
+
+			cookie = Some(auth_cookie);
+		}
+		(None, None) => {
+			return Ok(HttpResponse::Unauthorized().json(ErrorResponse {
+				error: "confirmation_required".to_string(),
+			}));
+		}
+	}
+
+	// the owner is checked again in case of a concurrent transfer
+	let query = query!(
+		"UPDATE Server SET owner_id = ? WHERE id = ? AND owner_id = ?",
+		body.owner_id,
+		server_id,
+		user_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	if query.rows_affected() == 0 {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let entry = AuditLogEntry::new(
+		&generator,
+		server_id,
+		user_id,
+		AuditLogAction::ServerUpdate,
+		server_id,
+		reason.0,
+	)
+	.with_changes([AuditLogChange::new(
+		"owner_id",
+		user_id.to_string(),
+		body.owner_id.to_string(),
+	)]);
+
+	insert_audit_log_entry(&mut *tx, &entry).await?;
+
+	tx.commit().await?;
+
+	if let Some(members) = app_state.server_connections.get(&server_id) {
+		send_updates(
+			[WsUpdateEvent::ServerUpdate {
+				id: server_id,
+				name: None,
+				owner_id: Some(body.owner_id),
+			}],
+			&app_state,
+			members.iter().copied(),
+		);
+	}
+
+	let mut response = HttpResponse::Ok();
+
+	if let Some(mut cookie) = cookie {
+		cookie.make_removal();
+		response.cookie(cookie);
+	}
+
+	Ok(response.finish())
+}
+
 pub async fn leave_server(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
@@ -258,26 +548,7 @@ pub async fn leave_server(
 	.execute(&app_state.db)
 	.await?;
 
-	send_updates(
-		[WsUpdateEvent::ServerDelete { id: server_id }],
-		&app_state,
-		// notify the user that they left the server
-		[user_id],
-	);
-
-	if let Entry::Occupied(mut members) = app_state.server_connections.entry(server_id) {
-		send_updates(
-			[WsUpdateEvent::MemberDelete { server_id, user_id }],
-			&app_state,
-			members.get().iter().copied(),
-		);
-
-		if members.get().len() == 1 {
-			members.remove_entry();
-		} else {
-			members.get_mut().remove(&user_id);
-		}
-	}
+	send_member_removal(&app_state, server_id, user_id);
 
 	Ok(HttpResponse::Ok().finish())
 }
@@ -292,18 +563,42 @@ pub async fn delete_server(
 	};
 
 	let server_id = server_id.into_inner();
+
+	let mut tx = app_state.db.begin().await?;
+
+	let attachment_keys = query!(
+		r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.filename, ChannelMessage.channel_id
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+INNER JOIN Server ON Server.id=Channel.server_id
+WHERE Server.id = ? AND Server.owner_id = ?
+"#,
+		server_id,
+		user_id
+	)
+	.fetch_all(&mut *tx)
+	.await?
+	.into_iter()
+	.map(|row| attachment_key(row.channel_id, row.id, &row.filename))
+	.collect::<Vec<_>>();
+
 	let query = query!(
 		"DELETE FROM Server WHERE id = ? AND owner_id = ?",
 		server_id,
 		user_id
 	)
-	.execute(&app_state.db)
+	.execute(&mut *tx)
 	.await?;
 
 	if query.rows_affected() == 0 {
 		return Ok(HttpResponse::Forbidden().finish());
 	}
 
+	tx.commit().await?;
+
+	delete_in_background(&app_state, attachment_keys);
+
 	if let Entry::Occupied(members) = app_state.server_connections.entry(server_id) {
 		send_updates(
 			[WsUpdateEvent::ServerDelete { id: server_id }],
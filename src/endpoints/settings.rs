@@ -0,0 +1,67 @@
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use sqlx::query;
+
+use crate::{
+	error::ApiResult,
+	middleware::Identity,
+	models::{
+		scope::{ReadWrite, Scope},
+		settings::get_user_settings,
+	},
+	ws::{send_updates, WsUpdateEvent},
+	AppState,
+};
+
+pub async fn get_settings(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Profile(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	Ok(HttpResponse::Ok().json(get_user_settings(&app_state.db, user_id).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateSettingsBody {
+	allow_server_member_dms: Option<bool>,
+}
+
+pub async fn update_settings(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	body: web::Json<UpdateSettingsBody>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Profile(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	if body.allow_server_member_dms.is_none() {
+		return Ok(HttpResponse::BadRequest().finish());
+	}
+
+	let mut settings = get_user_settings(&app_state.db, user_id).await?;
+
+	if let Some(allow_server_member_dms) = body.allow_server_member_dms {
+		settings.allow_server_member_dms = allow_server_member_dms;
+	}
+
+	query!(
+		r#"INSERT INTO UserSettings (user_id, allow_server_member_dms) VALUES (?, ?)
+ON DUPLICATE KEY UPDATE allow_server_member_dms = VALUES(allow_server_member_dms)"#,
+		user_id,
+		settings.allow_server_member_dms
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	send_updates(
+		[WsUpdateEvent::UserSettingsUpdate(settings.clone())],
+		&app_state,
+		[user_id],
+	);
+
+	Ok(HttpResponse::Ok().json(settings))
+}
@@ -0,0 +1,556 @@
+use std::sync::Mutex;
+
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use sqlx::{query, query_as, Executor, MySql, MySqlConnection};
+use validator::Validate;
+
+use crate::{
+	error::{ApiResult, BackendError, ErrorResponse},
+	middleware::Identity,
+	models::{
+		attachment::attachment_key,
+		channel::{Channel, ChannelKind, ThreadMetadata},
+		permissions::{get_channel_permissions, has_channel_permission, Permissions},
+		scope::{ReadWrite, Scope},
+		user::User,
+	},
+	storage::delete_in_background,
+	ws::{channel_connections, send_updates, WsUpdateEvent},
+	AppState,
+};
+
+async fn get_thread<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	server_id: u64,
+	thread_id: u64,
+) -> Result<Option<ThreadMetadata>, BackendError> {
+	Ok(query_as!(
+		ThreadMetadata,
+		r#"SELECT ChannelThread.parent_id, ChannelThread.starter_message_id, ChannelThread.owner_id, ChannelThread.archived AS `archived: bool`
+FROM ChannelThread
+INNER JOIN Channel ON Channel.id=ChannelThread.channel_id
+WHERE ChannelThread.channel_id = ? AND Channel.server_id = ?
+"#,
+		thread_id,
+		server_id
+	)
+	.fetch_optional(executor)
+	.await?)
+}
+
+// archived threads don't count towards the limit
+pub const MAX_ACTIVE_THREADS: i64 = 100;
+
+// locks the parent channel until the transaction ends, so that concurrent requests can't exceed the limit
+async fn active_thread_limit_reached(
+	tx: &mut MySqlConnection,
+	parent_id: u64,
+) -> Result<bool, BackendError> {
+	query!("SELECT id FROM Channel WHERE id = ? FOR UPDATE", parent_id)
+		.fetch_optional(&mut *tx)
+		.await?;
+
+	Ok(query!(
+		"SELECT COUNT(*) >= ? AS `limit_reached: bool` FROM ChannelThread WHERE parent_id = ? AND NOT archived",
+		MAX_ACTIVE_THREADS,
+		parent_id
+	)
+	.fetch_one(&mut *tx)
+	.await?
+	.limit_reached)
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateThreadBody {
+	#[serde(deserialize_with = "super::trim_string")]
+	#[validate(length(min = 2, max = 32))]
+	name: String,
+	// snowflakes are sent as strings, but numbers are accepted too
+	#[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+	starter_message_id: Option<u64>,
+}
+
+pub async fn create_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	generator: web::Data<Mutex<snowflaked::Generator>>,
+	body: web::Json<CreateThreadBody>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	body.validate()?;
+
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, parent_id) = path.into_inner();
+
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		parent_id,
+		user_id,
+		Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	// threads can't be nested
+	if !query!(
+		"SELECT EXISTS(SELECT 1 FROM Channel WHERE id = ? AND server_id = ? AND kind = 'text') AS `exists: bool`",
+		parent_id,
+		server_id
+	)
+	.fetch_one(&app_state.db)
+	.await?
+	.exists
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	if let Some(starter_message_id) = body.starter_message_id {
+		let Some(row) = query!(
+			r#"SELECT EXISTS(SELECT 1 FROM ChannelThread WHERE starter_message_id = ?) AS `has_thread: bool`
+FROM ChannelMessage
+WHERE id = ? AND channel_id = ?
+"#,
+			starter_message_id,
+			starter_message_id,
+			parent_id
+		)
+		.fetch_optional(&app_state.db)
+		.await?
+		else {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "invalid_starter_message".to_string(),
+			}));
+		};
+
+		if row.has_thread {
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "thread_already_exists".to_string(),
+			}));
+		}
+	}
+
+	let thread_id = {
+		let mut generator = generator.lock().unwrap();
+		generator.generate()
+	};
+
+	let mut tx = app_state.db.begin().await?;
+
+	if active_thread_limit_reached(&mut *tx, parent_id).await? {
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "thread_limit_reached".to_string(),
+		}));
+	}
+
+	query!(
+		"INSERT INTO Channel (id, name, kind, server_id) VALUES (?, ?, 'thread', ?)",
+		thread_id,
+		body.name,
+		server_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	let result = query!(
+		"INSERT INTO ChannelThread (channel_id, parent_id, starter_message_id, owner_id) VALUES (?, ?, ?, ?)",
+		thread_id,
+		parent_id,
+		body.starter_message_id,
+		user_id
+	)
+	.execute(&mut *tx)
+	.await;
+
+	match result {
+		Ok(_) => {}
+		Err(e)
+			if e.as_database_error()
+				.is_some_and(|e| e.is_unique_violation()) =>
+		{
+			return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+				error: "thread_already_exists".to_string(),
+			}));
+		}
+		Err(e) => return Err(e.into()),
+	}
+
+	query!(
+		"INSERT INTO ChannelThreadMember (channel_id, user_id) VALUES (?, ?)",
+		thread_id,
+		user_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	let channel = Channel {
+		id: thread_id,
+		name: body.name.to_string(),
+		kind: ChannelKind::Thread,
+		server_id: Some(server_id),
+		parent_id: None,
+		position: None,
+		user: None,
+		recipients: None,
+		owner_id: None,
+		thread: Some(ThreadMetadata {
+			parent_id,
+			starter_message_id: body.starter_message_id,
+			owner_id: Some(user_id),
+			archived: false,
+		}),
+		read_state: None,
+	};
+
+	send_updates(
+		[WsUpdateEvent::ThreadCreate(channel.clone())],
+		&app_state,
+		channel_connections(&app_state, server_id, thread_id).await?,
+	);
+
+	Ok(HttpResponse::Created().json(channel))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetThreadsQuery {
+	archived: Option<bool>,
+}
+
+pub async fn get_threads(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+	query: web::Query<GetThreadsQuery>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, parent_id) = path.into_inner();
+
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		parent_id,
+		user_id,
+		Permissions::VIEW_CHANNEL,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let threads = query!(
+		r#"SELECT Channel.id, Channel.name, ChannelThread.starter_message_id, ChannelThread.owner_id, ChannelThread.archived AS `archived: bool`
+FROM ChannelThread
+INNER JOIN Channel ON Channel.id=ChannelThread.channel_id
+WHERE ChannelThread.parent_id = ? AND Channel.server_id = ? AND (? IS NULL OR ChannelThread.archived = ?)
+ORDER BY Channel.id DESC
+"#,
+		parent_id,
+		server_id,
+		query.archived,
+		query.archived
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(
+		threads
+			.into_iter()
+			.map(|row| Channel {
+				id: row.id,
+				name: row.name,
+				kind: ChannelKind::Thread,
+				server_id: Some(server_id),
+				parent_id: None,
+				position: None,
+				user: None,
+				recipients: None,
+				owner_id: None,
+				thread: Some(ThreadMetadata {
+					parent_id,
+					starter_message_id: row.starter_message_id,
+					owner_id: row.owner_id,
+					archived: row.archived,
+				}),
+				read_state: None,
+			})
+			.collect::<Vec<_>>(),
+	))
+}
+
+// the owner of a thread can manage it without the MANAGE_THREADS permission
+async fn can_manage_thread(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	thread_id: u64,
+	user_id: u64,
+	thread: &ThreadMetadata,
+) -> Result<bool, BackendError> {
+	Ok(
+		get_channel_permissions(&app_state.db, server_id, thread_id, user_id)
+			.await?
+			.is_some_and(|permissions| {
+				permissions.contains(Permissions::VIEW_CHANNEL)
+					&& (thread.owner_id == Some(user_id)
+						|| permissions.contains(Permissions::MANAGE_THREADS))
+			}),
+	)
+}
+
+async fn set_thread_archived(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+	archived: bool,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, thread_id) = path.into_inner();
+
+	let Some(thread) = get_thread(&app_state.db, server_id, thread_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if !can_manage_thread(&app_state, server_id, thread_id, user_id, &thread).await? {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let mut tx = app_state.db.begin().await?;
+
+	if !archived
+		&& thread.archived
+		&& active_thread_limit_reached(&mut *tx, thread.parent_id).await?
+	{
+		return Ok(HttpResponse::BadRequest().json(ErrorResponse {
+			error: "thread_limit_reached".to_string(),
+		}));
+	}
+
+	let query = query!(
+		"UPDATE ChannelThread SET archived = ? WHERE channel_id = ?",
+		archived,
+		thread_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	if query.rows_affected() > 0 {
+		send_updates(
+			[WsUpdateEvent::ThreadUpdate {
+				id: thread_id,
+				name: None,
+				archived: Some(archived),
+			}],
+			&app_state,
+			channel_connections(&app_state, server_id, thread_id).await?,
+		);
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn archive_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	set_thread_archived(identity, app_state, path, true).await
+}
+
+pub async fn unarchive_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	set_thread_archived(identity, app_state, path, false).await
+}
+
+pub async fn delete_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, thread_id) = path.into_inner();
+
+	let Some(thread) = get_thread(&app_state.db, server_id, thread_id).await? else {
+		return Ok(HttpResponse::NotFound().finish());
+	};
+
+	if !can_manage_thread(&app_state, server_id, thread_id, user_id, &thread).await? {
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let viewers = channel_connections(&app_state, server_id, thread_id).await?;
+
+	let mut tx = app_state.db.begin().await?;
+
+	let attachment_keys = query!(
+		r#"SELECT ChannelMessageAttachment.id, ChannelMessageAttachment.filename
+FROM ChannelMessageAttachment
+INNER JOIN ChannelMessage ON ChannelMessage.id=ChannelMessageAttachment.message_id
+WHERE ChannelMessage.channel_id = ?
+"#,
+		thread_id
+	)
+	.fetch_all(&mut *tx)
+	.await?
+	.into_iter()
+	.map(|row| attachment_key(thread_id, row.id, &row.filename))
+	.collect::<Vec<_>>();
+
+	query!(
+		"DELETE FROM Channel WHERE id = ? AND server_id = ?",
+		thread_id,
+		server_id
+	)
+	.execute(&mut *tx)
+	.await?;
+
+	tx.commit().await?;
+
+	delete_in_background(&app_state, attachment_keys);
+
+	send_updates(
+		[WsUpdateEvent::ThreadDelete {
+			id: thread_id,
+			parent_id: thread.parent_id,
+		}],
+		&app_state,
+		viewers,
+	);
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn get_thread_members(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Read)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, thread_id) = path.into_inner();
+
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		thread_id,
+		user_id,
+		Permissions::VIEW_CHANNEL,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	let users = query_as!(
+		User,
+		r#"SELECT User.id, User.username, User.display_name
+FROM ChannelThreadMember
+INNER JOIN Channel ON Channel.id=ChannelThreadMember.channel_id
+INNER JOIN User ON User.id=ChannelThreadMember.user_id
+WHERE ChannelThreadMember.channel_id = ? AND Channel.server_id = ?
+ORDER BY ChannelThreadMember.created_at
+"#,
+		thread_id,
+		server_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().json(users))
+}
+
+pub async fn join_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, thread_id) = path.into_inner();
+
+	if get_thread(&app_state.db, server_id, thread_id)
+		.await?
+		.is_none()
+	{
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	if !has_channel_permission(
+		&app_state.db,
+		server_id,
+		thread_id,
+		user_id,
+		Permissions::VIEW_CHANNEL,
+	)
+	.await?
+	{
+		return Ok(HttpResponse::Forbidden().finish());
+	}
+
+	query!(
+		"INSERT IGNORE INTO ChannelThreadMember (channel_id, user_id) VALUES (?, ?)",
+		thread_id,
+		user_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn leave_thread(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<(u64, u64)>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Servers(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let (server_id, thread_id) = path.into_inner();
+
+	let query = query!(
+		r#"DELETE ChannelThreadMember
+FROM ChannelThreadMember
+INNER JOIN Channel ON Channel.id=ChannelThreadMember.channel_id
+WHERE ChannelThreadMember.channel_id = ? AND ChannelThreadMember.user_id = ? AND Channel.server_id = ?
+"#,
+		thread_id,
+		user_id,
+		server_id
+	)
+	.execute(&app_state.db)
+	.await?;
+
+	if query.rows_affected() == 0 {
+		return Ok(HttpResponse::NotFound().finish());
+	}
+
+	Ok(HttpResponse::Ok().finish())
+}
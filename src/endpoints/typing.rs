@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{rt, web, HttpResponse};
+use chrono::Utc;
+
+use crate::{
+	endpoints::messages::channel_recipients,
+	error::ApiResult,
+	middleware::Identity,
+	models::{
+		permissions::Permissions,
+		scope::{ReadWrite, Scope},
+	},
+	ws::{send_ephemeral_updates, WsUpdateEvent},
+	AppState,
+};
+
+// clients are expected to trigger typing again before the indicator expires
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(10);
+const TYPING_RATE_LIMIT: Duration = Duration::from_secs(5);
+
+pub async fn trigger_typing(
+	identity: web::ReqData<Identity>,
+	app_state: web::Data<AppState>,
+	path: web::Path<u64>,
+) -> ApiResult {
+	let Some(user_id) = identity.is_user_like_with_scope(Scope::Messages(ReadWrite::Write)) else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let channel_id = path.into_inner();
+
+	let Some(recipients) = channel_recipients(
+		&app_state,
+		channel_id,
+		user_id,
+		Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+	)
+	.await?
+	else {
+		return Ok(HttpResponse::Forbidden().finish());
+	};
+
+	let started_at = Instant::now();
+
+	if app_state
+		.typing
+		.get(&(channel_id, user_id))
+		.is_some_and(|previous| started_at.duration_since(*previous) < TYPING_RATE_LIMIT)
+	{
+		return Ok(HttpResponse::TooManyRequests().finish());
+	}
+
+	app_state.typing.insert((channel_id, user_id), started_at);
+
+	// the author's own clients already know they're typing
+	let recipients = recipients
+		.into_iter()
+		.filter(|recipient| *recipient != user_id)
+		.collect::<Vec<_>>();
+
+	send_ephemeral_updates(
+		[WsUpdateEvent::TypingStart {
+			channel_id,
+			user_id,
+			timestamp: Utc::now(),
+		}],
+		&app_state,
+		recipients.iter().copied(),
+	);
+
+	let app_state = app_state.clone();
+
+	rt::spawn(async move {
+		rt::time::sleep(TYPING_TIMEOUT).await;
+
+		// the indicator may have been refreshed or cleared by a message in the meantime
+		if app_state
+			.typing
+			.remove_if(&(channel_id, user_id), |_, at| *at == started_at)
+			.is_some()
+		{
+			send_ephemeral_updates(
+				[WsUpdateEvent::TypingStop {
+					channel_id,
+					user_id,
+				}],
+				&app_state,
+				recipients,
+			);
+		}
+	});
+
+	Ok(HttpResponse::NoContent().finish())
+}
@@ -3,7 +3,7 @@ use password_auth::generate_hash;
 use serde::Deserialize;
 use serde_json::Value;
 use sqlx::query;
-use std::{collections::HashSet, sync::Mutex};
+use std::sync::Mutex;
 use validator::{Validate, ValidationError};
 
 use crate::{
@@ -12,7 +12,7 @@ use crate::{
 	models::{
 		auth::create_session,
 		scope::{ReadWrite, Scope},
-		user::User,
+		user::{get_associates, User},
 	},
 	update_structure,
 	ws::{send_updates, WsUpdateEvent},
@@ -249,56 +249,7 @@ pub async fn update_user(
 		.execute(&app_state.db)
 		.await?;
 
-	let mut associates = query!(
-		r#"
-SELECT ServerMember.user_id
-FROM ServerMember
-INNER JOIN ServerMember AS UpdatedUser ON ServerMember.user_id=?
-WHERE ServerMember.server_id=UpdatedUser.server_id
-
-UNION
-    
-SELECT friend_id AS user_id
-FROM UserFriend
-WHERE user_id=?
-
-UNION
-
-SELECT user_id
-FROM UserFriend
-WHERE friend_id=?
-
-UNION
-
-SELECT receiver_id AS user_id
-FROM UserFriendRequest
-WHERE sender_id=?
-
-UNION
-
-SELECT sender_id AS user_id
-FROM UserFriendRequest
-WHERE receiver_id=?
-
-UNION
-
-SELECT Other.user_id
-FROM DMChannelRecipient
-INNER JOIN DMChannelRecipient AS Other ON DMChannelRecipient.channel_id=Other.channel_id
-WHERE DMChannelRecipient.user_id=?
-"#,
-		user_id,
-		user_id,
-		user_id,
-		user_id,
-		user_id,
-		user_id,
-	)
-	.fetch_all(&app_state.db)
-	.await?
-	.into_iter()
-	.map(|row| row.user_id)
-	.collect::<HashSet<u64>>();
+	let mut associates = get_associates(&app_state.db, user_id).await?;
 
 	associates.insert(user_id);
 
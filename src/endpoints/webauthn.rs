@@ -18,7 +18,7 @@ static WEBAUTHN_ID_GENERATOR: LazyLock<CuidConstructor> =
 	LazyLock::new(|| CuidConstructor::new().with_length(32));
 
 const REGISTRATION_ID_COOKIE_NAME: &str = "biasdo-passreg";
-const AUTHENTICATION_ID_COOKIE_NAME: &str = "biasdo-passauth";
+pub const AUTHENTICATION_ID_COOKIE_NAME: &str = "biasdo-passauth";
 
 fn make_cookie<'a>(name: &'a str, value: &'a str) -> Cookie<'a> {
 	Cookie::build(name, value)
@@ -64,6 +64,37 @@ WHERE cred_id=?"#,
 	Ok(())
 }
 
+// finishes an authentication started with start_authentication, returning the user the passkey belongs to
+pub async fn verify_passkey(
+	app_state: &AppState,
+	tx: &mut MySqlConnection,
+	auth_id: &str,
+	auth: &PublicKeyCredential,
+) -> Result<Option<u64>, BackendError> {
+	let Some(row) = query!(
+		r#"DELETE
+FROM WebauthnAuthState
+WHERE auth_id=? AND expires_at > NOW()
+RETURNING user_id, state"#,
+		auth_id
+	)
+	.fetch_optional(&mut *tx)
+	.await?
+	else {
+		return Ok(None);
+	};
+
+	let (user_id, state): (u64, _) = (row.get(0), serde_json::from_slice(row.get(1))?);
+
+	let res = app_state
+		.webauthn
+		.finish_passkey_authentication(auth, &state)?;
+
+	handle_auth_res(res, tx).await?;
+
+	Ok(Some(user_id))
+}
+
 pub async fn start_register_passkey(
 	identity: web::ReqData<Identity>,
 	app_state: web::Data<AppState>,
@@ -237,33 +268,16 @@ pub async fn finish_authentication(
 	request: HttpRequest,
 	auth: web::Json<PublicKeyCredential>,
 ) -> ApiResult {
-	let Some(mut cookie) = request.cookie("biasdo-passauth") else {
+	let Some(mut cookie) = request.cookie(AUTHENTICATION_ID_COOKIE_NAME) else {
 		return Ok(HttpResponse::Unauthorized().finish());
 	};
 
 	let mut tx = app_state.db.begin().await?;
 
-	let Some(row) = query!(
-		r#"DELETE
-FROM WebauthnAuthState
-WHERE auth_id=? AND expires_at > NOW()
-RETURNING user_id, state"#,
-		cookie.value()
-	)
-	.fetch_optional(&mut *tx)
-	.await?
-	else {
+	let Some(user_id) = verify_passkey(&app_state, &mut tx, cookie.value(), &auth).await? else {
 		return Ok(HttpResponse::Unauthorized().finish());
 	};
 
-	let (user_id, state): (u64, _) = (row.get(0), serde_json::from_slice(row.get(1))?);
-
-	let res = app_state
-		.webauthn
-		.finish_passkey_authentication(&auth, &state)?;
-
-	handle_auth_res(res, &mut tx).await?;
-
 	let session = create_session(&mut *tx, user_id).await?;
 	tx.commit().await?;
 
@@ -12,11 +12,18 @@ use actix_ws::{CloseCode, CloseReason, Message};
 use dashmap::mapref::entry::Entry;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use sqlx::query;
-use tokio::select;
+use tokio::{select, sync::mpsc};
 
 use crate::{
+	endpoints::{
+		members::send_member_removal,
+		presence::{broadcast_presence, set_presence, MAX_CUSTOM_STATUS_LENGTH},
+	},
 	middleware::{get_identity, Identity},
+	models::{block::get_blocked_ids, presence::PresenceStatus},
+	ws::{is_connected, SessionSender, OUTBOUND_QUEUE_SIZE},
 	AppState,
 };
 
@@ -28,18 +35,148 @@ const AUTHENTICATION_INTERVAL: Duration = Duration::from_secs(10);
 const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
 const REAUTHENTICATION_INTERVAL: Duration = Duration::from_secs(600);
 
+// how long a dropped session can be resumed for
+const RESUME_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[serde_as]
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 enum WsMessage {
 	Authenticate(String),
 	Reauthenticate,
+	Resume {
+		token: String,
+		#[serde_as(as = "DisplayFromStr")]
+		session_id: u64,
+		seq: u64,
+	},
+	Ready {
+		#[serde_as(as = "DisplayFromStr")]
+		session_id: u64,
+	},
+	Resumed,
+	InvalidSession,
+	UpdatePresence {
+		status: PresenceStatus,
+		#[serde(default, deserialize_with = "super::trim_opt_string")]
+		custom_status: Option<String>,
+	},
+	Error {
+		error: String,
+	},
+}
+
+async fn connect_servers(app_state: &web::Data<AppState>, user_id: u64) -> Result<(), sqlx::Error> {
+	let servers = query!(
+		"SELECT server_id FROM ServerMember WHERE user_id = ?",
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await?;
+
+	for row in servers {
+		app_state
+			.server_connections
+			.entry(row.server_id)
+			.or_default()
+			.insert(user_id);
+	}
+
+	Ok(())
+}
+
+// removes the session from the resumable ones, optionally only if it was dropped at the given time
+fn take_resumable_session(
+	app_state: &web::Data<AppState>,
+	user_id: u64,
+	session_id: u64,
+	disconnected_at: Option<Instant>,
+) -> bool {
+	let Entry::Occupied(mut sessions) = app_state.resumable_sessions.entry(user_id) else {
+		return false;
+	};
+
+	match sessions.get().get(&session_id) {
+		Some(at) if disconnected_at.is_none_or(|disconnected_at| *at == disconnected_at) => {}
+		_ => return false,
+	}
+
+	if sessions.get().len() == 1 {
+		sessions.remove_entry();
+	} else {
+		sessions.get_mut().remove(&session_id);
+	}
+
+	true
+}
+
+// forgets about the user once they have neither connected nor resumable sessions left
+async fn disconnect_user(app_state: &web::Data<AppState>, user_id: u64) {
+	if is_connected(app_state, user_id) {
+		return;
+	}
+
+	app_state.replay_buffers.remove(&user_id);
+	app_state.blocked_users.remove(&user_id);
+
+	let _ = broadcast_presence(app_state, user_id).await;
+
+	// temporary members who haven't been given a role in the meantime are removed
+	if let Ok(servers) = query!(
+		r#"SELECT server_id
+FROM ServerMember
+WHERE user_id = ? AND temporary AND NOT EXISTS(SELECT 1 FROM ServerMemberRole WHERE ServerMemberRole.server_id=ServerMember.server_id AND ServerMemberRole.user_id=ServerMember.user_id)
+"#,
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await
+	{
+		for server_id in servers.into_iter().map(|row| row.server_id) {
+			if query!(
+				"DELETE FROM ServerMember WHERE server_id = ? AND user_id = ? AND temporary",
+				server_id,
+				user_id
+			)
+			.execute(&app_state.db)
+			.await
+			.is_ok_and(|result| result.rows_affected() > 0)
+			{
+				send_member_removal(app_state, server_id, user_id);
+			}
+		}
+	}
+
+	if let Ok(servers) = query!(
+		"SELECT server_id FROM ServerMember WHERE user_id = ?",
+		user_id
+	)
+	.fetch_all(&app_state.db)
+	.await
+	{
+		for server_id in servers.into_iter().map(|row| row.server_id) {
+			if let Entry::Occupied(mut server_connections) =
+				app_state.server_connections.entry(server_id)
+			{
+				if server_connections.get().len() == 1 {
+					server_connections.remove_entry();
+				} else {
+					server_connections.get_mut().remove(&user_id);
+				}
+			}
+		}
+	}
+}
+
+fn send_message(sender: &SessionSender, msg: &WsMessage) {
+	sender.send(serde_json::to_string(msg).unwrap());
 }
 
 async fn ws_handler(
 	mut session: actix_ws::Session,
 	mut msg_stream: actix_ws::MessageStream,
 	app_state: web::Data<AppState>,
-	session_id: u64,
+	mut session_id: u64,
 ) {
 	let connected_at = Instant::now();
 	let mut last_heartbeat = Instant::now();
@@ -51,6 +188,22 @@ async fn ws_handler(
 	let mut last_reauthentication = Instant::now() + REAUTHENTICATION_INTERVAL;
 	let mut reauth_requested = false;
 
+	let (queue, mut outbound) = mpsc::channel::<String>(OUTBOUND_QUEUE_SIZE);
+	let sender = SessionSender::new(queue);
+
+	// the only task writing text to the session, so events are delivered in the order they were queued
+	let writer = rt::spawn({
+		let mut session = session.clone();
+
+		async move {
+			while let Some(json) = outbound.recv().await {
+				if session.text(json).await.is_err() {
+					break;
+				}
+			}
+		}
+	});
+
 	let reason = loop {
 		select! {
 			msg = msg_stream.next() => {
@@ -81,7 +234,6 @@ async fn ws_handler(
 							continue;
 						};
 
-						#[allow(clippy::single_match)]
 						match msg {
 							WsMessage::Authenticate(token) => {
 								if auth_info.is_some() && !reauth_requested {
@@ -91,9 +243,9 @@ async fn ws_handler(
 									});
 								}
 
-								let (user_id, scopes) = match get_identity(&token, &app_state).await {
-									Ok(Some((Identity::User(id), _))) => (id, None),
-									Ok(Some((Identity::UserByClient((id, scopes)), _))) => (id, Some(scopes)),
+								let (user_id, scopes, token) = match get_identity(&token, &app_state).await {
+									Ok(Some((Identity::User(id), token))) => (id, None, token),
+									Ok(Some((Identity::UserByClient((id, scopes)), token))) => (id, Some(scopes), token),
 									_ => {
 										break Some(CloseReason {
 											code: CloseCode::Policy,
@@ -122,7 +274,7 @@ async fn ws_handler(
 													});
 												}
 												(_, scopes) => {
-													session_info.insert((scopes, session.clone()));
+													session_info.insert((scopes, sender.clone(), token));
 												}
 											}
 										}
@@ -131,36 +283,135 @@ async fn ws_handler(
 									last_reauthentication = Instant::now();
 									reauth_requested = false;
 								} else {
+									app_state.replay_buffers.entry(user_id).or_default();
+
+									let came_online = !is_connected(&app_state, user_id);
+
 									app_state
 										.user_connections
 										.entry(user_id)
 										.or_default()
-										.insert(session_id, (scopes, session.clone()));
-
-									let Ok(servers) = query!(
-										"SELECT server_id FROM ServerMember WHERE user_id = ?",
-										user_id
-									)
-									.fetch_all(&app_state.db)
-									.await
-									else {
+										.insert(session_id, (scopes, sender.clone(), token));
+
+									auth_info = Some(user_id);
+
+									if connect_servers(&app_state, user_id).await.is_err() {
 										break Some(CloseReason {
 											code: CloseCode::Error,
 											description: None,
 										});
-									};
-
-									for row in servers {
-										app_state
-											.server_connections
-											.entry(row.server_id)
-											.or_default()
-											.insert(user_id);
 									}
 
-									auth_info = Some(user_id);
+									// resumed sessions keep the blocked users along with the replay buffer
+									match get_blocked_ids(&app_state.db, user_id).await {
+										Ok(blocked_ids) => {
+											app_state.blocked_users.insert(user_id, blocked_ids);
+										}
+										Err(_) => {
+											break Some(CloseReason {
+												code: CloseCode::Error,
+												description: None,
+											});
+										}
+									}
+
+									send_message(&sender, &WsMessage::Ready { session_id });
+
+									if came_online {
+										let _ = broadcast_presence(&app_state, user_id).await;
+									}
 								}
 							}
+							WsMessage::Resume { token, session_id: resumed_session_id, seq } => {
+								if auth_info.is_some() {
+									break Some(CloseReason {
+										code: CloseCode::Policy,
+										description: Some("Already authenticated".to_string()),
+									});
+								}
+
+								let (user_id, scopes, token) = match get_identity(&token, &app_state).await {
+									Ok(Some((Identity::User(id), token))) => (id, None, token),
+									Ok(Some((Identity::UserByClient((id, scopes)), token))) => (id, Some(scopes), token),
+									_ => {
+										break Some(CloseReason {
+											code: CloseCode::Policy,
+											description: Some("Unauthorized".to_string()),
+										});
+									}
+								};
+
+								// a session can only be resumed once, and only by the user it belongs to
+								if !take_resumable_session(&app_state, user_id, resumed_session_id, None) {
+									send_message(&sender, &WsMessage::InvalidSession);
+									continue;
+								}
+
+								// the buffer is held while queueing the missed events and registering the connection,
+								// so that no event is missed and live events can only be queued after the replayed ones.
+								// the user was still considered connected while the session could be resumed, so their presence doesn't change
+								let resumed = match app_state.replay_buffers.get(&user_id) {
+									Some(replay_buffer) => match replay_buffer.events_after(seq, scopes.as_ref()) {
+										Some(events) => {
+											send_message(&sender, &WsMessage::Resumed);
+
+											for json in events {
+												sender.send(json);
+											}
+
+											app_state
+												.user_connections
+												.entry(user_id)
+												.or_default()
+												.insert(resumed_session_id, (scopes, sender.clone(), token));
+
+											true
+										}
+										None => false,
+									},
+									None => false,
+								};
+
+								if !resumed {
+									disconnect_user(&app_state, user_id).await;
+									send_message(&sender, &WsMessage::InvalidSession);
+									continue;
+								}
+
+								session_id = resumed_session_id;
+								auth_info = Some(user_id);
+								last_reauthentication = Instant::now();
+
+								// memberships may have changed while the session was away
+								if connect_servers(&app_state, user_id).await.is_err() {
+									break Some(CloseReason {
+										code: CloseCode::Error,
+										description: None,
+									});
+								}
+							}
+							WsMessage::UpdatePresence { status, custom_status } => {
+								let Some(user_id) = auth_info else {
+									continue;
+								};
+
+								// the same rules as for the REST endpoint
+								if status == PresenceStatus::Offline {
+									send_message(&sender, &WsMessage::Error {
+										error: "Use the invisible status to appear offline".to_string(),
+									});
+									continue;
+								}
+
+								if custom_status.as_ref().is_some_and(|custom_status| custom_status.chars().count() > MAX_CUSTOM_STATUS_LENGTH) {
+									send_message(&sender, &WsMessage::Error {
+										error: "The custom status is too long".to_string(),
+									});
+									continue;
+								}
+
+								let _ = set_presence(&app_state, user_id, Some(status), Some(custom_status)).await;
+							}
 							_ => {}
 						}
 					}
@@ -169,6 +420,22 @@ async fn ws_handler(
 				};
 			}
 
+			_ = sender.lagged() => {
+				break Some(CloseReason {
+					code: CloseCode::Again,
+					description: Some("Too many pending events".to_string()),
+				});
+			}
+
+			_ = sender.reauthentication_requested() => {
+				if auth_info.is_some() && !reauth_requested {
+					send_message(&sender, &WsMessage::Reauthenticate);
+					reauth_requested = true;
+					// the timeout is counted from now, as the token was revoked rather than expired
+					last_reauthentication = Instant::now();
+				}
+			}
+
 			_ = heartbeat_interval.tick() => {
 				if Instant::now().duration_since(last_heartbeat) > HEARTBEAT_TIMEOUT {
 					break Some(CloseReason {
@@ -183,7 +450,7 @@ async fn ws_handler(
 			_ = auth_interval.tick() => {
 				if auth_info.is_some() {
 					if Instant::now().duration_since(last_reauthentication) > REAUTHENTICATION_INTERVAL && !reauth_requested {
-						let _ = session.text(serde_json::to_string(&WsMessage::Reauthenticate).unwrap()).await;
+						send_message(&sender, &WsMessage::Reauthenticate);
 						reauth_requested = true;
 					} else if Instant::now().duration_since(last_reauthentication) > AUTHENTICATION_TIMEOUT && reauth_requested {
 						break Some(CloseReason {
@@ -202,6 +469,23 @@ async fn ws_handler(
 	};
 
 	if let Some(user_id) = auth_info {
+		// sessions which were closed on purpose or for misbehaving can't be resumed
+		let resumable = !matches!(
+			reason.as_ref().map(|reason| reason.code),
+			Some(CloseCode::Normal | CloseCode::Policy)
+		);
+
+		// the session is made resumable before it's removed, so that the user doesn't appear disconnected in between
+		let disconnected_at = Instant::now();
+
+		if resumable {
+			app_state
+				.resumable_sessions
+				.entry(user_id)
+				.or_default()
+				.insert(session_id, disconnected_at);
+		}
+
 		if let Entry::Occupied(mut user_connections) = app_state.user_connections.entry(user_id) {
 			if user_connections.get().len() == 1 {
 				user_connections.remove_entry();
@@ -210,27 +494,23 @@ async fn ws_handler(
 			}
 		}
 
-		if let Ok(servers) = query!(
-			"SELECT server_id FROM ServerMember WHERE user_id = ?",
-			user_id
-		)
-		.fetch_all(&app_state.db)
-		.await
-		{
-			for server_id in servers.into_iter().map(|row| row.server_id) {
-				if let Entry::Occupied(mut server_connections) =
-					app_state.server_connections.entry(server_id)
-				{
-					if server_connections.get().len() == 1 {
-						server_connections.remove_entry();
-					} else {
-						server_connections.get_mut().remove(&user_id);
-					}
+		if resumable {
+			let app_state = app_state.clone();
+
+			rt::spawn(async move {
+				rt::time::sleep(RESUME_TIMEOUT).await;
+
+				// the session may have been resumed and dropped again in the meantime
+				if take_resumable_session(&app_state, user_id, session_id, Some(disconnected_at)) {
+					disconnect_user(&app_state, user_id).await;
 				}
-			}
+			});
+		} else {
+			disconnect_user(&app_state, user_id).await;
 		}
 	}
 
+	writer.abort();
 	let _ = session.close(reason).await;
 }
 
@@ -21,6 +21,15 @@ pub enum BackendError {
 
 	#[error("serde error")]
 	Serde(#[from] serde::de::value::Error),
+
+	#[error("error communicating with object storage")]
+	Storage(#[from] reqwest::Error),
+
+	#[error("error signing token")]
+	Openssl(#[from] openssl::error::ErrorStack),
+
+	#[error("error running blocking task")]
+	Blocking(#[from] actix_web::error::BlockingError),
 }
 
 #[derive(Debug, Serialize)]
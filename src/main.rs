@@ -2,24 +2,32 @@ mod endpoints;
 mod error;
 mod middleware;
 mod models;
+mod storage;
 mod ws;
 
-use crate::{middleware::TokenKey, models::scope::Scope};
+use crate::{
+	middleware::{Token, TokenKey},
+	models::scope::Scope,
+	storage::Storage,
+	ws::{ReplayBuffer, SessionSender},
+};
 use actix_cors::Cors;
 use actix_governor::{Governor, GovernorConfigBuilder};
+use actix_multipart::form::MultipartFormConfig;
 use actix_web::{
 	middleware::{from_fn, Compress, NormalizePath, TrailingSlash},
 	rt::System,
 	web, App, HttpServer,
 };
 use dashmap::DashMap;
+use rusty_s3::{Bucket, Credentials, UrlStyle};
 use snowflaked::Generator;
 use sqlx::{mysql::MySqlPoolOptions, MySqlPool};
 use std::{
 	collections::{HashMap, HashSet},
 	hash::{DefaultHasher, Hash, Hasher},
 	sync::Mutex,
-	time::{Duration, UNIX_EPOCH},
+	time::{Duration, Instant, UNIX_EPOCH},
 };
 use tracing_subscriber::{
 	filter::LevelFilter, fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt,
@@ -27,7 +35,10 @@ use tracing_subscriber::{
 };
 use webauthn_rs::{Webauthn, WebauthnBuilder};
 
-type Session = (Option<HashSet<Scope>>, actix_ws::Session);
+// the snowflake epoch, relative to the unix epoch
+pub const SNOWFLAKE_EPOCH: Duration = Duration::from_secs(1716501600);
+
+type Session = (Option<HashSet<Scope>>, SessionSender, Token);
 
 pub struct AppState {
 	pub db: MySqlPool,
@@ -35,7 +46,19 @@ pub struct AppState {
 	pub server_connections: DashMap<u64, HashSet<u64>>,
 	// user id -> ws(s) // multiple sessions
 	pub user_connections: DashMap<u64, HashMap<u64, Session>>,
+	// user id -> recently dispatched events
+	pub replay_buffers: DashMap<u64, ReplayBuffer>,
+	// user id -> session id -> disconnected at // dropped sessions which can still be resumed
+	pub resumable_sessions: DashMap<u64, HashMap<u64, Instant>>,
+	// user id -> the users they've blocked, kept for as long as the user's replay buffer
+	pub blocked_users: DashMap<u64, HashSet<u64>>,
+	// (channel id, user id) -> started typing at
+	pub typing: DashMap<(u64, u64), Instant>,
 	pub webauthn: Webauthn,
+	pub storage: Storage,
+	// where the api and the website are served from, for the urls advertised to oauth clients
+	pub api_url: url::Url,
+	pub app_url: url::Url,
 }
 
 #[macro_export]
@@ -71,6 +94,13 @@ macro_rules! benv {
     };
 }
 
+// urls are joined with relative paths, which only keeps the base path if it ends with a slash
+fn base_url(url: &str) -> url::Url {
+	format!("{}/", url.trim_end_matches('/'))
+		.parse()
+		.expect("invalid url")
+}
+
 async fn run() -> std::io::Result<()> {
 	let address = benv!("ADDRESS" => "127.0.0.1");
 	let port: u16 = benv!(parse "PORT" => "8080");
@@ -93,10 +123,19 @@ async fn run() -> std::io::Result<()> {
 		.map(|origin| url::Url::parse(origin).expect("invalid webauthn origin"))
 		.collect::<Vec<_>>();
 
+	let s3_endpoint = benv!(required "S3_ENDPOINT")
+		.parse()
+		.expect("invalid s3 endpoint");
+	let s3_path_style: bool = benv!(parse "S3_PATH_STYLE" => "false");
+
 	let app_data = web::Data::new(AppState {
 		db: pool,
 		server_connections: DashMap::new(),
 		user_connections: DashMap::new(),
+		replay_buffers: DashMap::new(),
+		resumable_sessions: DashMap::new(),
+		blocked_users: DashMap::new(),
+		typing: DashMap::new(),
 		webauthn: {
 			let first_origin = webauthn_origins
 				.first()
@@ -111,6 +150,25 @@ async fn run() -> std::io::Result<()> {
 
 			builder.build().expect("failed to build webauthn config")
 		},
+		storage: Storage::new(
+			Bucket::new(
+				s3_endpoint,
+				if s3_path_style {
+					UrlStyle::Path
+				} else {
+					UrlStyle::VirtualHost
+				},
+				benv!(required "S3_BUCKET"),
+				benv!("S3_REGION" => "us-east-1"),
+			)
+			.expect("invalid s3 bucket config"),
+			Credentials::new(
+				benv!(required "S3_ACCESS_KEY"),
+				benv!(required "S3_SECRET_KEY"),
+			),
+		),
+		api_url: base_url(&benv!(required "API_URL")),
+		app_url: base_url(&benv!(required "APP_URL")),
 	});
 
 	let generic_governor_config = GovernorConfigBuilder::default()
@@ -137,7 +195,7 @@ async fn run() -> std::io::Result<()> {
 			.app_data(web::Data::new(Mutex::new(
 				Generator::builder()
 					.instance(instance)
-					.epoch(UNIX_EPOCH + Duration::from_secs(1716501600))
+					.epoch(UNIX_EPOCH + SNOWFLAKE_EPOCH)
 					.build::<Generator>(),
 			)))
 			.route(
@@ -146,6 +204,18 @@ async fn run() -> std::io::Result<()> {
 					concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"))
 				}),
 			)
+			.route(
+				"/.well-known/oauth-authorization-server",
+				web::get().to(endpoints::oauth::metadata::authorization_server_metadata),
+			)
+			.route(
+				"/.well-known/openid-configuration",
+				web::get().to(endpoints::oauth::metadata::openid_configuration),
+			)
+			.route(
+				"/.well-known/jwks.json",
+				web::get().to(endpoints::oauth::oidc::jwks),
+			)
 			.service(
 				web::scope("/v0")
 					.route("/register", web::post().to(endpoints::users::register_user))
@@ -260,10 +330,24 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
-					.route(
-						"/direct-channels",
-						web::get()
-							.to(endpoints::direct_messages::get_direct_channels)
+					.service(
+						web::resource("/direct-channels")
+							.get(endpoints::direct_messages::get_direct_channels)
+							.post(endpoints::direct_messages::create_group_dm)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/direct-channels/{channel_id}")
+							.patch(endpoints::direct_messages::update_group_dm)
+							.delete(endpoints::direct_messages::leave_group_dm)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/direct-channels/{channel_id}/recipients/{user_id}")
+							.put(endpoints::direct_messages::add_recipient)
+							.delete(endpoints::direct_messages::remove_recipient)
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
@@ -275,6 +359,34 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
+					.route(
+						"/users/@me/blocks",
+						web::get()
+							.to(endpoints::blocks::get_blocks)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/users/@me/blocks/{user_id}")
+							.put(endpoints::blocks::block_user)
+							.delete(endpoints::blocks::unblock_user)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/users/@me/settings")
+							.get(endpoints::settings::get_settings)
+							.patch(endpoints::settings::update_settings)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/users/@me/presence",
+						web::patch()
+							.to(endpoints::presence::update_presence)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
 					.route(
 						"/users/{user_id}",
 						web::get()
@@ -282,6 +394,13 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
+					.route(
+						"/users/{user_id}/channel",
+						web::post()
+							.to(endpoints::direct_messages::open_direct_channel)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
 					.route(
 						"/users/username/{user_id}",
 						web::get()
@@ -311,10 +430,18 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
+					.route(
+						"/servers/{server_id}/transfer",
+						web::post()
+							.to(endpoints::servers::transfer_server)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
 					.service(
 						web::resource("/servers/{server_id}/channels")
 							.get(endpoints::channels::get_channels)
 							.post(endpoints::channels::create_channel)
+							.patch(endpoints::channels::reorder_channels)
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
@@ -326,8 +453,89 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
+					.route(
+						"/servers/{server_id}/channels/{channel_id}/overwrites",
+						web::get()
+							.to(endpoints::channels::get_overwrites)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource(
+							"/servers/{server_id}/channels/{channel_id}/overwrites/{target_id}",
+						)
+						.put(endpoints::channels::update_overwrite)
+						.delete(endpoints::channels::delete_overwrite)
+						.wrap(Governor::new(&generic_governor_config))
+						.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/channels/{channel_id}/threads")
+							.get(endpoints::threads::get_threads)
+							.post(endpoints::threads::create_thread)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/threads/{thread_id}",
+						web::delete()
+							.to(endpoints::threads::delete_thread)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/threads/{thread_id}/archive",
+						web::post()
+							.to(endpoints::threads::archive_thread)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/threads/{thread_id}/unarchive",
+						web::post()
+							.to(endpoints::threads::unarchive_thread)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/threads/{thread_id}/members",
+						web::get()
+							.to(endpoints::threads::get_thread_members)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/threads/{thread_id}/members/@me")
+							.put(endpoints::threads::join_thread)
+							.delete(endpoints::threads::leave_thread)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/messages/search",
+						web::get()
+							.to(endpoints::messages::search_server_messages)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/direct-channels/messages/search",
+						web::get()
+							.to(endpoints::messages::search_direct_messages)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
 					.service(
 						web::resource("/channels/{channel_id}/messages")
+							// the payload is buffered to tell JSON and multipart bodies apart
+							.app_data(web::PayloadConfig::new(
+								endpoints::messages::MAX_PAYLOAD_SIZE,
+							))
+							.app_data(
+								MultipartFormConfig::default()
+									.total_limit(endpoints::messages::MAX_PAYLOAD_SIZE)
+									.memory_limit(endpoints::messages::MAX_PAYLOAD_SIZE),
+							)
 							.get(endpoints::messages::get_messages)
 							.post(endpoints::messages::create_message)
 							.wrap(Governor::new(&generic_governor_config))
@@ -341,6 +549,36 @@ async fn run() -> std::io::Result<()> {
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
+					.route(
+						"/channels/{channel_id}/typing",
+						web::post()
+							.to(endpoints::typing::trigger_typing)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/channels/{channel_id}/messages/{message_id}/ack",
+						web::post()
+							.to(endpoints::messages::ack_message)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/channels/{channel_id}/messages/{message_id}/reactions/{emoji}",
+						web::get()
+							.to(endpoints::reactions::get_reactions)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource(
+							"/channels/{channel_id}/messages/{message_id}/reactions/{emoji}/@me",
+						)
+						.put(endpoints::reactions::add_reaction)
+						.delete(endpoints::reactions::remove_reaction)
+						.wrap(Governor::new(&generic_governor_config))
+						.wrap(from_fn(middleware::authentication)),
+					)
 					.service(
 						web::resource("/servers/{server_id}/invites")
 							.get(endpoints::invites::get_invites)
@@ -359,6 +597,57 @@ async fn run() -> std::io::Result<()> {
 						web::resource("/servers/{server_id}/members/{user_id}")
 							.get(endpoints::members::get_member)
 							.patch(endpoints::members::update_member)
+							.delete(endpoints::members::kick_member)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/members/{user_id}/timeout")
+							.put(endpoints::members::timeout_member)
+							.delete(endpoints::members::remove_member_timeout)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/audit-log",
+						web::get()
+							.to(endpoints::audit_log::get_audit_log)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/servers/{server_id}/bans",
+						web::get()
+							.to(endpoints::bans::get_bans)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/bans/{user_id}")
+							.put(endpoints::bans::ban_member)
+							.delete(endpoints::bans::unban_member)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/members/{user_id}/roles/{role_id}")
+							.put(endpoints::roles::add_member_role)
+							.delete(endpoints::roles::remove_member_role)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/roles")
+							.get(endpoints::roles::get_roles)
+							.post(endpoints::roles::create_role)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/servers/{server_id}/roles/{role_id}")
+							.get(endpoints::roles::get_role)
+							.patch(endpoints::roles::update_role)
+							.delete(endpoints::roles::delete_role)
 							.wrap(Governor::new(&generic_governor_config))
 							.wrap(from_fn(middleware::authentication)),
 					)
@@ -382,6 +671,38 @@ async fn run() -> std::io::Result<()> {
 							.to(endpoints::oauth::token::exchange_token)
 							.wrap(from_fn(middleware::maybe_authentication)),
 					)
+					.route(
+						"/token/revoke",
+						web::post()
+							.to(endpoints::oauth::token::revoke_token)
+							.wrap(from_fn(middleware::maybe_authentication)),
+					)
+					.route(
+						"/device/code",
+						web::post()
+							.to(endpoints::oauth::device::device_authorization)
+							.wrap(from_fn(middleware::maybe_authentication)),
+					)
+					.service(
+						web::resource("/device")
+							.get(endpoints::oauth::device::get_device_info)
+							.post(endpoints::oauth::device::approve_device)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.service(
+						web::resource("/userinfo")
+							.get(endpoints::oauth::oidc::userinfo)
+							.post(endpoints::oauth::oidc::userinfo)
+							.wrap(Governor::new(&generic_governor_config))
+							.wrap(from_fn(middleware::authentication)),
+					)
+					.route(
+						"/token/introspect",
+						web::post()
+							.to(endpoints::oauth::token::introspect_token)
+							.wrap(from_fn(middleware::authentication)),
+					)
 					.route("/ws", web::get().to(endpoints::ws::ws)),
 			)
 	})
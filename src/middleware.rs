@@ -9,6 +9,7 @@ use actix_web::{
 	web, Error as ActixError, HttpMessage, HttpResponse, ResponseError,
 };
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use sqlx::query;
 
 use crate::{error::BackendError, models::scope::Scope, AppState};
@@ -88,7 +89,7 @@ async fn basic_token(
 	Ok(Some(Identity::Client(client_id.parse().unwrap())))
 }
 
-fn scopes_from_string(scopes: &str) -> HashSet<Scope> {
+pub fn scopes_from_string(scopes: &str) -> HashSet<Scope> {
 	scopes
 		.split(',')
 		.filter(|s| !s.is_empty())
@@ -96,13 +97,22 @@ fn scopes_from_string(scopes: &str) -> HashSet<Scope> {
 		.collect()
 }
 
-async fn bearer_token(
+// an access token issued to a client, either on behalf of a user or for the client itself
+#[derive(Debug)]
+pub struct BearerToken {
+	pub identity: Identity,
+	pub client_id: u64,
+	pub issued_at: DateTime<Utc>,
+	pub expires_at: DateTime<Utc>,
+}
+
+pub async fn lookup_bearer_token(
 	token: &str,
 	app_state: &web::Data<AppState>,
-) -> Result<Option<Identity>, BackendError> {
+) -> Result<Option<BearerToken>, BackendError> {
 	if token.starts_with("u.") {
 		let Some(record) = query!(
-            "SELECT user_id, scope FROM ClientUserTokens WHERE access_token = ? AND access_expires_at > NOW() AND expires_at > NOW()",
+            "SELECT user_id, client_id, scope, created_at, access_expires_at FROM ClientUserTokens WHERE access_token = ? AND access_expires_at > NOW() AND expires_at > NOW()",
             token
         )
             .fetch_optional(&app_state.db)
@@ -110,13 +120,15 @@ async fn bearer_token(
                 return Ok(None);
             };
 
-		Ok(Some(Identity::UserByClient((
-			record.user_id,
-			scopes_from_string(&record.scope),
-		))))
+		Ok(Some(BearerToken {
+			identity: Identity::UserByClient((record.user_id, scopes_from_string(&record.scope))),
+			client_id: record.client_id,
+			issued_at: record.created_at,
+			expires_at: record.access_expires_at,
+		}))
 	} else {
 		let Some(record) = query!(
-            "SELECT client_id, scope FROM ClientToken WHERE access_token = ? AND expires_at > NOW()",
+            "SELECT client_id, scope, created_at, expires_at FROM ClientToken WHERE access_token = ? AND expires_at > NOW()",
             token
         )
             .fetch_optional(&app_state.db)
@@ -124,13 +136,27 @@ async fn bearer_token(
                 return Ok(None);
             };
 
-		Ok(Some(Identity::ClientByClient((
-			record.client_id,
-			scopes_from_string(&record.scope),
-		))))
+		Ok(Some(BearerToken {
+			identity: Identity::ClientByClient((
+				record.client_id,
+				scopes_from_string(&record.scope),
+			)),
+			client_id: record.client_id,
+			issued_at: record.created_at,
+			expires_at: record.expires_at,
+		}))
 	}
 }
 
+async fn bearer_token(
+	token: &str,
+	app_state: &web::Data<AppState>,
+) -> Result<Option<Identity>, BackendError> {
+	Ok(lookup_bearer_token(token, app_state)
+		.await?
+		.map(|bearer_token| bearer_token.identity))
+}
+
 async fn other_token(
 	token: &str,
 	app_state: &web::Data<AppState>,
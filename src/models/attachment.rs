@@ -0,0 +1,120 @@
+use serde::Serialize;
+use ts_rs::TS;
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct Attachment {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub id: u64,
+	pub filename: String,
+	pub content_type: String,
+	pub size: u32,
+	pub url: Url,
+}
+
+pub fn attachment_key(channel_id: u64, attachment_id: u64, filename: &str) -> String {
+	format!("attachments/{channel_id}/{attachment_id}/{filename}")
+}
+
+// filenames end up in object keys, so only keep characters which are safe in a URL path
+pub fn sanitize_filename(filename: &str) -> String {
+	let filename = filename
+		.chars()
+		.map(|c| {
+			if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+				c
+			} else {
+				'_'
+			}
+		})
+		.take(128)
+		.collect::<String>();
+
+	match filename.trim_start_matches('.') {
+		"" => "file".to_string(),
+		filename => filename.to_string(),
+	}
+}
+
+// media which is safe to be served inline from the bucket, anything else is served as a download
+const INLINE_CONTENT_TYPES: &[&str] = &[
+	"image/png",
+	"image/jpeg",
+	"image/gif",
+	"image/webp",
+	"image/avif",
+	"video/mp4",
+	"video/webm",
+	"video/ogg",
+	"audio/mpeg",
+	"audio/ogg",
+	"audio/wav",
+	"audio/webm",
+	"audio/flac",
+];
+
+pub const DOWNLOAD_CONTENT_TYPE: &str = "application/octet-stream";
+
+// the uploader chooses the content type, so types which browsers would render as active content (e.g. text/html or image/svg+xml) are never stored
+pub fn normalize_content_type(content_type: Option<&str>) -> &'static str {
+	content_type
+		.map(|content_type| {
+			content_type
+				.split(';')
+				.next()
+				.unwrap_or_default()
+				.trim()
+				.to_ascii_lowercase()
+		})
+		.and_then(|content_type| {
+			INLINE_CONTENT_TYPES
+				.iter()
+				.find(|inline| **inline == content_type)
+				.copied()
+		})
+		.unwrap_or(DOWNLOAD_CONTENT_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sanitizes_filenames() {
+		assert_eq!(sanitize_filename("photo.png"), "photo.png");
+		assert_eq!(sanitize_filename("my photo (1).png"), "my_photo__1_.png");
+		assert_eq!(sanitize_filename("../../etc/passwd"), "_.._etc_passwd");
+		assert_eq!(sanitize_filename(".env"), "env");
+		assert_eq!(sanitize_filename(""), "file");
+		assert_eq!(sanitize_filename("..."), "file");
+		assert_eq!(sanitize_filename(&"a".repeat(200)).len(), 128);
+	}
+
+	#[test]
+	fn builds_attachment_keys() {
+		assert_eq!(
+			attachment_key(1, 2, "photo.png"),
+			"attachments/1/2/photo.png"
+		);
+	}
+
+	#[test]
+	fn normalizes_content_types() {
+		assert_eq!(normalize_content_type(Some("image/png")), "image/png");
+		assert_eq!(
+			normalize_content_type(Some("Audio/OGG; codecs=opus")),
+			"audio/ogg"
+		);
+		assert_eq!(
+			normalize_content_type(Some("text/html")),
+			DOWNLOAD_CONTENT_TYPE
+		);
+		assert_eq!(
+			normalize_content_type(Some("image/svg+xml")),
+			DOWNLOAD_CONTENT_TYPE
+		);
+		assert_eq!(normalize_content_type(None), DOWNLOAD_CONTENT_TYPE);
+	}
+}
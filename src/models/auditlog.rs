@@ -0,0 +1,172 @@
+use std::{fmt::Display, str::FromStr, sync::Mutex};
+
+use crate::error::BackendError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use sqlx::{query, Executor, MySql};
+use ts_rs::TS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerializeDisplay, DeserializeFromStr, TS, Hash)]
+pub enum AuditLogAction {
+	#[ts(rename = "server_update")]
+	ServerUpdate,
+	#[ts(rename = "channel_create")]
+	ChannelCreate,
+	#[ts(rename = "channel_update")]
+	ChannelUpdate,
+	#[ts(rename = "channel_delete")]
+	ChannelDelete,
+	#[ts(rename = "channel_overwrite_update")]
+	ChannelOverwriteUpdate,
+	#[ts(rename = "channel_overwrite_delete")]
+	ChannelOverwriteDelete,
+	#[ts(rename = "invite_create")]
+	InviteCreate,
+	#[ts(rename = "invite_delete")]
+	InviteDelete,
+	#[ts(rename = "member_update")]
+	MemberUpdate,
+	#[ts(rename = "member_kick")]
+	MemberKick,
+	#[ts(rename = "member_ban")]
+	MemberBan,
+	#[ts(rename = "member_unban")]
+	MemberUnban,
+	#[ts(rename = "member_timeout")]
+	MemberTimeout,
+}
+
+impl Display for AuditLogAction {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			AuditLogAction::ServerUpdate => write!(f, "server_update"),
+			AuditLogAction::ChannelCreate => write!(f, "channel_create"),
+			AuditLogAction::ChannelUpdate => write!(f, "channel_update"),
+			AuditLogAction::ChannelDelete => write!(f, "channel_delete"),
+			AuditLogAction::ChannelOverwriteUpdate => write!(f, "channel_overwrite_update"),
+			AuditLogAction::ChannelOverwriteDelete => write!(f, "channel_overwrite_delete"),
+			AuditLogAction::InviteCreate => write!(f, "invite_create"),
+			AuditLogAction::InviteDelete => write!(f, "invite_delete"),
+			AuditLogAction::MemberUpdate => write!(f, "member_update"),
+			AuditLogAction::MemberKick => write!(f, "member_kick"),
+			AuditLogAction::MemberBan => write!(f, "member_ban"),
+			AuditLogAction::MemberUnban => write!(f, "member_unban"),
+			AuditLogAction::MemberTimeout => write!(f, "member_timeout"),
+		}
+	}
+}
+
+impl FromStr for AuditLogAction {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"server_update" => Ok(AuditLogAction::ServerUpdate),
+			"channel_create" => Ok(AuditLogAction::ChannelCreate),
+			"channel_update" => Ok(AuditLogAction::ChannelUpdate),
+			"channel_delete" => Ok(AuditLogAction::ChannelDelete),
+			"channel_overwrite_update" => Ok(AuditLogAction::ChannelOverwriteUpdate),
+			"channel_overwrite_delete" => Ok(AuditLogAction::ChannelOverwriteDelete),
+			"invite_create" => Ok(AuditLogAction::InviteCreate),
+			"invite_delete" => Ok(AuditLogAction::InviteDelete),
+			"member_update" => Ok(AuditLogAction::MemberUpdate),
+			"member_kick" => Ok(AuditLogAction::MemberKick),
+			"member_ban" => Ok(AuditLogAction::MemberBan),
+			"member_unban" => Ok(AuditLogAction::MemberUnban),
+			"member_timeout" => Ok(AuditLogAction::MemberTimeout),
+			_ => Err(format!("Invalid audit log action: {}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct AuditLogChange {
+	pub key: String,
+	#[ts(type = "unknown")]
+	pub old_value: serde_json::Value,
+	#[ts(type = "unknown")]
+	pub new_value: serde_json::Value,
+}
+
+impl AuditLogChange {
+	pub fn new<T: Serialize>(key: &str, old_value: T, new_value: T) -> Self {
+		Self {
+			key: key.to_string(),
+			old_value: serde_json::to_value(old_value).unwrap_or_default(),
+			new_value: serde_json::to_value(new_value).unwrap_or_default(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS)]
+#[ts(export)]
+pub struct AuditLogEntry {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub id: u64,
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub server_id: u64,
+	// None if the user who made the change has since been deleted
+	#[serde(serialize_with = "super::opt_id_str")]
+	#[ts(type = "`${number}` | null")]
+	pub user_id: Option<u64>,
+	pub action: AuditLogAction,
+	// a snowflake, or the code for invites
+	pub target_id: Option<String>,
+	// the values before and after the change, created and deleted targets have null on the missing side
+	pub changes: Vec<AuditLogChange>,
+	pub reason: Option<String>,
+	pub created_at: DateTime<Utc>,
+}
+
+impl AuditLogEntry {
+	// an entry for a change the user is making, which is inserted in the same transaction as the change
+	pub fn new<T: ToString>(
+		generator: &Mutex<snowflaked::Generator>,
+		server_id: u64,
+		user_id: u64,
+		action: AuditLogAction,
+		target_id: T,
+		reason: Option<String>,
+	) -> Self {
+		Self {
+			id: generator.lock().unwrap().generate(),
+			server_id,
+			user_id: Some(user_id),
+			action,
+			target_id: Some(target_id.to_string()),
+			changes: vec![],
+			reason,
+			created_at: Utc::now(),
+		}
+	}
+
+	pub fn with_changes<I: IntoIterator<Item = AuditLogChange>>(mut self, changes: I) -> Self {
+		self.changes = changes.into_iter().collect();
+		self
+	}
+}
+
+pub async fn insert_audit_log_entry<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	entry: &AuditLogEntry,
+) -> Result<(), BackendError> {
+	query!(
+		"INSERT INTO ServerAuditLogEntry (id, server_id, user_id, action, target_id, changes, reason, created_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+		entry.id,
+		entry.server_id,
+		entry.user_id,
+		entry.action.to_string(),
+		entry.target_id,
+		serde_json::to_string(&entry.changes).unwrap(),
+		entry.reason,
+		entry.created_at
+	)
+	.execute(executor)
+	.await?;
+
+	Ok(())
+}
@@ -0,0 +1,15 @@
+use crate::models::user::User;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct ServerBan {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub server_id: u64,
+	pub user: User,
+	pub reason: Option<String>,
+	pub created_at: DateTime<Utc>,
+}
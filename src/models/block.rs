@@ -0,0 +1,48 @@
+use std::collections::HashSet;
+
+use crate::{error::BackendError, models::user::User};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{query, Executor, MySql};
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct UserBlock {
+	// the blocked user
+	pub user: User,
+	pub created_at: DateTime<Utc>,
+}
+
+// whether either of the users has blocked the other
+pub async fn is_blocked_between<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	other_id: u64,
+) -> Result<bool, BackendError> {
+	Ok(query!(
+		"SELECT EXISTS(SELECT 1 FROM UserBlock WHERE (user_id, blocked_id) = (?, ?) OR (user_id, blocked_id) = (?, ?)) AS `exists: bool`",
+		user_id,
+		other_id,
+		other_id,
+		user_id
+	)
+	.fetch_one(executor)
+	.await?
+	.exists)
+}
+
+pub async fn get_blocked_ids<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<HashSet<u64>, BackendError> {
+	Ok(query!(
+		"SELECT blocked_id FROM UserBlock WHERE user_id = ?",
+		user_id
+	)
+	.fetch_all(executor)
+	.await?
+	.into_iter()
+	.map(|row| row.blocked_id)
+	.collect())
+}
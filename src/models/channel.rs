@@ -1,6 +1,6 @@
 use std::{fmt::Display, str::FromStr};
 
-use crate::models::user::User;
+use crate::models::{readstate::ReadState, user::User};
 use serde::Serialize;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use ts_rs::TS;
@@ -10,6 +10,12 @@ pub enum ChannelKind {
 	#[ts(rename = "text")]
 	Text,
 	DM,
+	#[ts(rename = "thread")]
+	Thread,
+	#[ts(rename = "category")]
+	Category,
+	#[ts(rename = "group_DM")]
+	GroupDM,
 }
 
 impl Display for ChannelKind {
@@ -17,6 +23,9 @@ impl Display for ChannelKind {
 		match self {
 			ChannelKind::Text => write!(f, "text"),
 			ChannelKind::DM => write!(f, "DM"),
+			ChannelKind::Thread => write!(f, "thread"),
+			ChannelKind::Category => write!(f, "category"),
+			ChannelKind::GroupDM => write!(f, "group_DM"),
 		}
 	}
 }
@@ -28,6 +37,9 @@ impl FromStr for ChannelKind {
 		match s {
 			"text" => Ok(ChannelKind::Text),
 			"DM" => Ok(ChannelKind::DM),
+			"thread" => Ok(ChannelKind::Thread),
+			"category" => Ok(ChannelKind::Category),
+			"group_DM" => Ok(ChannelKind::GroupDM),
 			_ => Err(format!("Invalid channel kind: {}", s)),
 		}
 	}
@@ -47,5 +59,49 @@ pub struct Channel {
 	)]
 	#[ts(type = "`${number}`")]
 	pub server_id: Option<u64>,
+	// the category the channel is in, only present for server channels
+	#[serde(
+		skip_serializing_if = "Option::is_none",
+		serialize_with = "super::opt_id_str"
+	)]
+	#[ts(type = "`${number}`", optional)]
+	pub parent_id: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub position: Option<u32>,
 	pub user: Option<User>,
+	// everyone in a direct message channel, including the current user
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub recipients: Option<Vec<User>>,
+	// only present for group DMs, the recipient who can remove the others
+	#[serde(
+		skip_serializing_if = "Option::is_none",
+		serialize_with = "super::opt_id_str"
+	)]
+	#[ts(type = "`${number}`", optional)]
+	pub owner_id: Option<u64>,
+	// only present for threads
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub thread: Option<ThreadMetadata>,
+	// only present in the channel lists of the current user
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub read_state: Option<ReadState>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct ThreadMetadata {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub parent_id: u64,
+	#[serde(serialize_with = "super::opt_id_str")]
+	#[ts(type = "`${number}` | null")]
+	pub starter_message_id: Option<u64>,
+	#[serde(serialize_with = "super::opt_id_str")]
+	#[ts(type = "`${number}` | null")]
+	pub owner_id: Option<u64>,
+	pub archived: bool,
 }
@@ -1,6 +1,10 @@
-use crate::models::{channel::Channel, user::User};
+use crate::{
+	error::BackendError,
+	models::{channel::Channel, presence::Presence, user::User},
+};
 use chrono::{DateTime, Utc};
 use serde::Serialize;
+use sqlx::{query, Executor, MySql};
 use ts_rs::TS;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
@@ -10,4 +14,25 @@ pub struct UserFriend {
 	pub friend: User,
 	pub created_at: DateTime<Utc>,
 	pub channel: Channel,
+	// the presence of the other user, only present when listing friends
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub presence: Option<Presence>,
+}
+
+pub async fn are_friends<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	friend_id: u64,
+) -> Result<bool, BackendError> {
+	Ok(query!(
+		"SELECT EXISTS(SELECT 1 FROM UserFriend WHERE (user_id, friend_id) = (?, ?) OR (user_id, friend_id) = (?, ?)) AS `exists: bool`",
+		user_id,
+		friend_id,
+		friend_id,
+		user_id
+	)
+	.fetch_one(executor)
+	.await?
+	.exists)
 }
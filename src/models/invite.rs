@@ -9,5 +9,11 @@ pub struct Invite {
 	pub id: String,
 	pub server: Server,
 	pub created_at: DateTime<Utc>,
-	pub expires_at: DateTime<Utc>,
+	// None if the invite never expires
+	pub expires_at: Option<DateTime<Utc>>,
+	// None if the invite can be used any number of times
+	pub max_uses: Option<u32>,
+	pub uses: u32,
+	// members who join through a temporary invite are removed once they disconnect, unless they've been given a role
+	pub temporary: bool,
 }
@@ -1,6 +1,8 @@
 use std::{fmt::Display, str::FromStr};
 
-use crate::models::{servermember::ServerMember, user::User};
+use crate::models::{
+	attachment::Attachment, reaction::Reaction, servermember::ServerMember, user::User,
+};
 use serde::Serialize;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 use ts_rs::TS;
@@ -38,10 +40,93 @@ pub struct Message {
 	pub id: u64,
 	pub kind: MessageKind,
 	pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+	// may mention users with <@user_id>, which counts towards their mention counts
 	pub content: String,
 	#[serde(serialize_with = "super::id_str")]
 	#[ts(type = "`${number}`")]
 	pub channel_id: u64,
 	pub user: User,
 	pub member: Option<ServerMember>,
+	pub attachments: Vec<Attachment>,
+	pub reactions: Vec<Reaction>,
+	pub referenced_message: Option<ReferencedMessage>,
+}
+
+// a snippet of the message being replied to
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct ReferencedMessage {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub id: u64,
+	// the user and content are missing if the message has been deleted
+	pub deleted: bool,
+	pub user: Option<User>,
+	pub content: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct MessageSearchHit {
+	pub message: Message,
+	// the words of the message's content which matched the search terms
+	pub highlights: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct MessageSearchResults {
+	// the amount of messages matching the filters, across all pages
+	pub total_results: u32,
+	pub hits: Vec<MessageSearchHit>,
+}
+
+pub const MAX_SEARCH_TERMS: usize = 10;
+
+// splits the search text into words, leaving out the full-text boolean mode operators
+pub fn search_terms(text: &str) -> Vec<String> {
+	let mut terms = Vec::new();
+
+	for term in text
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|term| !term.is_empty())
+		.map(str::to_lowercase)
+	{
+		if !terms.contains(&term) {
+			terms.push(term);
+		}
+	}
+
+	terms.truncate(MAX_SEARCH_TERMS);
+	terms
+}
+
+// every term is required and matches words starting with it
+pub fn search_boolean_query(terms: &[String]) -> String {
+	terms
+		.iter()
+		.map(|term| format!("+{term}*"))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+pub fn search_highlights(content: &str, terms: &[String]) -> Vec<String> {
+	let mut highlights = Vec::<String>::new();
+
+	for word in content
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+	{
+		let lowercase = word.to_lowercase();
+
+		if terms
+			.iter()
+			.any(|term| lowercase.starts_with(term.as_str()))
+			&& !highlights.iter().any(|highlight| highlight == word)
+		{
+			highlights.push(word.to_string());
+		}
+	}
+
+	highlights
 }
@@ -1,16 +1,28 @@
 use serde::Serializer;
 
+pub mod attachment;
+pub mod auditlog;
 pub mod auth;
+pub mod ban;
+pub mod block;
 pub mod channel;
 pub mod client;
 pub mod friend;
 pub mod friendrequest;
 pub mod invite;
 pub mod message;
+pub mod overwrite;
 pub mod passkey;
+pub mod permissions;
+pub mod presence;
+pub mod reaction;
+pub mod readstate;
+pub mod role;
 pub mod scope;
 pub mod server;
 pub mod servermember;
+pub mod settings;
+pub mod signingkey;
 pub mod user;
 
 // sending 64-bit integers will not work in JavaScript and other languages
@@ -25,6 +37,36 @@ pub fn opt_id_str<S: Serializer>(id: &Option<u64>, s: S) -> Result<S::Ok, S::Err
 	}
 }
 
+pub fn opt_opt_id_str<S: Serializer>(id: &Option<Option<u64>>, s: S) -> Result<S::Ok, S::Error> {
+	match id {
+		Some(id) => opt_id_str(id, s),
+		None => s.serialize_none(),
+	}
+}
+
+pub fn opt_ids_str<S: Serializer>(ids: &Option<Vec<u64>>, s: S) -> Result<S::Ok, S::Error> {
+	match ids {
+		Some(ids) => s.collect_seq(ids.iter().map(|id| id.to_string())),
+		None => s.serialize_none(),
+	}
+}
+
+// the smallest snowflake which could have been generated at the given time
+pub fn snowflake_at(at: chrono::DateTime<chrono::Utc>) -> u64 {
+	let millis = (at.timestamp_millis().max(0) as u64)
+		.saturating_sub(crate::SNOWFLAKE_EPOCH.as_millis() as u64);
+
+	millis << 22
+}
+
+// when the snowflake was generated, to the millisecond
+pub fn snowflake_time(id: u64) -> chrono::DateTime<chrono::Utc> {
+	chrono::DateTime::from_timestamp_millis(
+		(crate::SNOWFLAKE_EPOCH.as_millis() as u64 + (id >> 22)) as i64,
+	)
+	.unwrap_or_default()
+}
+
 pub fn id_to_uuid(id: u64) -> webauthn_rs::prelude::Uuid {
 	webauthn_rs::prelude::Uuid::from_u64_pair(0, id)
 }
@@ -0,0 +1,52 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::models::permissions::Permissions;
+use serde::Serialize;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use ts_rs::TS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerializeDisplay, DeserializeFromStr, TS, Hash)]
+pub enum OverwriteKind {
+	#[ts(rename = "role")]
+	Role,
+	#[ts(rename = "member")]
+	Member,
+}
+
+impl Display for OverwriteKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			OverwriteKind::Role => write!(f, "role"),
+			OverwriteKind::Member => write!(f, "member"),
+		}
+	}
+}
+
+impl FromStr for OverwriteKind {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"role" => Ok(OverwriteKind::Role),
+			"member" => Ok(OverwriteKind::Member),
+			_ => Err(format!("Invalid overwrite kind: {}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct PermissionOverwrite {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub channel_id: u64,
+	// a role id, or a user id for member overwrites
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub target_id: u64,
+	pub kind: OverwriteKind,
+	#[ts(type = "`${number}`")]
+	pub allow: Permissions,
+	#[ts(type = "`${number}`")]
+	pub deny: Permissions,
+}
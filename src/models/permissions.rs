@@ -0,0 +1,616 @@
+use crate::{
+	error::BackendError,
+	models::overwrite::{OverwriteKind, PermissionOverwrite},
+};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use sqlx::{query, Executor, MySql, MySqlPool};
+use std::{
+	collections::{HashMap, HashSet},
+	fmt::Display,
+	ops::{BitAnd, BitOr, BitOrAssign, Not},
+	str::FromStr,
+};
+
+#[derive(
+	Clone, Copy, Debug, Default, PartialEq, Eq, Hash, SerializeDisplay, DeserializeFromStr,
+)]
+pub struct Permissions(u64);
+
+macro_rules! permissions {
+    ($($name:ident = $bit:literal,)*) => {
+        impl Permissions {
+            $(pub const $name: Self = Self(1 << $bit);)*
+
+            pub const ALL: Self = Self($((1 << $bit))|*);
+        }
+    };
+}
+
+permissions! {
+	ADMINISTRATOR = 0,
+	MANAGE_SERVER = 1,
+	MANAGE_ROLES = 2,
+	MANAGE_CHANNELS = 3,
+	MANAGE_INVITES = 4,
+	KICK_MEMBERS = 5,
+	MANAGE_MESSAGES = 6,
+	MANAGE_NICKNAMES = 7,
+	VIEW_CHANNEL = 8,
+	SEND_MESSAGES = 9,
+	ADD_REACTIONS = 10,
+	MANAGE_THREADS = 11,
+	BAN_MEMBERS = 12,
+	MODERATE_MEMBERS = 13,
+	VIEW_AUDIT_LOG = 14,
+}
+
+impl Permissions {
+	// what the @everyone role of a new server is granted
+	pub const DEFAULT: Self =
+		Self(Self::VIEW_CHANNEL.0 | Self::SEND_MESSAGES.0 | Self::ADD_REACTIONS.0);
+
+	pub const fn empty() -> Self {
+		Self(0)
+	}
+
+	pub const fn from_bits_truncate(bits: u64) -> Self {
+		Self(bits & Self::ALL.0)
+	}
+
+	pub const fn bits(self) -> u64 {
+		self.0
+	}
+
+	pub const fn contains(self, other: Self) -> bool {
+		self.0 & other.0 == other.0
+	}
+}
+
+impl BitOr for Permissions {
+	type Output = Self;
+
+	fn bitor(self, rhs: Self) -> Self::Output {
+		Self(self.0 | rhs.0)
+	}
+}
+
+impl BitOrAssign for Permissions {
+	fn bitor_assign(&mut self, rhs: Self) {
+		self.0 |= rhs.0;
+	}
+}
+
+impl BitAnd for Permissions {
+	type Output = Self;
+
+	fn bitand(self, rhs: Self) -> Self::Output {
+		Self(self.0 & rhs.0)
+	}
+}
+
+impl Not for Permissions {
+	type Output = Self;
+
+	fn not(self) -> Self::Output {
+		Self(!self.0 & Self::ALL.0)
+	}
+}
+
+impl Display for Permissions {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl FromStr for Permissions {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let bits = s
+			.parse::<u64>()
+			.map_err(|_| format!("Invalid permissions: {}", s))?;
+
+		if bits & !Self::ALL.0 != 0 {
+			return Err(format!("Unknown permissions: {}", s));
+		}
+
+		Ok(Self(bits))
+	}
+}
+
+fn resolve_server_permissions<I: IntoIterator<Item = Permissions>>(
+	is_owner: bool,
+	role_permissions: I,
+) -> Permissions {
+	if is_owner {
+		return Permissions::ALL;
+	}
+
+	let permissions = role_permissions
+		.into_iter()
+		.fold(Permissions::empty(), |permissions, role_permissions| {
+			permissions | role_permissions
+		});
+
+	if permissions.contains(Permissions::ADMINISTRATOR) {
+		return Permissions::ALL;
+	}
+
+	permissions
+}
+
+// returns the member's permissions along with the ids of their roles (excluding @everyone),
+// or None if the user isn't a member of the server
+pub async fn get_member_permissions<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	server_id: u64,
+	user_id: u64,
+) -> Result<Option<(Permissions, Vec<u64>)>, BackendError> {
+	let rows = query!(
+		r#"SELECT Server.owner_id, ServerRole.id, ServerRole.permissions
+FROM ServerMember
+INNER JOIN Server ON Server.id=ServerMember.server_id
+INNER JOIN ServerRole ON ServerRole.server_id=ServerMember.server_id
+LEFT JOIN ServerMemberRole ON ServerMemberRole.role_id=ServerRole.id AND ServerMemberRole.user_id=ServerMember.user_id
+WHERE ServerMember.server_id = ? AND ServerMember.user_id = ? AND (ServerRole.id = ServerMember.server_id OR ServerMemberRole.role_id IS NOT NULL)
+"#,
+		server_id,
+		user_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	let Some(first) = rows.first() else {
+		return Ok(None);
+	};
+
+	let permissions = resolve_server_permissions(
+		first.owner_id == user_id,
+		rows.iter()
+			.map(|row| Permissions::from_bits_truncate(row.permissions)),
+	);
+
+	let roles = rows
+		.iter()
+		.map(|row| row.id)
+		.filter(|role_id| *role_id != server_id)
+		.collect();
+
+	Ok(Some((permissions, roles)))
+}
+
+// the same as get_member_permissions for every server the user is a member of, keyed by server id
+pub async fn get_all_member_permissions<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<HashMap<u64, (Permissions, Vec<u64>)>, BackendError> {
+	let rows = query!(
+		r#"SELECT ServerMember.server_id, Server.owner_id, ServerRole.id, ServerRole.permissions
+FROM ServerMember
+INNER JOIN Server ON Server.id=ServerMember.server_id
+INNER JOIN ServerRole ON ServerRole.server_id=ServerMember.server_id
+LEFT JOIN ServerMemberRole ON ServerMemberRole.role_id=ServerRole.id AND ServerMemberRole.user_id=ServerMember.user_id
+WHERE ServerMember.user_id = ? AND (ServerRole.id = ServerMember.server_id OR ServerMemberRole.role_id IS NOT NULL)
+"#,
+		user_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	let mut servers = HashMap::<u64, (bool, Vec<Permissions>, Vec<u64>)>::new();
+
+	for row in rows {
+		let (is_owner, role_permissions, roles) = servers.entry(row.server_id).or_default();
+
+		*is_owner = row.owner_id == user_id;
+		role_permissions.push(Permissions::from_bits_truncate(row.permissions));
+		if row.id != row.server_id {
+			roles.push(row.id);
+		}
+	}
+
+	Ok(servers
+		.into_iter()
+		.map(|(server_id, (is_owner, role_permissions, roles))| {
+			(
+				server_id,
+				(
+					resolve_server_permissions(is_owner, role_permissions),
+					roles,
+				),
+			)
+		})
+		.collect())
+}
+
+// returns None if the user isn't a member of the server
+pub async fn get_server_permissions<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	server_id: u64,
+	user_id: u64,
+) -> Result<Option<Permissions>, BackendError> {
+	Ok(get_member_permissions(executor, server_id, user_id)
+		.await?
+		.map(|(permissions, _)| permissions))
+}
+
+pub async fn has_server_permission<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	server_id: u64,
+	user_id: u64,
+	permission: Permissions,
+) -> Result<bool, BackendError> {
+	Ok(get_server_permissions(executor, server_id, user_id)
+		.await?
+		.is_some_and(|permissions| permissions.contains(permission)))
+}
+
+// overwrites are applied in order of precedence: @everyone, then the member's roles, then the member
+pub fn apply_overwrites(
+	permissions: Permissions,
+	server_id: u64,
+	user_id: u64,
+	roles: &[u64],
+	overwrites: &[PermissionOverwrite],
+) -> Permissions {
+	if permissions.contains(Permissions::ADMINISTRATOR) {
+		return permissions;
+	}
+
+	let mut permissions = permissions;
+
+	if let Some(overwrite) = overwrites
+		.iter()
+		.find(|overwrite| overwrite.kind == OverwriteKind::Role && overwrite.target_id == server_id)
+	{
+		permissions = (permissions & !overwrite.deny) | overwrite.allow;
+	}
+
+	let (allow, deny) = overwrites
+		.iter()
+		.filter(|overwrite| {
+			overwrite.kind == OverwriteKind::Role && roles.contains(&overwrite.target_id)
+		})
+		.fold(
+			(Permissions::empty(), Permissions::empty()),
+			|(allow, deny), overwrite| (allow | overwrite.allow, deny | overwrite.deny),
+		);
+
+	permissions = (permissions & !deny) | allow;
+
+	if let Some(overwrite) = overwrites
+		.iter()
+		.find(|overwrite| overwrite.kind == OverwriteKind::Member && overwrite.target_id == user_id)
+	{
+		permissions = (permissions & !overwrite.deny) | overwrite.allow;
+	}
+
+	permissions
+}
+
+// threads don't have overwrites of their own, they inherit the ones of their parent channel
+pub async fn get_channel_overwrites<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	channel_id: u64,
+) -> Result<Vec<PermissionOverwrite>, BackendError> {
+	Ok(query!(
+		"SELECT channel_id, target_id, kind, allow, deny FROM ChannelPermissionOverwrite WHERE channel_id = COALESCE((SELECT parent_id FROM ChannelThread WHERE channel_id = ?), ?)",
+		channel_id,
+		channel_id
+	)
+	.fetch_all(executor)
+	.await?
+	.into_iter()
+	.map(|row| PermissionOverwrite {
+		channel_id: row.channel_id,
+		target_id: row.target_id,
+		kind: row.kind.parse().unwrap(),
+		allow: Permissions::from_bits_truncate(row.allow),
+		deny: Permissions::from_bits_truncate(row.deny),
+	})
+	.collect())
+}
+
+// the overwrites of every channel in the server, keyed by channel id
+pub async fn get_server_overwrites<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	server_id: u64,
+) -> Result<HashMap<u64, Vec<PermissionOverwrite>>, BackendError> {
+	let rows = query!(
+		r#"SELECT ChannelPermissionOverwrite.channel_id, ChannelPermissionOverwrite.target_id, ChannelPermissionOverwrite.kind, ChannelPermissionOverwrite.allow, ChannelPermissionOverwrite.deny
+FROM ChannelPermissionOverwrite
+INNER JOIN Channel ON Channel.id=ChannelPermissionOverwrite.channel_id
+WHERE Channel.server_id = ?
+"#,
+		server_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	let mut overwrites = HashMap::<u64, Vec<PermissionOverwrite>>::new();
+
+	for row in rows {
+		overwrites
+			.entry(row.channel_id)
+			.or_default()
+			.push(PermissionOverwrite {
+				channel_id: row.channel_id,
+				target_id: row.target_id,
+				kind: row.kind.parse().unwrap(),
+				allow: Permissions::from_bits_truncate(row.allow),
+				deny: Permissions::from_bits_truncate(row.deny),
+			});
+	}
+
+	Ok(overwrites)
+}
+
+// the overwrites of every channel in the servers the user is a member of, keyed by channel id
+pub async fn get_member_overwrites<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<HashMap<u64, Vec<PermissionOverwrite>>, BackendError> {
+	let rows = query!(
+		r#"SELECT ChannelPermissionOverwrite.channel_id, ChannelPermissionOverwrite.target_id, ChannelPermissionOverwrite.kind, ChannelPermissionOverwrite.allow, ChannelPermissionOverwrite.deny
+FROM ChannelPermissionOverwrite
+INNER JOIN Channel ON Channel.id=ChannelPermissionOverwrite.channel_id
+INNER JOIN ServerMember ON ServerMember.server_id=Channel.server_id
+WHERE ServerMember.user_id = ?
+"#,
+		user_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	let mut overwrites = HashMap::<u64, Vec<PermissionOverwrite>>::new();
+
+	for row in rows {
+		overwrites
+			.entry(row.channel_id)
+			.or_default()
+			.push(PermissionOverwrite {
+				channel_id: row.channel_id,
+				target_id: row.target_id,
+				kind: row.kind.parse().unwrap(),
+				allow: Permissions::from_bits_truncate(row.allow),
+				deny: Permissions::from_bits_truncate(row.deny),
+			});
+	}
+
+	Ok(overwrites)
+}
+
+// returns None if the user isn't a member of the channel's server
+pub async fn get_channel_permissions(
+	db: &MySqlPool,
+	server_id: u64,
+	channel_id: u64,
+	user_id: u64,
+) -> Result<Option<Permissions>, BackendError> {
+	let Some((permissions, roles)) = get_member_permissions(db, server_id, user_id).await? else {
+		return Ok(None);
+	};
+
+	if permissions.contains(Permissions::ADMINISTRATOR) {
+		return Ok(Some(permissions));
+	}
+
+	let overwrites = get_channel_overwrites(db, channel_id).await?;
+
+	Ok(Some(apply_overwrites(
+		permissions,
+		server_id,
+		user_id,
+		&roles,
+		&overwrites,
+	)))
+}
+
+pub async fn has_channel_permission(
+	db: &MySqlPool,
+	server_id: u64,
+	channel_id: u64,
+	user_id: u64,
+	permission: Permissions,
+) -> Result<bool, BackendError> {
+	Ok(get_channel_permissions(db, server_id, channel_id, user_id)
+		.await?
+		.is_some_and(|permissions| permissions.contains(permission)))
+}
+
+// narrows the given members of the server down to the ones who can view the channel
+pub async fn get_channel_viewers<I: IntoIterator<Item = u64>>(
+	db: &MySqlPool,
+	server_id: u64,
+	channel_id: u64,
+	users: I,
+) -> Result<HashSet<u64>, BackendError> {
+	let roles = query!(
+		r#"SELECT Server.owner_id, ServerRole.id, ServerRole.permissions
+FROM Server
+INNER JOIN ServerRole ON ServerRole.server_id=Server.id
+WHERE Server.id = ?
+"#,
+		server_id
+	)
+	.fetch_all(db)
+	.await?;
+
+	let Some(owner_id) = roles.first().map(|row| row.owner_id) else {
+		return Ok(HashSet::new());
+	};
+
+	let role_permissions = roles
+		.into_iter()
+		.map(|row| (row.id, Permissions::from_bits_truncate(row.permissions)))
+		.collect::<HashMap<_, _>>();
+
+	let everyone = role_permissions
+		.get(&server_id)
+		.copied()
+		.unwrap_or_default();
+
+	let overwrites = get_channel_overwrites(db, channel_id).await?;
+
+	if overwrites.is_empty() && everyone.contains(Permissions::VIEW_CHANNEL) {
+		return Ok(users.into_iter().collect());
+	}
+
+	let mut member_roles = HashMap::<u64, Vec<u64>>::new();
+
+	for row in query!(
+		"SELECT user_id, role_id FROM ServerMemberRole WHERE server_id = ?",
+		server_id
+	)
+	.fetch_all(db)
+	.await?
+	{
+		member_roles
+			.entry(row.user_id)
+			.or_default()
+			.push(row.role_id);
+	}
+
+	Ok(users
+		.into_iter()
+		.filter(|user_id| {
+			let roles = member_roles
+				.get(user_id)
+				.map(Vec::as_slice)
+				.unwrap_or_default();
+
+			let permissions = resolve_server_permissions(
+				*user_id == owner_id,
+				std::iter::once(everyone).chain(
+					roles
+						.iter()
+						.filter_map(|role_id| role_permissions.get(role_id).copied()),
+				),
+			);
+
+			apply_overwrites(permissions, server_id, *user_id, roles, &overwrites)
+				.contains(Permissions::VIEW_CHANNEL)
+		})
+		.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SERVER_ID: u64 = 1;
+	const USER_ID: u64 = 2;
+	const ROLE_ID: u64 = 3;
+	const OTHER_ROLE_ID: u64 = 4;
+
+	fn overwrite(
+		kind: OverwriteKind,
+		target_id: u64,
+		allow: Permissions,
+		deny: Permissions,
+	) -> PermissionOverwrite {
+		PermissionOverwrite {
+			channel_id: 10,
+			target_id,
+			kind,
+			allow,
+			deny,
+		}
+	}
+
+	#[test]
+	fn keeps_permissions_without_overwrites() {
+		assert_eq!(
+			apply_overwrites(Permissions::DEFAULT, SERVER_ID, USER_ID, &[], &[]),
+			Permissions::DEFAULT
+		);
+	}
+
+	#[test]
+	fn applies_overwrites_in_order_of_precedence() {
+		let overwrites = [
+			// @everyone can't view the channel
+			overwrite(
+				OverwriteKind::Role,
+				SERVER_ID,
+				Permissions::empty(),
+				Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+			),
+			// but the role can
+			overwrite(
+				OverwriteKind::Role,
+				ROLE_ID,
+				Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+				Permissions::empty(),
+			),
+			// and the member can't send messages there
+			overwrite(
+				OverwriteKind::Member,
+				USER_ID,
+				Permissions::empty(),
+				Permissions::SEND_MESSAGES,
+			),
+		];
+
+		assert_eq!(
+			apply_overwrites(
+				Permissions::DEFAULT,
+				SERVER_ID,
+				USER_ID,
+				&[ROLE_ID],
+				&overwrites
+			),
+			Permissions::VIEW_CHANNEL | Permissions::ADD_REACTIONS
+		);
+
+		// without the role, only the @everyone overwrite applies to other members
+		assert_eq!(
+			apply_overwrites(Permissions::DEFAULT, SERVER_ID, 5, &[], &overwrites),
+			Permissions::ADD_REACTIONS
+		);
+	}
+
+	#[test]
+	fn role_allows_take_precedence_over_role_denies() {
+		let overwrites = [
+			overwrite(
+				OverwriteKind::Role,
+				ROLE_ID,
+				Permissions::empty(),
+				Permissions::SEND_MESSAGES,
+			),
+			overwrite(
+				OverwriteKind::Role,
+				OTHER_ROLE_ID,
+				Permissions::SEND_MESSAGES,
+				Permissions::empty(),
+			),
+		];
+
+		assert_eq!(
+			apply_overwrites(
+				Permissions::DEFAULT,
+				SERVER_ID,
+				USER_ID,
+				&[ROLE_ID, OTHER_ROLE_ID],
+				&overwrites
+			),
+			Permissions::DEFAULT
+		);
+	}
+
+	#[test]
+	fn ignores_overwrites_for_administrators() {
+		let overwrites = [overwrite(
+			OverwriteKind::Member,
+			USER_ID,
+			Permissions::empty(),
+			Permissions::VIEW_CHANNEL,
+		)];
+
+		assert_eq!(
+			apply_overwrites(Permissions::ALL, SERVER_ID, USER_ID, &[], &overwrites),
+			Permissions::ALL
+		);
+	}
+}
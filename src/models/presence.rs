@@ -0,0 +1,81 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::Serialize;
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+use ts_rs::TS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SerializeDisplay, DeserializeFromStr, TS, Hash)]
+pub enum PresenceStatus {
+	#[ts(rename = "online")]
+	Online,
+	#[ts(rename = "idle")]
+	Idle,
+	#[ts(rename = "dnd")]
+	Dnd,
+	#[ts(rename = "invisible")]
+	Invisible,
+	// never stored, only shown for users without a connected session
+	#[ts(rename = "offline")]
+	Offline,
+}
+
+impl Display for PresenceStatus {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			PresenceStatus::Online => write!(f, "online"),
+			PresenceStatus::Idle => write!(f, "idle"),
+			PresenceStatus::Dnd => write!(f, "dnd"),
+			PresenceStatus::Invisible => write!(f, "invisible"),
+			PresenceStatus::Offline => write!(f, "offline"),
+		}
+	}
+}
+
+impl FromStr for PresenceStatus {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"online" => Ok(PresenceStatus::Online),
+			"idle" => Ok(PresenceStatus::Idle),
+			"dnd" => Ok(PresenceStatus::Dnd),
+			"invisible" => Ok(PresenceStatus::Invisible),
+			"offline" => Ok(PresenceStatus::Offline),
+			_ => Err(format!("Invalid presence status: {}", s)),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct Presence {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub user_id: u64,
+	pub status: PresenceStatus,
+	pub custom_status: Option<String>,
+}
+
+impl Presence {
+	// how the user appears to others: invisible users and users without a connected session are offline
+	pub fn visible(
+		user_id: u64,
+		status: PresenceStatus,
+		custom_status: Option<String>,
+		connected: bool,
+	) -> Self {
+		if !connected || status == PresenceStatus::Invisible {
+			return Presence {
+				user_id,
+				status: PresenceStatus::Offline,
+				custom_status: None,
+			};
+		}
+
+		Presence {
+			user_id,
+			status,
+			custom_status,
+		}
+	}
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct Reaction {
+	pub emoji: String,
+	pub count: u32,
+	// whether the current user has reacted with this emoji
+	pub me: bool,
+}
+
+// only unicode emojis are supported, which can't be validated exhaustively without a dataset
+pub fn is_valid_emoji(emoji: &str) -> bool {
+	!emoji.is_empty()
+		&& emoji.len() <= 64
+		&& !emoji.is_ascii()
+		&& !emoji
+			.chars()
+			.any(|c| c.is_whitespace() || c.is_control() || c.is_alphabetic())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn accepts_unicode_emojis() {
+		for emoji in ["👍", "❤️", "🇫🇷", "👍🏽", "👨‍👩‍👧"] {
+			assert!(is_valid_emoji(emoji), "{emoji}");
+		}
+	}
+
+	#[test]
+	fn rejects_text() {
+		for emoji in ["", "a", ":thumbsup:", "<3", "é", "日本", "👍a"] {
+			assert!(!is_valid_emoji(emoji), "{emoji}");
+		}
+	}
+
+	#[test]
+	fn rejects_whitespace_and_control_chars() {
+		for emoji in ["👍 👍", "👍\n", "\u{a0}👍", "👍\u{7f}"] {
+			assert!(!is_valid_emoji(emoji), "{emoji:?}");
+		}
+	}
+
+	#[test]
+	fn rejects_long_sequences() {
+		assert!(is_valid_emoji(&"👍".repeat(16)));
+		assert!(!is_valid_emoji(&"👍".repeat(17)));
+	}
+}
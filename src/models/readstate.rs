@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::error::BackendError;
+use serde::Serialize;
+use sqlx::{query, Executor, MySql};
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct ReadState {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub channel_id: u64,
+	#[serde(serialize_with = "super::opt_id_str")]
+	#[ts(type = "`${number}` | null")]
+	pub last_read_message_id: Option<u64>,
+	// messages of other users sent after the last read message
+	pub unread_count: u32,
+	pub mention_count: u32,
+}
+
+// users are mentioned by including <@user_id> in the content of a message, which clients render as the user's name.
+// there's no separate list of mentions, so a message mentions whoever it contains the syntax for
+pub fn mention_pattern(user_id: u64) -> String {
+	format!("%<@{user_id}>%")
+}
+
+// members start out having read everything sent before they joined, so that only newer messages count as unread
+pub async fn seed_read_states<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	server_id: u64,
+) -> Result<(), BackendError> {
+	query!(
+		r#"INSERT INTO ChannelReadState (channel_id, user_id, last_read_message_id)
+SELECT ChannelMessage.channel_id, ?, MAX(ChannelMessage.id)
+FROM ChannelMessage
+INNER JOIN Channel ON Channel.id=ChannelMessage.channel_id
+WHERE Channel.server_id = ?
+GROUP BY ChannelMessage.channel_id
+ON DUPLICATE KEY UPDATE last_read_message_id = GREATEST(last_read_message_id, VALUES(last_read_message_id))
+"#,
+		user_id,
+		server_id
+	)
+	.execute(executor)
+	.await?;
+
+	Ok(())
+}
+
+// the same as seed_read_states, for a recipient added to a group DM
+pub async fn seed_read_state<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	channel_id: u64,
+) -> Result<(), BackendError> {
+	query!(
+		r#"INSERT INTO ChannelReadState (channel_id, user_id, last_read_message_id)
+SELECT channel_id, ?, MAX(id)
+FROM ChannelMessage
+WHERE channel_id = ?
+GROUP BY channel_id
+ON DUPLICATE KEY UPDATE last_read_message_id = GREATEST(last_read_message_id, VALUES(last_read_message_id))
+"#,
+		user_id,
+		channel_id
+	)
+	.execute(executor)
+	.await?;
+
+	Ok(())
+}
+
+// the read states of the server's channels, or of the user's direct channels if server_id is None.
+// threads are left out, just like they are from the channel lists
+pub async fn get_read_states<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	server_id: Option<u64>,
+) -> Result<HashMap<u64, ReadState>, BackendError> {
+	Ok(query!(
+		r#"SELECT Channel.id, ChannelReadState.last_read_message_id AS `last_read_message_id?`,
+COUNT(ChannelMessage.id) AS unread_count,
+COUNT(CASE WHEN ChannelMessage.content LIKE ? THEN 1 END) AS mention_count
+FROM Channel
+LEFT JOIN ChannelReadState ON ChannelReadState.channel_id=Channel.id AND ChannelReadState.user_id=?
+LEFT JOIN ChannelMessage ON ChannelMessage.channel_id=Channel.id AND ChannelMessage.id > COALESCE(ChannelReadState.last_read_message_id, 0) AND ChannelMessage.user_id != ?
+WHERE Channel.server_id <=> ? AND Channel.kind != 'thread'
+AND (Channel.server_id IS NOT NULL OR EXISTS(SELECT 1 FROM DMChannelRecipient WHERE DMChannelRecipient.channel_id=Channel.id AND DMChannelRecipient.user_id=?))
+GROUP BY Channel.id, ChannelReadState.last_read_message_id
+"#,
+		mention_pattern(user_id),
+		user_id,
+		user_id,
+		server_id,
+		user_id
+	)
+	.fetch_all(executor)
+	.await?
+	.into_iter()
+	.map(|row| {
+		(
+			row.id,
+			ReadState {
+				channel_id: row.id,
+				last_read_message_id: row.last_read_message_id,
+				unread_count: row.unread_count as u32,
+				mention_count: row.mention_count as u32,
+			},
+		)
+	})
+	.collect())
+}
+
+// the read states of every server channel of the servers the user is a member of, keyed by server id
+pub async fn get_server_read_states<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<HashMap<u64, Vec<ReadState>>, BackendError> {
+	let rows = query!(
+		r#"SELECT Channel.id, ServerMember.server_id, ChannelReadState.last_read_message_id AS `last_read_message_id?`,
+COUNT(ChannelMessage.id) AS unread_count,
+COUNT(CASE WHEN ChannelMessage.content LIKE ? THEN 1 END) AS mention_count
+FROM ServerMember
+INNER JOIN Channel ON Channel.server_id=ServerMember.server_id AND Channel.kind != 'thread'
+LEFT JOIN ChannelReadState ON ChannelReadState.channel_id=Channel.id AND ChannelReadState.user_id=ServerMember.user_id
+LEFT JOIN ChannelMessage ON ChannelMessage.channel_id=Channel.id AND ChannelMessage.id > COALESCE(ChannelReadState.last_read_message_id, 0) AND ChannelMessage.user_id != ServerMember.user_id
+WHERE ServerMember.user_id = ?
+GROUP BY Channel.id, ServerMember.server_id, ChannelReadState.last_read_message_id
+"#,
+		mention_pattern(user_id),
+		user_id
+	)
+	.fetch_all(executor)
+	.await?;
+
+	let mut read_states = HashMap::<u64, Vec<ReadState>>::new();
+
+	for row in rows {
+		read_states
+			.entry(row.server_id)
+			.or_default()
+			.push(ReadState {
+				channel_id: row.id,
+				last_read_message_id: row.last_read_message_id,
+				unread_count: row.unread_count as u32,
+				mention_count: row.mention_count as u32,
+			});
+	}
+
+	Ok(read_states)
+}
+
+pub async fn get_read_state<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+	channel_id: u64,
+) -> Result<ReadState, BackendError> {
+	let row = query!(
+		r#"SELECT ChannelReadState.last_read_message_id AS `last_read_message_id?`,
+COUNT(ChannelMessage.id) AS unread_count,
+COUNT(CASE WHEN ChannelMessage.content LIKE ? THEN 1 END) AS mention_count
+FROM Channel
+LEFT JOIN ChannelReadState ON ChannelReadState.channel_id=Channel.id AND ChannelReadState.user_id=?
+LEFT JOIN ChannelMessage ON ChannelMessage.channel_id=Channel.id AND ChannelMessage.id > COALESCE(ChannelReadState.last_read_message_id, 0) AND ChannelMessage.user_id != ?
+WHERE Channel.id = ?
+GROUP BY Channel.id, ChannelReadState.last_read_message_id
+"#,
+		mention_pattern(user_id),
+		user_id,
+		user_id,
+		channel_id
+	)
+	.fetch_one(executor)
+	.await?;
+
+	Ok(ReadState {
+		channel_id,
+		last_read_message_id: row.last_read_message_id,
+		unread_count: row.unread_count as u32,
+		mention_count: row.mention_count as u32,
+	})
+}
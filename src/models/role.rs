@@ -0,0 +1,17 @@
+use crate::models::permissions::Permissions;
+use serde::Serialize;
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct ServerRole {
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub id: u64,
+	#[serde(serialize_with = "super::id_str")]
+	#[ts(type = "`${number}`")]
+	pub server_id: u64,
+	pub name: String,
+	#[ts(type = "`${number}`")]
+	pub permissions: Permissions,
+}
@@ -29,6 +29,7 @@ macro_rules! scopes {
             from_str_impl: [$($from_str_impl:tt)*],
             access_impl: [$($access_impl:tt)*],
             not_impl: [$($not_impl:tt)*],
+            all_impl: [$($all_impl:tt)*],
         }
     } => {
             #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, SerializeDisplay, DeserializeFromStr, TS)]
@@ -38,6 +39,9 @@ macro_rules! scopes {
             }
 
             impl $enum_name {
+                // every scope, with both read and write access for those which have it
+                pub const ALL: &'static [Self] = &[$($all_impl)*];
+
                 pub fn access(&self) -> Option<ReadWrite> {
                     match self {
                         $($access_impl)*
@@ -80,6 +84,49 @@ macro_rules! scopes {
             }
     };
 
+    {
+        $enum_name:ident,
+        @impl [
+            bare $scope:ident = $name:literal,
+            $($tail:tt)*
+        ] -> {
+            scope_enum: [$($scope_enum:tt)*],
+            display_impl: [$($display_impl:tt)*],
+            from_str_impl: [$($from_str_impl:tt)*],
+            access_impl: [$($access_impl:tt)*],
+            not_impl: [$($not_impl:tt)*],
+            all_impl: [$($all_impl:tt)*],
+        }
+    } => {
+        scopes! {
+            $enum_name,
+            @impl [$($tail)*] -> {
+                scope_enum: [
+                    $($scope_enum)*
+                    $scope,
+                ],
+                display_impl: [
+                    $($display_impl)*
+                    Self::$scope => $name,
+                ],
+                from_str_impl: [
+                    $($from_str_impl)*
+                    $name => Ok(Self::$scope),
+                ],
+                access_impl: [
+                    $($access_impl)*
+                ],
+                not_impl: [
+                    $($not_impl)*
+                ],
+                all_impl: [
+                    $($all_impl)*
+                    Self::$scope,
+                ],
+            }
+        }
+    };
+
     {
         $enum_name:ident,
         @impl [
@@ -91,6 +138,7 @@ macro_rules! scopes {
             from_str_impl: [$($from_str_impl:tt)*],
             access_impl: [$($access_impl:tt)*],
             not_impl: [$($not_impl:tt)*],
+            all_impl: [$($all_impl:tt)*],
         }
     } => {
         scopes! {
@@ -114,6 +162,10 @@ macro_rules! scopes {
                 not_impl: [
                     $($not_impl)*
                 ],
+                all_impl: [
+                    $($all_impl)*
+                    Self::$scope,
+                ],
             }
         }
     };
@@ -129,6 +181,7 @@ macro_rules! scopes {
             from_str_impl: [$($from_str_impl:tt)*],
             access_impl: [$($access_impl:tt)*],
             not_impl: [$($not_impl:tt)*],
+            all_impl: [$($all_impl:tt)*],
         }
     } => {
         scopes! {
@@ -156,6 +209,11 @@ macro_rules! scopes {
                     $($not_impl)*
                     Self::$scope(access) => Self::$scope(!access),
                 ],
+                all_impl: [
+                    $($all_impl)*
+                    Self::$scope(ReadWrite::Read),
+                    Self::$scope(ReadWrite::Write),
+                ],
             }
         }
     };
@@ -169,6 +227,7 @@ macro_rules! scopes {
                 from_str_impl: [],
                 access_impl: [],
                 not_impl: [],
+                all_impl: [],
             }
         }
     };
@@ -180,6 +239,7 @@ scopes! {
 	mut Servers = "servers",
 	mut Messages = "messages",
 	mut Friends = "friends",
+	bare OpenId = "openid",
 }
 
 pub fn has_scope(scopes: &HashSet<Scope>, scope: Scope) -> bool {
@@ -1,4 +1,4 @@
-use crate::models::user::User;
+use crate::models::{presence::Presence, user::User};
 use chrono::{DateTime, Utc};
 use serde::Serialize;
 use ts_rs::TS;
@@ -15,4 +15,20 @@ pub struct ServerMember {
 	pub created_at: DateTime<Utc>,
 	pub nickname: Option<String>,
 	pub user: Option<User>,
+	// None if the roles weren't loaded
+	#[serde(serialize_with = "super::opt_ids_str")]
+	#[ts(type = "Array<`${number}`> | null")]
+	pub roles: Option<Vec<u64>>,
+	// None if the member isn't timed out, or if it wasn't loaded
+	pub timed_out_until: Option<DateTime<Utc>>,
+	// temporary members are removed once they disconnect, unless they've been given a role
+	pub temporary: bool,
+	// the invite the member joined through, only shown to those who can manage invites
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub invite_id: Option<String>,
+	// only present when listing members
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(optional)]
+	pub presence: Option<Presence>,
 }
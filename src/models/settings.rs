@@ -0,0 +1,35 @@
+use crate::error::BackendError;
+use serde::Serialize;
+use sqlx::{query, Executor, MySql};
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
+#[ts(export)]
+pub struct UserSettings {
+	// whether users who only share a server with the user can open a DM with them
+	pub allow_server_member_dms: bool,
+}
+
+impl Default for UserSettings {
+	fn default() -> Self {
+		Self {
+			allow_server_member_dms: true,
+		}
+	}
+}
+
+pub async fn get_user_settings<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<UserSettings, BackendError> {
+	Ok(query!(
+		"SELECT allow_server_member_dms AS `allow_server_member_dms: bool` FROM UserSettings WHERE user_id = ?",
+		user_id
+	)
+	.fetch_optional(executor)
+	.await?
+	.map(|row| UserSettings {
+		allow_server_member_dms: row.allow_server_member_dms,
+	})
+	.unwrap_or_default())
+}
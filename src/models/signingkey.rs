@@ -0,0 +1,140 @@
+use std::sync::LazyLock;
+
+use crate::error::BackendError;
+use actix_web::web;
+use base64::Engine;
+use cuid2::CuidConstructor;
+use openssl::{
+	hash::MessageDigest,
+	pkey::{PKey, Private},
+	rsa::Rsa,
+	sign::Signer,
+};
+use serde::Serialize;
+use sqlx::{query, MySqlPool};
+
+// a key signs tokens for this many days, after which a new one is generated
+const SIGNING_DAYS: i32 = 7;
+// keys stay published for a while after being rotated out, so the tokens they signed can still be verified
+const PUBLISHED_DAYS: i32 = SIGNING_DAYS * 2;
+
+static KEY_ID_GENERATOR: LazyLock<CuidConstructor> =
+	LazyLock::new(|| CuidConstructor::new().with_length(24));
+
+pub struct SigningKey {
+	pub id: String,
+	pub key: PKey<Private>,
+}
+
+// RFC 7517
+#[derive(Debug, Serialize)]
+pub struct Jwk {
+	kty: &'static str,
+	#[serde(rename = "use")]
+	use_: &'static str,
+	alg: &'static str,
+	kid: String,
+	n: String,
+	e: String,
+}
+
+fn b64(bytes: &[u8]) -> String {
+	base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl SigningKey {
+	fn from_der(id: String, der: &[u8]) -> Result<Self, BackendError> {
+		Ok(SigningKey {
+			id,
+			key: PKey::private_key_from_der(der)?,
+		})
+	}
+
+	pub fn jwk(&self) -> Result<Jwk, BackendError> {
+		let rsa = self.key.rsa()?;
+
+		Ok(Jwk {
+			kty: "RSA",
+			use_: "sig",
+			alg: "RS256",
+			kid: self.id.clone(),
+			n: b64(&rsa.n().to_vec()),
+			e: b64(&rsa.e().to_vec()),
+		})
+	}
+
+	// RFC 7519, signed with RS256
+	pub fn sign_jwt<T: Serialize>(&self, claims: &T) -> Result<String, BackendError> {
+		let header = serde_json::json!({
+			"alg": "RS256",
+			"typ": "JWT",
+			"kid": self.id,
+		});
+
+		let payload = format!(
+			"{}.{}",
+			b64(&serde_json::to_vec(&header)?),
+			b64(&serde_json::to_vec(claims)?)
+		);
+
+		let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+		signer.update(payload.as_bytes())?;
+
+		Ok(format!("{payload}.{}", b64(&signer.sign_to_vec()?)))
+	}
+}
+
+async fn get_newest_key(db: &MySqlPool) -> Result<Option<SigningKey>, BackendError> {
+	query!(
+		"SELECT id, private_key FROM SigningKey WHERE created_at > TIMESTAMPADD(DAY, ?, NOW()) ORDER BY created_at DESC, id DESC LIMIT 1",
+		-SIGNING_DAYS
+	)
+	.fetch_optional(db)
+	.await?
+	.map(|record| SigningKey::from_der(record.id, &record.private_key))
+	.transpose()
+}
+
+// the key tokens are currently signed with, which is rotated once it gets too old
+pub async fn get_signing_key(db: &MySqlPool) -> Result<SigningKey, BackendError> {
+	if let Some(key) = get_newest_key(db).await? {
+		return Ok(key);
+	}
+
+	// generating the key takes a while, so it isn't done on the async executor
+	let der = web::block(|| Rsa::generate(2048)?.private_key_to_der()).await??;
+
+	// the private keys are stored unencrypted, so the database has to be kept as secret as the keys themselves
+	query!(
+		"INSERT INTO SigningKey (id, private_key, created_at) VALUES (?, ?, DEFAULT)",
+		KEY_ID_GENERATOR.create_id(),
+		der
+	)
+	.execute(db)
+	.await?;
+
+	query!(
+		"DELETE FROM SigningKey WHERE created_at <= TIMESTAMPADD(DAY, ?, NOW())",
+		-PUBLISHED_DAYS
+	)
+	.execute(db)
+	.await?;
+
+	// concurrent rotations may each insert a key, so the newest one is used by all of them
+	get_newest_key(db)
+		.await?
+		.ok_or(BackendError::DB(sqlx::Error::RowNotFound))
+}
+
+// every key whose signatures may still be valid
+pub async fn get_published_keys(db: &MySqlPool) -> Result<Vec<SigningKey>, BackendError> {
+	query!(
+		"SELECT id, private_key FROM SigningKey WHERE created_at > TIMESTAMPADD(DAY, ?, NOW()) ORDER BY created_at DESC",
+		-PUBLISHED_DAYS
+	)
+	.fetch_all(db)
+	.await?
+	.into_iter()
+	.map(|record| SigningKey::from_der(record.id, &record.private_key))
+	.collect()
+}
@@ -1,4 +1,8 @@
+use std::collections::HashSet;
+
+use crate::error::BackendError;
 use serde::Serialize;
+use sqlx::{query, Executor, MySql};
 use ts_rs::TS;
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, TS, Hash)]
@@ -10,3 +14,60 @@ pub struct User {
 	pub username: String,
 	pub display_name: Option<String>,
 }
+
+// the users who can see the user: co-members, friends, pending friend requests and direct message recipients
+pub async fn get_associates<'a, E: Executor<'a, Database = MySql>>(
+	executor: E,
+	user_id: u64,
+) -> Result<HashSet<u64>, BackendError> {
+	Ok(query!(
+		r#"
+SELECT ServerMember.user_id
+FROM ServerMember
+INNER JOIN ServerMember AS UpdatedUser ON UpdatedUser.user_id=?
+WHERE ServerMember.server_id=UpdatedUser.server_id
+
+UNION
+    
+SELECT friend_id AS user_id
+FROM UserFriend
+WHERE user_id=?
+
+UNION
+
+SELECT user_id
+FROM UserFriend
+WHERE friend_id=?
+
+UNION
+
+SELECT receiver_id AS user_id
+FROM UserFriendRequest
+WHERE sender_id=?
+
+UNION
+
+SELECT sender_id AS user_id
+FROM UserFriendRequest
+WHERE receiver_id=?
+
+UNION
+
+SELECT Other.user_id
+FROM DMChannelRecipient
+INNER JOIN DMChannelRecipient AS Other ON DMChannelRecipient.channel_id=Other.channel_id
+WHERE DMChannelRecipient.user_id=?
+"#,
+		user_id,
+		user_id,
+		user_id,
+		user_id,
+		user_id,
+		user_id,
+	)
+	.fetch_all(executor)
+	.await?
+	.into_iter()
+	.map(|row| row.user_id)
+	.collect())
+}
@@ -0,0 +1,91 @@
+use crate::AppState;
+use actix_web::{
+	rt,
+	web::{self, Bytes},
+};
+use reqwest::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use rusty_s3::{Bucket, Credentials, S3Action};
+use std::time::Duration;
+use url::Url;
+
+// uploads are done right after signing, so this can be short
+const UPLOAD_URL_DURATION: Duration = Duration::from_secs(60);
+const DOWNLOAD_URL_DURATION: Duration = Duration::from_secs(60 * 60 * 24);
+
+pub struct Storage {
+	bucket: Bucket,
+	credentials: Credentials,
+	client: reqwest::Client,
+}
+
+impl Storage {
+	pub fn new(bucket: Bucket, credentials: Credentials) -> Self {
+		Self {
+			bucket,
+			credentials,
+			client: reqwest::Client::new(),
+		}
+	}
+
+	// objects which aren't inline are always downloaded by browsers instead of being displayed
+	pub async fn put(
+		&self,
+		key: &str,
+		content_type: &str,
+		inline: bool,
+		data: Bytes,
+	) -> reqwest::Result<()> {
+		let url = self
+			.bucket
+			.put_object(Some(&self.credentials), key)
+			.sign(UPLOAD_URL_DURATION);
+
+		self.client
+			.put(url)
+			.header(CONTENT_TYPE, content_type)
+			.header(
+				CONTENT_DISPOSITION,
+				if inline { "inline" } else { "attachment" },
+			)
+			.body(data)
+			.send()
+			.await?
+			.error_for_status()?;
+
+		Ok(())
+	}
+
+	pub async fn delete(&self, key: &str) -> reqwest::Result<()> {
+		let url = self
+			.bucket
+			.delete_object(Some(&self.credentials), key)
+			.sign(UPLOAD_URL_DURATION);
+
+		self.client.delete(url).send().await?.error_for_status()?;
+
+		Ok(())
+	}
+
+	pub fn url(&self, key: &str) -> Url {
+		self.bucket
+			.get_object(Some(&self.credentials), key)
+			.sign(DOWNLOAD_URL_DURATION)
+	}
+}
+
+// used once the rows referencing the objects are already gone, so failures are only logged
+pub fn delete_in_background(app_state: &web::Data<AppState>, keys: Vec<String>) {
+	if keys.is_empty() {
+		return;
+	}
+
+	let app_state = app_state.clone();
+
+	rt::spawn(async move {
+		for key in keys {
+			if let Err(e) = app_state.storage.delete(&key).await {
+				tracing::error!("failed to delete attachment {key}: {e}");
+			}
+		}
+	});
+}
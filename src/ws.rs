@@ -1,19 +1,35 @@
-use std::fmt::Display;
+use std::{
+	collections::{HashSet, VecDeque},
+	sync::Arc,
+};
 
-use actix_web::{rt, web};
+use actix_web::web;
 use serde::Serialize;
+use tokio::sync::{
+	mpsc::{self, error::TrySendError},
+	Notify,
+};
 use ts_rs::TS;
 
 use crate::{
+	error::BackendError,
 	models::{
+		block::UserBlock,
 		channel::Channel,
 		friend::UserFriend,
 		friendrequest::UserFriendRequest,
 		invite::Invite,
 		message::Message,
+		overwrite::PermissionOverwrite,
+		permissions::{get_channel_viewers, Permissions},
+		presence::Presence,
+		readstate::ReadState,
+		role::ServerRole,
 		scope::{has_scope, ReadWrite, Scope},
 		server::Server,
 		servermember::ServerMember,
+		settings::UserSettings,
+		user::User,
 	},
 	AppState,
 };
@@ -26,6 +42,18 @@ pub enum WsUpdateEvent {
 	#[cfg(test)]
 	#[allow(dead_code)]
 	Reauthenticate,
+	#[cfg(test)]
+	#[allow(dead_code)]
+	Ready {
+		#[ts(type = "`${number}`")]
+		session_id: u64,
+	},
+	#[cfg(test)]
+	#[allow(dead_code)]
+	Resumed,
+	#[cfg(test)]
+	#[allow(dead_code)]
+	InvalidSession,
 
 	ServerCreate(Server),
 	ServerUpdate {
@@ -34,6 +62,12 @@ pub enum WsUpdateEvent {
 		id: u64,
 		#[serde(skip_serializing_if = "Option::is_none")]
 		name: Option<String>,
+		#[serde(
+			skip_serializing_if = "Option::is_none",
+			serialize_with = "crate::models::opt_id_str"
+		)]
+		#[ts(type = "`${number}`")]
+		owner_id: Option<u64>,
 	},
 	ServerDelete {
 		#[serde(serialize_with = "crate::models::id_str")]
@@ -48,12 +82,68 @@ pub enum WsUpdateEvent {
 		id: u64,
 		#[serde(skip_serializing_if = "Option::is_none")]
 		name: Option<String>,
+		#[serde(
+			skip_serializing_if = "Option::is_none",
+			serialize_with = "crate::models::opt_opt_id_str"
+		)]
+		#[ts(type = "`${number}` | null")]
+		parent_id: Option<Option<u64>>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		position: Option<u32>,
+		#[serde(
+			skip_serializing_if = "Option::is_none",
+			serialize_with = "crate::models::opt_id_str"
+		)]
+		#[ts(type = "`${number}`")]
+		owner_id: Option<u64>,
 	},
 	ChannelDelete {
 		#[serde(serialize_with = "crate::models::id_str")]
 		#[ts(type = "`${number}`")]
 		id: u64,
 	},
+	ChannelOverwriteUpdate(PermissionOverwrite),
+	ChannelOverwriteDelete {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		target_id: u64,
+	},
+	ChannelRecipientAdd {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		user: User,
+	},
+	ChannelRecipientRemove {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+	},
+
+	ThreadCreate(Channel),
+	ThreadUpdate {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		id: u64,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		name: Option<String>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		archived: Option<bool>,
+	},
+	ThreadDelete {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		parent_id: u64,
+	},
 
 	MessageCreate(Message),
 	MessageUpdate {
@@ -70,6 +160,51 @@ pub enum WsUpdateEvent {
 		id: u64,
 	},
 
+	ReactionAdd {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		message_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+		emoji: String,
+	},
+	ReactionRemove {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		message_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+		emoji: String,
+	},
+
+	TypingStart {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+		timestamp: chrono::DateTime<chrono::Utc>,
+	},
+	TypingStop {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		channel_id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+	},
+
+	ReadStateUpdate(ReadState),
+
 	InviteCreate(Invite),
 	InviteDelete {
 		id: String,
@@ -85,6 +220,14 @@ pub enum WsUpdateEvent {
 		server_id: u64,
 		#[serde(skip_serializing_if = "Option::is_none")]
 		nickname: Option<Option<String>>,
+		#[serde(
+			skip_serializing_if = "Option::is_none",
+			serialize_with = "crate::models::opt_ids_str"
+		)]
+		#[ts(type = "Array<`${number}`>")]
+		roles: Option<Vec<u64>>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		timed_out_until: Option<Option<chrono::DateTime<chrono::Utc>>>,
 	},
 	MemberDelete {
 		#[serde(serialize_with = "crate::models::id_str")]
@@ -95,6 +238,29 @@ pub enum WsUpdateEvent {
 		server_id: u64,
 	},
 
+	RoleCreate(ServerRole),
+	RoleUpdate {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		server_id: u64,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		name: Option<String>,
+		#[serde(skip_serializing_if = "Option::is_none")]
+		#[ts(type = "`${number}`")]
+		permissions: Option<Permissions>,
+	},
+	RoleDelete {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		id: u64,
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		server_id: u64,
+	},
+
 	UserUpdate {
 		#[serde(serialize_with = "crate::models::id_str")]
 		#[ts(type = "`${number}`")]
@@ -105,6 +271,9 @@ pub enum WsUpdateEvent {
 		display_name: Option<Option<String>>,
 	},
 
+	PresenceUpdate(Presence),
+	UserSettingsUpdate(UserSettings),
+
 	FriendRequestCreate(UserFriendRequest),
 	FriendRequestDelete {
 		#[serde(serialize_with = "crate::models::id_str")]
@@ -124,12 +293,13 @@ pub enum WsUpdateEvent {
 		#[ts(type = "`${number}`")]
 		friend_id: u64,
 	},
-}
 
-impl Display for WsUpdateEvent {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		write!(f, "{}", serde_json::to_string(self).unwrap())
-	}
+	BlockCreate(UserBlock),
+	BlockDelete {
+		#[serde(serialize_with = "crate::models::id_str")]
+		#[ts(type = "`${number}`")]
+		user_id: u64,
+	},
 }
 
 impl WsUpdateEvent {
@@ -137,7 +307,10 @@ impl WsUpdateEvent {
 		match self {
 			// doesn't matter
 			#[cfg(test)]
-			WsUpdateEvent::Reauthenticate => Scope::Profile(ReadWrite::Read),
+			WsUpdateEvent::Reauthenticate
+			| WsUpdateEvent::Ready { .. }
+			| WsUpdateEvent::Resumed
+			| WsUpdateEvent::InvalidSession => Scope::Profile(ReadWrite::Read),
 
 			WsUpdateEvent::ServerCreate { .. } => Scope::Servers(ReadWrite::Read),
 			WsUpdateEvent::ServerUpdate { .. } => Scope::Servers(ReadWrite::Read),
@@ -146,11 +319,27 @@ impl WsUpdateEvent {
 			WsUpdateEvent::ChannelCreate { .. } => Scope::Servers(ReadWrite::Read),
 			WsUpdateEvent::ChannelUpdate { .. } => Scope::Servers(ReadWrite::Read),
 			WsUpdateEvent::ChannelDelete { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::ChannelOverwriteUpdate { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::ChannelOverwriteDelete { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::ChannelRecipientAdd { .. } => Scope::Friends(ReadWrite::Read),
+			WsUpdateEvent::ChannelRecipientRemove { .. } => Scope::Friends(ReadWrite::Read),
+
+			WsUpdateEvent::ThreadCreate { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::ThreadUpdate { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::ThreadDelete { .. } => Scope::Servers(ReadWrite::Read),
 
 			WsUpdateEvent::MessageCreate { .. } => Scope::Messages(ReadWrite::Read),
 			WsUpdateEvent::MessageUpdate { .. } => Scope::Messages(ReadWrite::Read),
 			WsUpdateEvent::MessageDelete { .. } => Scope::Messages(ReadWrite::Read),
 
+			WsUpdateEvent::ReactionAdd { .. } => Scope::Messages(ReadWrite::Read),
+			WsUpdateEvent::ReactionRemove { .. } => Scope::Messages(ReadWrite::Read),
+
+			WsUpdateEvent::TypingStart { .. } => Scope::Messages(ReadWrite::Read),
+			WsUpdateEvent::TypingStop { .. } => Scope::Messages(ReadWrite::Read),
+
+			WsUpdateEvent::ReadStateUpdate { .. } => Scope::Messages(ReadWrite::Read),
+
 			WsUpdateEvent::InviteCreate { .. } => Scope::Servers(ReadWrite::Read),
 			WsUpdateEvent::InviteDelete { .. } => Scope::Servers(ReadWrite::Read),
 
@@ -158,13 +347,197 @@ impl WsUpdateEvent {
 			WsUpdateEvent::MemberUpdate { .. } => Scope::Servers(ReadWrite::Read),
 			WsUpdateEvent::MemberDelete { .. } => Scope::Servers(ReadWrite::Read),
 
+			WsUpdateEvent::RoleCreate { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::RoleUpdate { .. } => Scope::Servers(ReadWrite::Read),
+			WsUpdateEvent::RoleDelete { .. } => Scope::Servers(ReadWrite::Read),
+
 			WsUpdateEvent::UserUpdate { .. } => Scope::Profile(ReadWrite::Read),
+			WsUpdateEvent::PresenceUpdate { .. } => Scope::Profile(ReadWrite::Read),
+			WsUpdateEvent::UserSettingsUpdate { .. } => Scope::Profile(ReadWrite::Read),
 
 			WsUpdateEvent::FriendRequestCreate { .. } => Scope::Friends(ReadWrite::Read),
 			WsUpdateEvent::FriendRequestDelete { .. } => Scope::Friends(ReadWrite::Read),
 
 			WsUpdateEvent::FriendCreate { .. } => Scope::Friends(ReadWrite::Read),
 			WsUpdateEvent::FriendDelete { .. } => Scope::Friends(ReadWrite::Read),
+
+			WsUpdateEvent::BlockCreate { .. } => Scope::Friends(ReadWrite::Read),
+			WsUpdateEvent::BlockDelete { .. } => Scope::Friends(ReadWrite::Read),
+		}
+	}
+
+	// the user who caused the event, for the events which are hidden when they come from a blocked user
+	fn author_id(&self) -> Option<u64> {
+		match self {
+			WsUpdateEvent::MessageCreate(message) => Some(message.user.id),
+			WsUpdateEvent::ReactionAdd { user_id, .. } => Some(*user_id),
+			WsUpdateEvent::TypingStart { user_id, .. } => Some(*user_id),
+			_ => None,
+		}
+	}
+}
+
+// the members of the server connected to the gateway who can view the channel
+pub async fn channel_connections(
+	app_state: &web::Data<AppState>,
+	server_id: u64,
+	channel_id: u64,
+) -> Result<HashSet<u64>, BackendError> {
+	let members = app_state
+		.server_connections
+		.get(&server_id)
+		.map(|conns| conns.clone())
+		.unwrap_or_default();
+
+	get_channel_viewers(&app_state.db, server_id, channel_id, members).await
+}
+
+// how events are sent to sessions, numbered so that dropped sessions can resume from the last one they received
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct WsDispatch<'a> {
+	#[serde(flatten)]
+	event: &'a WsUpdateEvent,
+	// missing for ephemeral events, which can't be resumed from
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[ts(type = "number", optional)]
+	seq: Option<u64>,
+	// lets the client hide the event without having to keep track of the blocked users itself
+	#[serde(skip_serializing_if = "std::ops::Not::not")]
+	#[ts(as = "Option<bool>", optional)]
+	author_blocked: bool,
+}
+
+pub const REPLAY_BUFFER_SIZE: usize = 1000;
+
+// the events recently dispatched to a user, kept so that dropped sessions can catch up when resuming
+#[derive(Debug, Default)]
+pub struct ReplayBuffer {
+	seq: u64,
+	events: VecDeque<(u64, Scope, String)>,
+}
+
+impl ReplayBuffer {
+	fn push(&mut self, scope: Scope, event: &WsUpdateEvent, author_blocked: bool) -> String {
+		self.seq += 1;
+
+		let json = serde_json::to_string(&WsDispatch {
+			event,
+			seq: Some(self.seq),
+			author_blocked,
+		})
+		.unwrap();
+
+		if self.events.len() == REPLAY_BUFFER_SIZE {
+			self.events.pop_front();
+		}
+		self.events.push_back((self.seq, scope, json.clone()));
+
+		json
+	}
+
+	// returns None if events after `seq` have already been dropped from the buffer
+	pub fn events_after(&self, seq: u64, scopes: Option<&HashSet<Scope>>) -> Option<Vec<String>> {
+		let oldest = self
+			.events
+			.front()
+			.map_or(self.seq + 1, |(oldest, _, _)| *oldest);
+
+		if seq > self.seq || seq + 1 < oldest {
+			return None;
+		}
+
+		Some(
+			self.events
+				.iter()
+				.filter(|(event_seq, scope, _)| {
+					*event_seq > seq && scopes.is_none_or(|scopes| has_scope(scopes, *scope))
+				})
+				.map(|(_, _, json)| json.clone())
+				.collect(),
+		)
+	}
+}
+
+// large enough to fit a full replay when resuming, with room for the events dispatched meanwhile
+pub const OUTBOUND_QUEUE_SIZE: usize = REPLAY_BUFFER_SIZE * 2;
+
+// the sending half of a session's outbound queue, which is drained in order by a single writer task
+#[derive(Clone, Debug)]
+pub struct SessionSender {
+	queue: mpsc::Sender<String>,
+	lagged: Arc<Notify>,
+	reauthenticate: Arc<Notify>,
+}
+
+impl SessionSender {
+	pub fn new(queue: mpsc::Sender<String>) -> Self {
+		Self {
+			queue,
+			lagged: Arc::new(Notify::new()),
+			reauthenticate: Arc::new(Notify::new()),
+		}
+	}
+
+	// a full queue means the client can't keep up, which the session is notified of
+	pub fn send(&self, json: String) {
+		if let Err(TrySendError::Full(_)) = self.queue.try_send(json) {
+			self.lagged.notify_one();
+		}
+	}
+
+	pub async fn lagged(&self) {
+		self.lagged.notified().await
+	}
+
+	// asks the session to authenticate again, such as when its token has been revoked
+	pub fn reauthenticate(&self) {
+		self.reauthenticate.notify_one();
+	}
+
+	pub async fn reauthentication_requested(&self) {
+		self.reauthenticate.notified().await
+	}
+}
+
+// sessions authenticated with the given access token have to authenticate again with another one
+pub fn reauthenticate_sessions(app_state: &web::Data<AppState>, user_id: u64, access_token: &str) {
+	if let Some(rf) = app_state.user_connections.get(&user_id) {
+		for (_, sender, token) in rf.values() {
+			if token.0.strip_prefix("Bearer ") == Some(access_token) {
+				sender.reauthenticate();
+			}
+		}
+	}
+}
+
+// users whose sessions dropped are still considered connected for as long as they can be resumed,
+// so that reconnecting doesn't make them flicker offline
+pub fn is_connected(app_state: &web::Data<AppState>, user_id: u64) -> bool {
+	app_state.user_connections.contains_key(&user_id)
+		|| app_state.resumable_sessions.contains_key(&user_id)
+}
+
+fn author_blocked(app_state: &web::Data<AppState>, user_id: u64, author_id: Option<u64>) -> bool {
+	author_id.is_some_and(|author_id| {
+		app_state
+			.blocked_users
+			.get(&user_id)
+			.is_some_and(|blocked_users| blocked_users.contains(&author_id))
+	})
+}
+
+fn send_to_sessions(app_state: &web::Data<AppState>, user_id: u64, events: &[(Scope, String)]) {
+	if let Some(rf) = app_state.user_connections.get(&user_id) {
+		for (scopes, sender, _) in rf.values() {
+			for (scope, json) in events {
+				match scopes {
+					Some(scopes) if !has_scope(scopes, *scope) => continue,
+					_ => {}
+				}
+
+				sender.send(json.to_string());
+			}
 		}
 	}
 }
@@ -176,26 +549,122 @@ pub fn send_updates<I: IntoIterator<Item = WsUpdateEvent>, J: IntoIterator<Item
 ) {
 	let events = events
 		.into_iter()
-		.map(|event| (event.scope_for(), event.to_string()))
+		.map(|event| (event.scope_for(), event.author_id(), event))
 		.collect::<Vec<_>>();
 
 	for user_id in users {
-		if let Some(rf) = app_state.user_connections.get(&user_id) {
-			for (scopes, session) in rf.values() {
-				for (scope, json) in &events {
-					match scopes {
-						Some(scopes) if !has_scope(scopes, *scope) => continue,
-						_ => {}
-					}
-
-					let mut session = session.clone();
-					let json = json.to_string();
-
-					rt::spawn(async move {
-						let _ = session.text(json).await;
-					});
-				}
-			}
+		// users without a replay buffer have neither connected nor resumable sessions
+		let Some(mut replay_buffer) = app_state.replay_buffers.get_mut(&user_id) else {
+			continue;
+		};
+
+		let events = events
+			.iter()
+			.map(|(scope, author_id, event)| {
+				let author_blocked = author_blocked(app_state, user_id, *author_id);
+
+				(*scope, replay_buffer.push(*scope, event, author_blocked))
+			})
+			.collect::<Vec<_>>();
+
+		send_to_sessions(app_state, user_id, &events);
+	}
+}
+
+// for events which only matter while they're happening, such as typing indicators.
+// they aren't numbered nor kept in the replay buffer, so resumed sessions don't receive stale ones
+pub fn send_ephemeral_updates<
+	I: IntoIterator<Item = WsUpdateEvent>,
+	J: IntoIterator<Item = u64>,
+>(
+	events: I,
+	app_state: &web::Data<AppState>,
+	users: J,
+) {
+	let events = events
+		.into_iter()
+		.map(|event| (event.scope_for(), event.author_id(), event))
+		.collect::<Vec<_>>();
+
+	for user_id in users {
+		let events = events
+			.iter()
+			.map(|(scope, author_id, event)| {
+				let json = serde_json::to_string(&WsDispatch {
+					event,
+					seq: None,
+					author_blocked: author_blocked(app_state, user_id, *author_id),
+				})
+				.unwrap();
+
+				(*scope, json)
+			})
+			.collect::<Vec<_>>();
+
+		send_to_sessions(app_state, user_id, &events);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn buffer_with(events: u64, scope: Scope) -> ReplayBuffer {
+		let mut buffer = ReplayBuffer::default();
+
+		for id in 0..events {
+			buffer.push(scope, &WsUpdateEvent::ChannelDelete { id }, false);
 		}
+
+		buffer
+	}
+
+	#[test]
+	fn replays_events_after_seq() {
+		let buffer = buffer_with(3, Scope::Servers(ReadWrite::Read));
+
+		let events = buffer.events_after(1, None).unwrap();
+		assert_eq!(events.len(), 2);
+		assert!(events[0].contains(r#""seq":2"#));
+		assert!(events[1].contains(r#""seq":3"#));
+
+		assert_eq!(buffer.events_after(3, None), Some(vec![]));
+		assert_eq!(ReplayBuffer::default().events_after(0, None), Some(vec![]));
+	}
+
+	#[test]
+	fn rejects_unknown_seqs() {
+		let buffer = buffer_with(3, Scope::Servers(ReadWrite::Read));
+
+		assert_eq!(buffer.events_after(4, None), None);
+	}
+
+	#[test]
+	fn rejects_seqs_dropped_from_the_buffer() {
+		let buffer = buffer_with(
+			REPLAY_BUFFER_SIZE as u64 + 2,
+			Scope::Servers(ReadWrite::Read),
+		);
+
+		assert_eq!(buffer.events_after(1, None), None);
+		assert_eq!(
+			buffer.events_after(2, None).map(|events| events.len()),
+			Some(REPLAY_BUFFER_SIZE)
+		);
+	}
+
+	#[test]
+	fn filters_events_by_scope() {
+		let mut buffer = buffer_with(1, Scope::Servers(ReadWrite::Read));
+		buffer.push(
+			Scope::Messages(ReadWrite::Read),
+			&WsUpdateEvent::ChannelDelete { id: 1 },
+			false,
+		);
+
+		let scopes = HashSet::from([Scope::Servers(ReadWrite::Write)]);
+		let events = buffer.events_after(0, Some(&scopes)).unwrap();
+		assert_eq!(events.len(), 1);
+		assert!(events[0].contains(r#""seq":1"#));
 	}
 }